@@ -0,0 +1,110 @@
+//! Base45 (RFC 9285) packs binary data into the 45 characters of the alphanumeric mode, so that
+//! payloads like CBOR tokens or health certificates can be encoded in alphanumeric segments.
+//! Two bytes become three characters, i.e. 16 bits take 16.5 bits in the symbol.
+use crate::error::{QrError, QrResult};
+
+//-------------------------------------------------------------------------------------------------
+
+/// The Base45 alphabet, which is the character set of the alphanumeric mode in the same order
+pub const ALPHABET: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// Encode the data as Base45. The result only contains characters of the alphanumeric mode.
+pub fn encode(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(2) * 3);
+    for chunk in data.chunks(2) {
+        let mut value = chunk.iter().fold(0usize, |acc, &byte| (acc << 8) | byte as usize);
+        for _ in 0..chunk.len() + 1 {
+            result.push(ALPHABET[value % 45] as char);
+            value /= 45;
+        }
+    }
+    result
+}
+
+/// Decode Base45 text. Errors contain the position of the first character of the invalid group.
+pub fn decode(text: &str) -> QrResult<Vec<u8>> {
+    let text = text.as_bytes();
+    let mut result = Vec::with_capacity(text.len() / 3 * 2 + 1);
+    for (i, chunk) in text.chunks(3).enumerate() {
+        let position = i * 3;
+        // a single trailing character can not be decoded
+        if chunk.len() == 1 {
+            return Err(QrError::InvalidBase45(position));
+        }
+        let mut value = 0usize;
+        for (j, &c) in chunk.iter().enumerate().rev() {
+            let digit = ALPHABET.iter().position(|&a| a == c)
+                .ok_or(QrError::InvalidBase45(position + j))?;
+            value = value * 45 + digit;
+        }
+        if chunk.len() == 3 {
+            if value > 0xFFFF {
+                return Err(QrError::InvalidBase45(position));
+            }
+            result.push((value >> 8) as u8);
+        } else if value > 0xFF {
+            return Err(QrError::InvalidBase45(position));
+        }
+        result.push(value as u8);
+    }
+    Ok(result)
+}
+
+/// Compress the data with zlib before encoding it as Base45, as done for EU DCC payloads
+#[cfg(feature = "zlib")]
+pub fn encode_zlib(data: &[u8]) -> String {
+    encode(&miniz_oxide::deflate::compress_to_vec_zlib(data, 9))
+}
+
+/// Decode Base45 text and decompress the result with zlib
+#[cfg(feature = "zlib")]
+pub fn decode_zlib(text: &str) -> QrResult<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_zlib(&decode(text)?)
+        .map_err(|_| QrError::InvalidZlibData)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        // examples from RFC 9285
+        assert_eq!(encode(b"AB"), "BB8");
+        assert_eq!(encode(b"Hello!!"), "%69 VD92EX0");
+        assert_eq!(encode(b"base-45"), "UJCLQE7W581");
+        assert_eq!(encode(b"ietf!"), "QED8WEX0");
+        assert_eq!(encode(b""), "");
+        assert_eq!(encode(&[0xFF, 0xFF, 0xFF]), "FGWU5");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("QED8WEX0"), Ok(b"ietf!".to_vec()));
+        assert_eq!(decode("%69 VD92EX0"), Ok(b"Hello!!".to_vec()));
+        assert_eq!(decode("FGWU5"), Ok(vec![0xFF, 0xFF, 0xFF]));
+        assert_eq!(decode(""), Ok(vec![]));
+
+        // lowercase letters are not in the alphabet
+        assert_eq!(decode("GGw"), Err(QrError::InvalidBase45(2)));
+        // groups which do not fit into two bytes or one byte
+        assert_eq!(decode("GGW"), Err(QrError::InvalidBase45(0)));
+        assert_eq!(decode("BB8:6"), Err(QrError::InvalidBase45(3)));
+        // dangling character
+        assert_eq!(decode("BB8B"), Err(QrError::InvalidBase45(3)));
+
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&data)), Ok(data));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_zlib() {
+        let data = b"HC1 payloads are highly redundant, redundant, redundant".repeat(4);
+        let text = encode_zlib(&data);
+        assert!(text.len() < encode(&data).len());
+        assert_eq!(decode_zlib(&text), Ok(data));
+        assert_eq!(decode_zlib("BB8"), Err(QrError::InvalidZlibData));
+    }
+}
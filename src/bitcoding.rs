@@ -1,9 +1,12 @@
 use crate::config::{Size, Encoding};
+use crate::error::{QrError, QrResult};
+use crate::matrix::EncodeInfo;
 
-use crate::tables::lookup_capacity;
+use crate::tables::{last_data_codeword_bits, try_lookup_capacity};
 
-use std::convert::TryInto;
 use std::cmp;
+use std::io;
+use std::ops::Range;
 
 use bitstream_io::{BitWriter, BitRecorder, BitWrite, BigEndian};
 use crate::ECCLevel;
@@ -13,15 +16,20 @@ pub type QrBitWriter<'a> = BitWriter<&'a mut Vec<u8>, BigEndian>;
 
 
 
-fn write_mode_indicator(stream: &mut QrBitRecorder, size: Size, ec: Encoding) {
+fn write_mode_indicator(stream: &mut QrBitRecorder, size: Size, ec: Encoding) -> QrResult<()> {
     match size {
-        Size::Micro(1) => {},
+        Size::Micro(1) => {
+            // no mode indicator, only numeric mode is available
+            if ec != Encoding::Numeric {
+                return Err(QrError::UnsupportedEncoding(ec, size));
+            }
+        },
         Size::Micro(2) => {
             // one bit: 0 => Numeric, 1 => Alphanumeric
             stream.write(1, match ec {
                 Encoding::Numeric => 0,
                 Encoding::Alphanumeric => 1,
-                _ => panic!("Invalid encoding mode for chosen size!")
+                _ => return Err(QrError::UnsupportedEncoding(ec, size))
             }).unwrap();
         },
         Size::Micro(3) => {
@@ -50,26 +58,46 @@ fn write_mode_indicator(stream: &mut QrBitRecorder, size: Size, ec: Encoding) {
                 Encoding::Kanji => 0b1000
             }).unwrap();
         },
-        _ => panic!("Invalid size given")
+        _ => return Err(QrError::InvalidSize(size))
     }
+    Ok(())
+}
+
+/// Return the largest character count that fits into the character count indicator
+fn max_char_count(size: Size, ec: Encoding) -> usize {
+    (1 << ec.num_char_count_bits(size)) - 1
 }
 
 /// write character count indicator to the bitstream. The interesting part is how many bits
 /// are used for this, which is given by a helper member of Encoding
-fn write_charcount_indicator(stream: &mut QrBitRecorder, count: u32, size: Size, ec: Encoding) {
-    stream.write(ec.num_char_count_bits(size) as u32, count).unwrap();
+fn write_charcount_indicator(stream: &mut QrBitRecorder, count: usize, size: Size, ec: Encoding) {
+    debug_assert!(count <= max_char_count(size, ec));
+    stream.write(ec.num_char_count_bits(size) as u32, count as u32).unwrap();
+}
+
+/// Whether the AIM ECI specification defines the assignment number: 000000 to 000899 are
+/// character sets, 000900 to 000999 general purpose, 001000 to 810899 user defined and
+/// 810900 to 811799 closed system interpretations. The numbers from 811800 to 999999 are
+/// reserved for future use, and larger numbers can not be encoded at all.
+pub fn is_valid_eci_assignment(assignment: u32) -> bool {
+    assignment < 811800
 }
 
 /// Write an ECI header to the bitstream, which changes the interpretation
 /// of the following encoded message, until another ECI header is encountered.
 ///
-/// assignment is a decimal 6-digit number between 000000 and 999999 specifying
-/// the encoding (as defined by the AIM ECI specification).
+/// assignment is a decimal 6-digit number specifying the encoding (as defined by the
+/// AIM ECI specification), see is_valid_eci_assignment. Character sets can be
+/// given by name as `Charset::Utf8.into()`.
 ///
 /// The ECI header can be omitted completely; in that case, the default
 /// interpretation is Shift JIS X 0208 for "kanji" mode and ISO/IEC 8859-1
 /// for the other three modes.
-pub fn write_eci_header(stream: &mut QrBitRecorder, assignment: u32) {
+pub fn write_eci_header(stream: &mut QrBitRecorder, assignment: u32) -> QrResult<()> {
+    if !is_valid_eci_assignment(assignment) {
+        return Err(QrError::InvalidEciAssignment(assignment));
+    }
+
     // write ECI mode indicator
     stream.write(4, 0b0111).unwrap();
     // depending on value of assignment, encode it as either 1, 2 or 3
@@ -78,15 +106,50 @@ pub fn write_eci_header(stream: &mut QrBitRecorder, assignment: u32) {
         // encode as 0bbbbbbb
         stream.write(1, 0).unwrap();
         stream.write(7, assignment).unwrap();
-    } else if assignment >= 128 && assignment < 16384 {
+    } else if assignment < 16384 {
         // encode as 10bbbbbb bbbbbbbb
         stream.write(2, 0b10).unwrap();
         stream.write(14, assignment).unwrap();
-    } else /* assigment >= 16384 && assigment < 1000000 */ {
+    } else /* assigment >= 16384 && assigment < 811800 */ {
         // encode as 110bbbbb bbbbbbbb bbbbbbbb
         stream.write(3, 0b110).unwrap();
         stream.write(21, assignment).unwrap();
     }
+    Ok(())
+}
+
+/// FNC1 mode (see 7.4.8), marking the data as formatted according to an industry standard
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum Fnc1 {
+    First,          // GS1 application identifiers
+    Second(u8)      // AIM application standard, with the encoded application indicator
+}
+
+impl Fnc1 {
+    /// FNC1 in second position for the given application indicator, which is either
+    /// a single letter or a two-digit number as assigned by AIM International.
+    pub fn second_position(indicator: &str) -> QrResult<Fnc1> {
+        match *indicator.as_bytes() {
+            // letters are encoded as their ASCII value + 100
+            [c] if c.is_ascii_alphabetic() => Ok(Fnc1::Second(c + 100)),
+            [a, b] if a.is_ascii_digit() && b.is_ascii_digit() => Ok(Fnc1::Second((a - b'0') * 10 + b - b'0')),
+            _ => Err(QrError::InvalidApplicationIndicator(indicator.to_string()))
+        }
+    }
+}
+
+/// Write a FNC1 mode header to the bitstream. It precedes the first segment, but follows
+/// a structured append or ECI header. In FNC1 mode, a '%' in alphanumeric segments stands for
+/// the FNC1 field separator (GS), so a literal '%' has to be doubled.
+/// Micro symbols do not support FNC1 mode.
+pub fn write_fnc1_header(stream: &mut QrBitRecorder, fnc1: Fnc1) {
+    match fnc1 {
+        Fnc1::First => stream.write(4, 0b0101).unwrap(),
+        Fnc1::Second(indicator) => {
+            stream.write(4, 0b1001).unwrap();
+            stream.write(8, indicator).unwrap();
+        }
+    }
 }
 
 fn encode_numeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
@@ -96,7 +159,7 @@ fn encode_numeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
     let mut i = 0;         // 0-index of current digit in triplet
     let mut cur_code: u32 = 0;  // current value of triplet
     for &l in input {
-        assert!(l >= 0x30 || l <= 0x39);    // ASCII codes for digits 0 to 9
+        debug_assert!(l.is_ascii_digit());    // ASCII codes for digits 0 to 9
         let digit = l - 0x30;
         cur_code = cur_code * 10 + digit as u32;
         i += 1;
@@ -117,8 +180,10 @@ fn encode_numeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
 }
 
 
-fn map_alphanumeric(in_char: u8) -> u8 {
-    match in_char {
+/// Return the value of a character in the alphanumeric mode (table 5), or None
+/// if the character cannot be encoded in that mode.
+fn alphanumeric_value(in_char: u8) -> Option<u8> {
+    Some(match in_char {
         0x30..=0x39 => in_char - 0x30,  // a digit in [0-9] maps to that value
         0x41..=0x5A => in_char - 0x37,  // capital letters in [A-Z] map to the next 26 values
         0x20 => 36, // space
@@ -130,8 +195,8 @@ fn map_alphanumeric(in_char: u8) -> u8 {
         0x2E => 42, // period .
         0x2F => 43, // slash /
         0x3A => 44, // colon :
-        _ => panic!("Invalid char for alphanumeric mode!")
-    }
+        _ => return None
+    })
 }
 
 fn encode_alphanumeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
@@ -141,7 +206,8 @@ fn encode_alphanumeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
     let mut i = 0;         // 0-index of current digit in triplet
     let mut cur_code: u32 = 0;  // current value of triplet
     for &l in input {
-        cur_code = cur_code * 45 + map_alphanumeric(l) as u32;
+        // the input was validated before, so every character has a value
+        cur_code = cur_code * 45 + alphanumeric_value(l).unwrap_or(0) as u32;
         i += 1;
         if i == 2 {
             // got pair. write the code to the bitstream and reset state
@@ -157,30 +223,73 @@ fn encode_alphanumeric_data(stream: &mut QrBitRecorder, input: &[u8]) {
 }
 
 fn encode_byte_data(stream: &mut QrBitRecorder, input: &[u8]) {
-    // assume byte data is already ISO8859-1 encoded,
-    // so just write those as bits
-    for &l in input {
-        stream.write(8, l).unwrap();
-    }
+    // assume byte data is already ISO8859-1 encoded, so just write those as bits.
+    // The recorder copies the slice into a single record instead of one per byte; it is
+    // copied once more when the recorder is played back into the codewords.
+    stream.write_bytes(input).unwrap();
+}
+
+/// Whether a pair of bytes is a Shift JIS encoded character that can be
+/// represented in the kanji mode (see 7.4.6)
+fn is_kanji_pair(first: u8, second: u8) -> bool {
+    let number = first as u16 * 0x100 + second as u16;
+    ((0x8140..=0x9FFC).contains(&number) || (0xE040..=0xEBBF).contains(&number)) &&
+        (0x40..=0xFC).contains(&second) && second != 0x7F
 }
 
-fn encode_kanji_data(stream: &mut QrBitRecorder, input: &[u8]) {
+/// Return the index of the first byte pair of the input that is not a Shift JIS character
+/// of the kanji mode, or None if the whole input can be encoded in kanji mode. A trailing
+/// single byte counts as an invalid pair.
+pub fn find_invalid_kanji_pair(input: &[u8]) -> Option<usize> {
+    input.chunks(2).position(|p| p.len() < 2 || !is_kanji_pair(p[0], p[1]))
+}
+
+fn encode_kanji_data(stream: &mut QrBitRecorder, input: &[u8]) -> QrResult<()> {
     // we assume input is encoded in Shift JIS (see JIS X 0208)
     // using two bytes per character. Every character is compacted
-    // into a 13bit codeword and written to the output.
-    assert!(input.len() % 2 == 0);
+    // into a 13bit codeword and written to the output. Skipping a pair
+    // would break the character count, so invalid pairs are an error.
+    if let Some(index) = find_invalid_kanji_pair(input) {
+        return Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 2 * index, value: input[2 * index] });
+    }
     for p in input.chunks(2) {
-        let pair: &[u8;2] = p.try_into().unwrap();
-        let number: u16 = pair[0] as u16 * 0x100 + pair[1] as u16;
-        if number >= 0x8140 && number <= 0x9FFC {
-            let number = number - 0x8140;
-            let code = (number >> 8) * 0xC0 + (number & 0xFF);
-            stream.write(13, code).unwrap();
-        } else if number >= 0xE040 && number <= 0xEBBF {
-            let number = number - 0xC140;
-            let code = (number >> 8) * 0xC0 + (number & 0xFF);
-            stream.write(13, code).unwrap();
+        let number: u16 = p[0] as u16 * 0x100 + p[1] as u16;
+        let number = if number >= 0xE040 { number - 0xC140 } else { number - 0x8140 };
+        let code = (number >> 8) * 0xC0 + (number & 0xFF);
+        stream.write(13, code).unwrap();
+    }
+    Ok(())
+}
+
+/// Convert a text into Shift JIS encoded bytes, ready for the kanji mode. Returns an error
+/// pointing to the first character (as byte offset into the text) that has no two-byte
+/// Shift JIS representation in the kanji ranges supported by the kanji mode.
+pub fn shift_jis_from_str(text: &str) -> QrResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut buf = [0u8; 4];
+    for (position, ch) in text.char_indices() {
+        let (sjis, _, had_errors) = encoding_rs::SHIFT_JIS.encode(ch.encode_utf8(&mut buf));
+        if had_errors || sjis.len() != 2 || !is_kanji_pair(sjis[0], sjis[1]) {
+            return Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position, value: text.as_bytes()[position] });
         }
+        bytes.extend_from_slice(&sjis);
+    }
+    Ok(bytes)
+}
+
+/// Check that every character of the input can be represented in the given encoding,
+/// and return an error with the position and value of the first byte that can not.
+/// In the kanji mode that is the first byte of the offending pair.
+pub fn validate_content(input: &[u8], ec: Encoding) -> QrResult<()> {
+    let position = match ec {
+        Encoding::Numeric => input.iter().position(|c| !c.is_ascii_digit()),
+        Encoding::Alphanumeric => input.iter().position(|&c| alphanumeric_value(c).is_none()),
+        Encoding::Bytes => None,
+        Encoding::Kanji => find_invalid_kanji_pair(input).map(|i| 2 * i)
+    };
+    match position {
+        Some(position) => Err(QrError::InvalidCharacter { encoding: ec, position, value: input[position] }),
+        None => Ok(())
     }
 }
 
@@ -193,49 +302,435 @@ fn encode_kanji_data(stream: &mut QrBitRecorder, input: &[u8]) {
 /// function to write data in any of the four supported encoding modes. The ECI changes the
 /// interpretation of the encoded data. In most cases you will want to use the "bytes" encoding
 /// there. See
-pub fn encode_data_segment(stream: &mut QrBitRecorder, input: &[u8], ec: Encoding, size: Size) {
-    write_mode_indicator(stream, size, ec);
-    match ec {
-        Encoding::Numeric => {
-            write_charcount_indicator(stream, input.len() as u32, size, ec);
-            encode_numeric_data(stream, input);
-        },
-        Encoding::Alphanumeric => {
-            write_charcount_indicator(stream, input.len() as u32, size, ec);
-            encode_alphanumeric_data(stream, input);
-        },
-        Encoding::Bytes => {
-            write_charcount_indicator(stream, input.len() as u32, size, ec);
-            encode_byte_data(stream, input);
-        },
-        Encoding::Kanji => {
-            write_charcount_indicator(stream, input.len() as u32 / 2, size, ec);
-            encode_kanji_data(stream, input);
+///
+/// If the input has more characters than the character count indicator of the size allows,
+/// it is split into several segments. The stream is left untouched if the input can not be encoded.
+pub fn encode_data_segment(stream: &mut QrBitRecorder, input: &[u8], ec: Encoding, size: Size) -> QrResult<()> {
+    // check everything up front, so the segment can be written directly to the stream
+    validate_content(input, ec)?;
+    if !ec.is_available(size) {
+        return Err(QrError::UnsupportedEncoding(ec, size));
+    }
+
+    // content with more characters than the character count indicator can hold is
+    // split into several consecutive segments of the same mode
+    let max_bytes = match ec {
+        Encoding::Kanji => 2 * max_char_count(size, ec),
+        _ => max_char_count(size, ec)
+    };
+    let chunks: Vec<&[u8]> = if input.is_empty() { vec![input] } else { input.chunks(max_bytes).collect() };
+
+    for chunk in chunks {
+        write_mode_indicator(stream, size, ec)?;
+
+        let count = match ec {
+            Encoding::Kanji => chunk.len() / 2,
+            _ => chunk.len()
+        };
+        write_charcount_indicator(stream, count, size, ec);
+
+        match ec {
+            Encoding::Numeric => encode_numeric_data(stream, chunk),
+            Encoding::Alphanumeric => encode_alphanumeric_data(stream, chunk),
+            Encoding::Bytes => encode_byte_data(stream, chunk),
+            Encoding::Kanji => encode_kanji_data(stream, chunk)?
+        }
+    }
+    Ok(())
+}
+
+/// A mode segment for building a bitstream by hand, e.g. interleaved with ECI and FNC1 headers.
+/// The mode and character count indicators are derived from the encoding and the size when written.
+/// A segment can carry its own ECI header, switching the interpretation from this segment on.
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct Segment {
+    pub encoding: Encoding,     // mode of the segment
+    pub data: Vec<u8>,          // content, as Shift JIS byte pairs in kanji mode
+    pub eci: Option<u32>        // ECI assignment number written before the segment
+}
+
+impl Segment {
+    pub fn new(encoding: Encoding, data: impl Into<Vec<u8>>) -> Segment {
+        Segment { encoding, data: data.into(), eci: None }
+    }
+
+    /// Precede the segment with an ECI header (not available for micro symbols)
+    pub fn with_eci(self, assignment: u32) -> Segment {
+        Segment { eci: Some(assignment), ..self }
+    }
+
+    /// Write the ECI header, if any, and the segment to the bitstream, see encode_data_segment.
+    /// The stream is left untouched if the segment can not be encoded in the size.
+    pub fn write_to(&self, stream: &mut QrBitRecorder, size: Size) -> QrResult<()> {
+        let mut recorder = QrBitRecorder::new();
+        if let Some(assignment) = self.eci {
+            if size.is_micro() {
+                return Err(QrError::EciNotSupported(size));
+            }
+            write_eci_header(&mut recorder, assignment)?;
         }
+        encode_data_segment(&mut recorder, &self.data, self.encoding, size)?;
+        recorder.playback(stream).unwrap();
+        Ok(())
+    }
+
+    /// Number of bits write_to writes, including the ECI header, see segment_bit_length
+    pub fn bit_length(&self, size: Size) -> Option<usize> {
+        let eci_bits = match self.eci {
+            Some(_) if size.is_micro() => return None,
+            Some(assignment) => eci_header_bit_length(assignment),
+            None => 0
+        };
+        segment_bit_length(self.data.len(), self.encoding, size).map(|bits| eci_bits + bits)
     }
 }
 
-// TODO: structured append (see Chapter 8, page 67)
+/// Return the number of bits encode_data_segment writes for content of the given length in bytes,
+/// including the mode and character count indicators of all segments it is split into.
+/// Returns None if the encoding is not available for the size, or the length is odd for kanji.
+pub fn segment_bit_length(len: usize, ec: Encoding, size: Size) -> Option<usize> {
+    if !available_encodings(size).contains(&ec) || (ec == Encoding::Kanji && len % 2 == 1) {
+        return None;
+    }
+
+    let chars = if ec == Encoding::Kanji { len / 2 } else { len };
+
+    // full segments of the maximum character count, followed by the rest
+    let max_chars = max_char_count(size, ec);
+    let header_bits = size.num_mode_indicator_bits() + ec.num_char_count_bits(size);
+    let (full, rest) = (chars / max_chars, chars % max_chars);
+    let mut bits = full * (header_bits + ec.num_encoded_bits(max_chars));
+    if rest > 0 || full == 0 {
+        bits += header_bits + ec.num_encoded_bits(rest);
+    }
+    Some(bits)
+}
+
+/// Return the number of bits encode_segments writes for the given segments, computed from the
+/// segment lengths alone. Returns None if one of the segments can not be encoded in the size,
+/// see segment_bit_length.
+pub fn segments_bit_length(segments: &[(Encoding, Range<usize>)], size: Size) -> Option<usize> {
+    segments.iter().map(|(ec, range)| segment_bit_length(range.len(), *ec, size)).sum()
+}
+
+/// Return the number of bits of the ECI header for the given assignment number, which
+/// must be valid (see is_valid_eci_assignment)
+pub fn eci_header_bit_length(assignment: u32) -> usize {
+    match assignment {
+        0..=127 => 4 + 8,
+        128..=16383 => 4 + 16,
+        _ => 4 + 24
+    }
+}
+
+/// Whether content of the given length in bytes fits into a symbol as a single segment of the
+/// given encoding, optionally preceded by an ECI header. Returns false for combinations of size,
+/// level, encoding and ECI header that are not available, so the input can be validated before
+/// attempting to encode it. Does not check the content itself against the encoding.
+pub fn fits(content_len: usize, ec: Encoding, size: Size, ecl: ECCLevel, eci: Option<u32>) -> bool {
+    let capacity = match try_lookup_capacity(size, ecl) {
+        Ok(capacity) => capacity.data_bits as usize,
+        Err(_) => return false
+    };
+    let eci_bits = match eci {
+        None => 0,
+        Some(assignment) if !size.is_micro() && is_valid_eci_assignment(assignment) => eci_header_bit_length(assignment),
+        Some(_) => return false
+    };
+    segment_bit_length(content_len, ec, size).is_some_and(|bits| eci_bits + bits <= capacity)
+}
+
+/// Compute the parity byte of a structured append series, i.e. the XOR of all bytes
+/// of the complete message (before splitting it into the symbols of the series)
+pub fn structured_append_parity(message: &[u8]) -> u8 {
+    message.iter().fold(0, |parity, &b| parity ^ b)
+}
+
+/// Same as structured_append_parity, for a message that is already split into the parts
+/// of the series, in any order
+pub fn structured_append_parity_of_parts(parts: &[&[u8]]) -> u8 {
+    parts.iter().fold(0, |parity, part| parity ^ structured_append_parity(part))
+}
+
+/// Position of a symbol in a structured append series of up to 16 symbols
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct StructuredAppend {
+    pub position: u8,       // 0-based index of the symbol in the series
+    pub total: u8,          // number of symbols in the series
+    pub parity: u8          // parity of the complete message, see structured_append_parity
+}
+
+/// Write a structured append header to the bitstream (see chapter 8), marking the symbol as
+/// part of a series. Must be the first thing in the bitstream; micro symbols do not
+/// support structured append.
+pub fn write_structured_append_header(stream: &mut QrBitRecorder, header: StructuredAppend) -> QrResult<()> {
+    let StructuredAppend { position, total, parity } = header;
+    if !(2..=16).contains(&total) || position >= total {
+        return Err(QrError::InvalidStructuredAppend { position, total });
+    }
+
+    // mode indicator, symbol sequence indicator (position and total - 1), parity
+    stream.write(4, 0b0011).unwrap();
+    stream.write(4, position).unwrap();
+    stream.write(4, total - 1).unwrap();
+    stream.write(8, parity).unwrap();
+    Ok(())
+}
+
+
+/// Character statistics of a content, as returned by analyze
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct ContentReport {
+    pub length: usize,          // length of the content in bytes
+    pub numeric: usize,         // number of digits
+    pub alphanumeric: usize,    // number of characters of the alphanumeric set, including digits
+    pub latin1: usize,          // number of printable ISO 8859-1 characters and whitespace
+    pub kanji: usize,           // number of Shift JIS characters of the kanji mode, scanning pairs from the start
+    pub recommended: Encoding   // most restrictive encoding which supports the whole content
+}
+
+// whether a byte is a graphic character of ISO 8859-1 or common whitespace
+fn is_latin1_char(c: u8) -> bool {
+    matches!(c, b'\t' | b'\n' | b'\r' | 0x20..=0x7E | 0xA0..=0xFF)
+}
+
+/// Count which characters of the content each encoding can represent, e.g. to explain to a
+/// user why the content needs a large symbol. Byte mode can represent every byte.
+pub fn analyze(input: &[u8]) -> ContentReport {
+    let mut kanji = 0;
+    let mut i = 0;
+    while i < input.len() {
+        if i + 1 < input.len() && is_kanji_pair(input[i], input[i + 1]) {
+            kanji += 1;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+
+    let numeric = input.iter().filter(|c| c.is_ascii_digit()).count();
+    let alphanumeric = input.iter().filter(|&&c| alphanumeric_value(c).is_some()).count();
+    let recommended = if numeric == input.len() {
+        Encoding::Numeric
+    } else if alphanumeric == input.len() {
+        Encoding::Alphanumeric
+    } else if 2 * kanji == input.len() {
+        Encoding::Kanji
+    } else {
+        Encoding::Bytes
+    };
+
+    ContentReport {
+        length: input.len(),
+        numeric,
+        alphanumeric,
+        latin1: input.iter().filter(|&&c| is_latin1_char(c)).count(),
+        kanji,
+        recommended
+    }
+}
+
+/// Analyze the content and return the encoding that represents it in the fewest bits,
+/// i.e. the most restrictive encoding which supports all characters of the content.
+/// Content consisting only of Shift JIS kanji characters is encoded in kanji mode.
+pub fn guess_encoding(input: &[u8]) -> Encoding {
+    analyze(input).recommended
+}
+
+// characters used to group the digits of numbers in various locales
+const GROUPING_SEPARATORS: [char; 8] = [' ', '\t', '\u{a0}', '\u{202f}', '.', ',', '\'', '\u{2019}'];
+
+/// Digits of a number written with grouping separators, as returned by normalize_numeric
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct NumericNormalization {
+    pub digits: Vec<u8>,
+    pub removed: Vec<(usize, char)>     // byte offsets and the separators removed from the input
+}
+
+/// Strip grouping separators and whitespace from a formatted number, e.g. "1 234 567", so it
+/// can be encoded in numeric mode. Returns None if the text contains anything else than digits
+/// and separators, or no digits at all. Note that decimal separators are removed as well,
+/// so this is meant for identifiers like invoice numbers rather than for amounts.
+pub fn normalize_numeric(text: &str) -> Option<NumericNormalization> {
+    let mut result = NumericNormalization { digits: Vec::new(), removed: Vec::new() };
+    for (i, c) in text.char_indices() {
+        if c.is_ascii_digit() {
+            result.digits.push(c as u8);
+        } else if GROUPING_SEPARATORS.contains(&c) {
+            result.removed.push((i, c));
+        } else {
+            return None;
+        }
+    }
+
+    if result.digits.is_empty() { None } else { Some(result) }
+}
+
+/// Return the encodings available in symbols of the given size
+fn available_encodings(size: Size) -> &'static [Encoding] {
+    match size {
+        Size::Micro(1) => &[Encoding::Numeric],
+        Size::Micro(2) => &[Encoding::Numeric, Encoding::Alphanumeric],
+        _ => &[Encoding::Numeric, Encoding::Alphanumeric, Encoding::Bytes, Encoding::Kanji]
+    }
+}
+
+/// Return the densest encoding available for the size that can represent the whole input and
+/// is denser than the given one, if there is one. Empty input never needs a denser encoding.
+pub fn denser_encoding(input: &[u8], ec: Encoding, size: Size) -> Option<Encoding> {
+    if input.is_empty() {
+        return None;
+    }
+    [Encoding::Numeric, Encoding::Alphanumeric].iter().copied()
+        .find(|&candidate| candidate < ec && candidate.is_available(size) && validate_content(input, candidate).is_ok())
+}
+
+/// Split the input into segments such that the total number of encoded bits is minimal,
+/// taking the cost of the segment headers into account. Returns the encoding and the byte
+/// range of every segment, covering the whole input. Falls back to a single byte segment if
+/// the input cannot be represented by the encodings available for the size.
+///
+/// Uses dynamic programming over the input positions, with the encoding of the last segment as
+/// state. Costs are tracked in sixths of bits, so the fractional cost per character of the numeric
+/// (10/3 bits) and alphanumeric (11/2 bits) encodings are integers.
+pub fn optimal_segmentation(input: &[u8], size: Size) -> Vec<(Encoding, Range<usize>)> {
+    let encodings = available_encodings(size);
+    let n = input.len();
+    if n == 0 {
+        return vec![(encodings[0], 0..0)];
+    }
+
+    // number of bytes and cost of the character starting at position i in the given encoding,
+    // or None if it can not be encoded that way
+    let char_cost = |i: usize, ec: Encoding| -> Option<(usize, u32)> {
+        match ec {
+            Encoding::Numeric if input[i].is_ascii_digit() => Some((1, 20)),
+            Encoding::Alphanumeric if alphanumeric_value(input[i]).is_some() => Some((1, 33)),
+            Encoding::Bytes => Some((1, 48)),
+            Encoding::Kanji if i + 1 < n && is_kanji_pair(input[i], input[i + 1]) => Some((2, 78)),
+            _ => None
+        }
+    };
+    let header_cost = |ec: Encoding| 6 * (size.num_mode_indicator_bits() + ec.num_char_count_bits(size)) as u32;
+
+    // best[i][e]: cost of encoding input[..i] with the last segment in encodings[e], and the
+    // position and encoding index the last character was appended to
+    type Step = (u32, usize, Option<usize>);
+    let mut best: Vec<Vec<Option<Step>>> = vec![vec![None; encodings.len()]; n + 1];
+    for i in 0..n {
+        // states at position i, plus the start state at position 0
+        let states: Vec<(Option<usize>, u32)> = if i == 0 {
+            vec![(None, 0)]
+        } else {
+            best[i].iter().enumerate().filter_map(|(e, b)| b.map(|(cost, _, _)| (Some(e), cost))).collect()
+        };
+
+        for (prev, cost) in states {
+            for (e, &ec) in encodings.iter().enumerate() {
+                if let Some((len, c)) = char_cost(i, ec) {
+                    let new_cost = cost + c + if prev == Some(e) { 0 } else { header_cost(ec) };
+                    let entry = &mut best[i + len][e];
+                    if entry.is_none_or(|(old_cost, _, _)| new_cost < old_cost) {
+                        *entry = Some((new_cost, i, prev));
+                    }
+                }
+            }
+        }
+    }
 
-// TODO: FCN1 format (see Chapter 7.4.8, page 38)
+    // find best final state and walk back through the segments
+    let last = best[n].iter().enumerate()
+        .filter_map(|(e, b)| b.map(|(cost, _, _)| (e, cost)))
+        .min_by_key(|&(_, cost)| cost)
+        .map(|(e, _)| e);
+    let mut e = match last {
+        Some(e) => e,
+        None => return vec![(Encoding::Bytes, 0..n)]
+    };
 
+    let mut segments: Vec<(Encoding, Range<usize>)> = Vec::new();
+    let mut end = n;
+    let mut pos = n;
+    loop {
+        let (_, prev_pos, prev) = best[pos][e].unwrap();
+        pos = prev_pos;
+        if prev != Some(e) {
+            segments.push((encodings[e], pos..end));
+            end = pos;
+        }
+        match prev {
+            Some(p) => e = p,
+            None => break
+        }
+    }
+    segments.reverse();
+    segments
+}
+
+/// Write the input to the bitstream as a sequence of segments, e.g. as returned by
+/// optimal_segmentation. The ranges refer to the input.
+pub fn encode_segments(stream: &mut QrBitRecorder, input: &[u8], segments: &[(Encoding, Range<usize>)], size: Size) -> QrResult<()> {
+    let mut recorder = QrBitRecorder::new();
+    for (ec, range) in segments {
+        encode_data_segment(&mut recorder, &input[range.clone()], *ec, size)
+            .map_err(|err| match err {
+                // report positions relative to the whole input
+                QrError::InvalidCharacter { encoding, position, value } =>
+                    QrError::InvalidCharacter { encoding, position: position + range.start, value },
+                err => err
+            })?;
+    }
+    recorder.playback(stream).unwrap();
+    Ok(())
+}
 
 /// takes a recorded sequence of mode segments, maybe interspersed with
 /// ECI headers and maybe containing more complex data sequences and finalizes it,
 /// returning a sequence of codewords as a byte array. The finalization entails potentially
 /// appending a terminator sequence, adding zero bits to byte-align the sequence and potentially
 /// adding padding bytes to fill the chosen symbol's capacity exactly.
-pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel) -> Vec<u8> {
-    let bit_capacity = lookup_capacity(size, ecl).data_bits;
+pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel) -> QrResult<Vec<u8>> {
+    Ok(finalize_bitstream_with_info(stream, size, ecl)?.0)
+}
+
+/// Same as finalize_bitstream, but also return how much of the capacity is used
+pub fn finalize_bitstream_with_info(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel) -> QrResult<(Vec<u8>, EncodeInfo)> {
+    let mut data_codewords: Vec<u8> = Vec::new();
+    let info = finalize_into(stream, size, ecl, &mut data_codewords)?;
+    Ok((data_codewords, info))
+}
+
+/// Same as finalize_bitstream, but write the codewords into the given sink instead of
+/// allocating a new vector. When writing into a vector, the codewords are appended.
+/// Returns the number of codewords written.
+pub fn finalize_bitstream_into<W: io::Write>(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel, sink: W) -> QrResult<usize> {
+    finalize_into(stream, size, ecl, sink)?;
+    Ok(stream.written() as usize / 8)
+}
+
+fn finalize_into<W: io::Write>(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel, sink: W) -> QrResult<EncodeInfo> {
+    let bit_capacity = try_lookup_capacity(size, ecl)?.data_bits;
+    let mut info = EncodeInfo {
+        data_bits: stream.written() as usize,
+        capacity_bits: bit_capacity as usize,
+        terminator_bits: 0,
+        alignment_bits: 0,
+        pad_codewords: 0,
+        segments: Vec::new(),
+        warnings: Vec::new()
+    };
 
     // append terminator bits. At most as many zeroes as specified, and at least as many
     // of those as can fit within the symbol capacity.
     {
         let bit_rawdatasize = stream.written();
-        assert!(bit_rawdatasize <= bit_capacity, "Too many data bits for chosen symbol size {:?}!", size);
+        if bit_rawdatasize > bit_capacity {
+            return Err(QrError::DataTooLong { bits: bit_rawdatasize as usize, capacity: bit_capacity as usize, suggestion: None });
+        }
 
         let terminator_bits = cmp::min(bit_capacity - bit_rawdatasize, size.terminator_length() as u32);
-        stream.write(terminator_bits, 0 as u32).unwrap();
+        stream.write(terminator_bits, 0u32).unwrap();
+        info.terminator_bits = terminator_bits as usize;
     }
 
     // pad with zeroes to next full byte
@@ -245,17 +740,16 @@ pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel)
 
         // if we are already byte-aligned there is nothing to do.
         if alignment > 0 {
-            let padding = 8 - alignment;
-
             // special case: last word in M1 and M3 symbols is only 4 bits
-            if (size == Size::Micro(1) || size == Size::Micro(3)) &&
-               written + 4 > bit_capacity {
+            let padding = if last_data_codeword_bits(size) == 4 && written + 4 > bit_capacity {
                 // if we are already into those 4 last bits, just pad those with zeroes completely
-                stream.write(bit_capacity - written, 0 as u32).unwrap();
+                bit_capacity - written
             } else {
                 // simply add zero padding
-                stream.write(padding, 0 as u32).unwrap();
-            }
+                8 - alignment
+            };
+            stream.write(padding, 0u32).unwrap();
+            info.alignment_bits = padding as usize;
         }
     }
 
@@ -273,6 +767,7 @@ pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel)
         //        is zero, and in the third will return the remaining multiplicity of 8, which is correct.
 
         // pad bytes_left with special codewords
+        info.pad_codewords = bytes_left as usize;
         const PAD_CODEWORDS: [u32; 2] = [0b11101100, 0b00010001];
         for i in 0..bytes_left {
             let padding = PAD_CODEWORDS[i as usize % 2];
@@ -285,12 +780,12 @@ pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel)
     {
         let bits_left = bit_capacity - stream.written();
 
-        if (size == Size::Micro(1) || size == Size::Micro(3)) && bits_left > 0 {
+        if last_data_codeword_bits(size) == 4 && bits_left > 0 {
             assert_eq!(bits_left, 4);
-            stream.write(bits_left, 0 as u32).unwrap();
+            stream.write(bits_left, 0u32).unwrap();
         } else {
             // otherwise no bits should be left, ever
-            assert_eq!(bits_left, 0 as u32);
+            assert_eq!(bits_left, 0u32);
         }
     }
 
@@ -298,18 +793,15 @@ pub fn finalize_bitstream(stream: &mut QrBitRecorder, size: Size, ecl: ECCLevel)
 
     // add four more zero bits in the case of M1 and M3 symbols, so we can return
     // as a vector of full bytes
-    if size == Size::Micro(1) || size == Size::Micro(3) {
+    if last_data_codeword_bits(size) == 4 {
         stream.write(4, 0).unwrap();
     }
 
-    // create a bit writer on a vector, play back all bits to it.
-    let mut data_codewords: Vec<u8> = Vec::new();
-    {
-        let mut writer = QrBitWriter::new(&mut data_codewords);
-        stream.playback(&mut writer).unwrap();
-    }
+    // create a bit writer on the sink, play back all bits to it.
+    let mut writer = BitWriter::endian(sink, BigEndian);
+    stream.playback(&mut writer).map_err(|e| QrError::WriteFailed(e.kind()))?;
 
-    data_codewords
+    Ok(info)
 }
 
 
@@ -333,7 +825,7 @@ mod tests {
     #[test]
     fn test_numeric_example_1() {
         let mut recorder = QrBitRecorder::new();
-        encode_data_segment(&mut recorder, b"01234567", Encoding::Numeric, Size::Standard(1));
+        encode_data_segment(&mut recorder, b"01234567", Encoding::Numeric, Size::Standard(1)).unwrap();
         let (data, bits, value) = to_bytes(recorder);
         assert_eq!(data, [0b0001_0000, 0b0010_0000, 0b0000_1100, 0b0101_0110, 0b0110_0001]);
         assert_eq!(bits, 1);  // one bit left over
@@ -343,7 +835,7 @@ mod tests {
     #[test]
     fn test_numeric_example_2() {
         let mut recorder = QrBitRecorder::new();
-        encode_data_segment(&mut recorder, b"0123456789012345", Encoding::Numeric, Size::Micro(3));
+        encode_data_segment(&mut recorder, b"0123456789012345", Encoding::Numeric, Size::Micro(3)).unwrap();
         let (data, bits, value) = to_bytes(recorder);
         assert_eq!(data, [0b0010_0000, 0b0000_0110, 0b0010_1011, 0b0011_0101, 0b0011_0111,
                           0b0000_1010, 0b0111_0101]);
@@ -354,22 +846,342 @@ mod tests {
     #[test]
     fn test_alphanumeric_example() {
         let mut recorder = QrBitRecorder::new();
-        encode_data_segment(&mut recorder, b"AC-42", Encoding::Alphanumeric, Size::Standard(1));
+        encode_data_segment(&mut recorder, b"AC-42", Encoding::Alphanumeric, Size::Standard(1)).unwrap();
         let (data, bits, value) = to_bytes(recorder);
         assert_eq!(data, [0b0010_0000, 0b0010_1001, 0b1100_1110, 0b1110_0111, 0b0010_0001]);
         assert_eq!(bits, 1);  // one bit left over
         assert_eq!(value, 0); // value of that bit is zero
     }
 
+    #[test]
+    fn test_guess_encoding() {
+        assert_eq!(guess_encoding(b"01234567"), Encoding::Numeric);
+        assert_eq!(guess_encoding(b"AC-42"), Encoding::Alphanumeric);
+        assert_eq!(guess_encoding(b"HTTP://EXAMPLE.COM/$1"), Encoding::Alphanumeric);
+        assert_eq!(guess_encoding(b"Hello"), Encoding::Bytes);
+        assert_eq!(guess_encoding(&[0x93, 0x5F, 0xE4, 0xAA]), Encoding::Kanji);
+        // odd length or a trailing byte outside of the kanji ranges
+        assert_eq!(guess_encoding(&[0x93, 0x5F, 0xE4]), Encoding::Bytes);
+        assert_eq!(guess_encoding(&[0x93, 0x7F]), Encoding::Bytes);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let report = analyze(b"Order 42: AC-47");
+        assert_eq!((report.length, report.numeric, report.alphanumeric, report.latin1, report.kanji), (15, 4, 11, 15, 0));
+        assert_eq!(report.recommended, Encoding::Bytes);
+
+        assert_eq!(analyze(b"").recommended, Encoding::Numeric);
+        assert_eq!(analyze(b"AC-47").recommended, Encoding::Alphanumeric);
+        let report = analyze(&[0x93, 0x5F, 0xE4, 0xAA]);
+        assert_eq!((report.kanji, report.recommended), (2, Encoding::Kanji));
+        // mixed content, the trailing byte is not part of a pair
+        let report = analyze(&[b'A', 0x93, 0x5F, 0x00, 0x93]);
+        assert_eq!((report.kanji, report.latin1, report.recommended), (1, 2, Encoding::Bytes));
+    }
+
     #[test]
     fn test_kanji_example() {
         let mut recorder = QrBitRecorder::new();
-        encode_data_segment(&mut recorder, &[0x93, 0x5F, 0xE4, 0xAA], Encoding::Kanji, Size::Standard(1));
+        encode_data_segment(&mut recorder, &[0x93, 0x5F, 0xE4, 0xAA], Encoding::Kanji, Size::Standard(1)).unwrap();
         let (data, bits, value) = to_bytes(recorder);
         assert_eq!(data, [0b1000_0000, 0b0010_0110, 0b1100_1111, 0b1110_1010]);
         assert_eq!(bits, 6);  // six bits left over
         assert_eq!(value, 0b101010); // those bits are 0b101010
     }
 
-    //TODO: tests for finalizing the bitstream
+    #[test]
+    fn test_kanji_boundaries() {
+        // first and last characters of both ranges, and the limits of the second byte
+        for &(pair, code) in [([0x81, 0x40], 0x0000), ([0x9F, 0xFC], 0x173C), ([0xE0, 0x40], 0x1740),
+                              ([0xEB, 0xBF], 0x1FFF), ([0x81, 0xFC], 0x00BC), ([0x82, 0x40], 0x00C0)].iter() {
+            let mut recorder = QrBitRecorder::new();
+            encode_kanji_data(&mut recorder, &pair).unwrap();
+            let (data, bits, value) = to_bytes(recorder);
+            assert_eq!(bits, 5);
+            assert_eq!((data[0] as u16) << 5 | value as u16, code, "{:02X?}", pair);
+        }
+
+        // just outside of the ranges, and the gap in the second byte
+        for pair in [[0x81u8, 0x3F], [0x9F, 0xFD], [0xA0, 0x40], [0xDF, 0xFC],
+                     [0xE0, 0x3F], [0xEB, 0xC0], [0x81, 0x7F], [0x82, 0x3F]] {
+            let mut input = vec![0x93, 0x5F];
+            input.extend_from_slice(&pair);
+            assert_eq!(find_invalid_kanji_pair(&input), Some(1), "{:02X?}", pair);
+            let mut recorder = QrBitRecorder::new();
+            assert_eq!(encode_data_segment(&mut recorder, &input, Encoding::Kanji, Size::Standard(1)),
+                       Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 2, value: pair[0] }), "{:02X?}", pair);
+        }
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let mut recorder = QrBitRecorder::new();
+        assert_eq!(encode_data_segment(&mut recorder, b"0123a5", Encoding::Numeric, Size::Standard(1)),
+                   Err(QrError::InvalidCharacter { encoding: Encoding::Numeric, position: 4, value: b'a' }));
+        assert_eq!(encode_data_segment(&mut recorder, b"AC-42a", Encoding::Alphanumeric, Size::Standard(1)),
+                   Err(QrError::InvalidCharacter { encoding: Encoding::Alphanumeric, position: 5, value: b'a' }));
+        assert_eq!(encode_data_segment(&mut recorder, &[0x93, 0x5F, 0xE4], Encoding::Kanji, Size::Standard(1)),
+                   Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 2, value: 0xE4 }));
+        assert_eq!(find_invalid_kanji_pair(&[0x93, 0x5F, 0xE4, 0xAA]), None);
+        assert_eq!(find_invalid_kanji_pair(&[0x93, 0x5F, 0xA0, 0xAA, 0xE4, 0xAA]), Some(1));
+        // the encoder itself does not skip invalid pairs either
+        assert_eq!(encode_kanji_data(&mut recorder, &[0x93, 0x5F, 0x41, 0x42]),
+                   Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 2, value: 0x41 }));
+        assert_eq!(encode_data_segment(&mut recorder, b"AC", Encoding::Alphanumeric, Size::Micro(1)),
+                   Err(QrError::UnsupportedEncoding(Encoding::Alphanumeric, Size::Micro(1))));
+        assert_eq!(write_eci_header(&mut recorder, 1000000), Err(QrError::InvalidEciAssignment(1000000)));
+        assert_eq!(write_eci_header(&mut recorder, 811800), Err(QrError::InvalidEciAssignment(811800)));
+        // nothing was written
+        assert_eq!(recorder.written(), 0);
+
+        assert_eq!(validate_content(b"HELLO WORLD", Encoding::Alphanumeric), Ok(()));
+        let err = validate_content(b"12 3", Encoding::Numeric).unwrap_err();
+        assert_eq!(err, QrError::InvalidCharacter { encoding: Encoding::Numeric, position: 2, value: b' ' });
+        assert_eq!(err.to_string(), "character 0x20 at position 2 cannot be encoded in Numeric mode");
+    }
+
+    #[test]
+    fn test_segment_splitting() {
+        // 8 bit character count in byte mode for version 9, so 300 bytes need two segments
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, &[b'a'; 300], Encoding::Bytes, Size::Standard(9)).unwrap();
+        assert_eq!(recorder.written(), 2 * (4 + 8) + 300 * 8);
+
+        // M1 allows at most 7 digits per segment
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, b"12345678", Encoding::Numeric, Size::Micro(1)).unwrap();
+        assert_eq!(recorder.written(), (3 + 24) + (3 + 4));
+
+        // the split happens between characters in kanji mode
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, &[0x93, 0x5F].repeat(300), Encoding::Kanji, Size::Standard(1)).unwrap();
+        assert_eq!(recorder.written(), 2 * (4 + 8) + 300 * 13);
+
+        // empty content still gives a segment
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, b"", Encoding::Bytes, Size::Standard(1)).unwrap();
+        assert_eq!(recorder.written(), 4 + 8);
+    }
+
+    #[test]
+    fn test_segment_write_to() {
+        let segment = Segment::new(Encoding::Alphanumeric, "AC-42");
+        let mut expected = QrBitRecorder::new();
+        encode_data_segment(&mut expected, b"AC-42", Encoding::Alphanumeric, Size::Standard(1)).unwrap();
+
+        // ECI header, FNC1 header and segment in the order given
+        let mut recorder = QrBitRecorder::new();
+        write_eci_header(&mut recorder, 26).unwrap();
+        write_fnc1_header(&mut recorder, Fnc1::First);
+        segment.write_to(&mut recorder, Size::Standard(1)).unwrap();
+        assert_eq!(recorder.written(), 12 + 4 + expected.written());
+        assert_eq!(segment.bit_length(Size::Standard(1)), Some(expected.written() as usize));
+
+        // M1 has no alphanumeric mode, and nothing is written
+        let mut recorder = QrBitRecorder::new();
+        assert_eq!(segment.write_to(&mut recorder, Size::Micro(1)),
+                   Err(QrError::UnsupportedEncoding(Encoding::Alphanumeric, Size::Micro(1))));
+        assert_eq!(recorder.written(), 0);
+        assert_eq!(segment.bit_length(Size::Micro(1)), None);
+    }
+
+    #[test]
+    fn test_segment_with_eci() {
+        let segment = Segment::new(Encoding::Bytes, "ab").with_eci(26);
+        let mut recorder = QrBitRecorder::new();
+        segment.write_to(&mut recorder, Size::Standard(1)).unwrap();
+        assert_eq!(recorder.written(), 12 + 4 + 8 + 16);
+        assert_eq!(segment.bit_length(Size::Standard(1)), Some(40));
+
+        let mut recorder = QrBitRecorder::new();
+        assert_eq!(segment.write_to(&mut recorder, Size::Micro(4)), Err(QrError::EciNotSupported(Size::Micro(4))));
+        assert_eq!(recorder.written(), 0);
+        assert_eq!(segment.bit_length(Size::Micro(4)), None);
+    }
+
+    #[test]
+    fn test_structured_append_header() {
+        // example from chapter 8: "ABCDEFGHIJKLMN" as symbol 3 of 4
+        let parity = structured_append_parity(b"ABCDEFGHIJKLMN");
+        let mut recorder = QrBitRecorder::new();
+        write_structured_append_header(&mut recorder, StructuredAppend { position: 2, total: 4, parity }).unwrap();
+        let (data, bits, value) = to_bytes(recorder);
+        assert_eq!(data, [0b0011_0010, 0b0011_0000 | (parity >> 4)]);
+        assert_eq!((bits, value), (4, parity & 0xF));
+
+        let mut recorder = QrBitRecorder::new();
+        for &(position, total) in [(4, 4), (0, 17), (0, 1)].iter() {
+            assert_eq!(write_structured_append_header(&mut recorder, StructuredAppend { position, total, parity: 0 }),
+                       Err(QrError::InvalidStructuredAppend { position, total }));
+        }
+    }
+
+    #[test]
+    fn test_shift_jis_from_str() {
+        // same as the kanji example
+        assert_eq!(shift_jis_from_str("点茗"), Ok(vec![0x93, 0x5F, 0xE4, 0xAA]));
+        assert_eq!(shift_jis_from_str("漢字x"), Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 6, value: b'x' }));
+        // half-width katakana is single-byte in Shift JIS, emoji have no representation at all
+        assert!(shift_jis_from_str("ｱ").is_err());
+        assert!(shift_jis_from_str("😀").is_err());
+    }
+
+    #[test]
+    fn test_optimal_segmentation() {
+        let size = Size::Standard(1);
+        assert_eq!(optimal_segmentation(b"ORDER-0001234567", size),
+                   [(Encoding::Alphanumeric, 0..6), (Encoding::Numeric, 6..16)]);
+        // short runs of digits are not worth a segment of their own
+        assert_eq!(optimal_segmentation(b"AB12CD", size), [(Encoding::Alphanumeric, 0..6)]);
+        assert_eq!(optimal_segmentation(b"a1234567890123b", size),
+                   [(Encoding::Bytes, 0..1), (Encoding::Numeric, 1..14), (Encoding::Bytes, 14..15)]);
+        assert_eq!(optimal_segmentation(&[0x93, 0x5F, 0xE4, 0xAA], size), [(Encoding::Kanji, 0..4)]);
+        assert_eq!(optimal_segmentation(b"12345", Size::Micro(1)), [(Encoding::Numeric, 0..5)]);
+        // not encodable in M2 at all
+        assert_eq!(optimal_segmentation(b"abc", Size::Micro(2)), [(Encoding::Bytes, 0..3)]);
+
+        // the segmentation is never worse than a single segment
+        for input in [&b"HELLO WORLD 2021"[..], b"https://example.com/0123456789", b"0000AAAA0000aaaa"].iter() {
+            let segments = optimal_segmentation(input, size);
+            let mut segmented = QrBitRecorder::new();
+            encode_segments(&mut segmented, input, &segments, size).unwrap();
+            let mut single = QrBitRecorder::new();
+            encode_data_segment(&mut single, input, guess_encoding(input), size).unwrap();
+            assert!(segmented.written() <= single.written());
+        }
+    }
+
+    #[test]
+    fn test_data_too_long() {
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, b"123456", Encoding::Numeric, Size::Micro(2)).unwrap();
+        encode_data_segment(&mut recorder, b"123456", Encoding::Numeric, Size::Micro(2)).unwrap();
+        assert_eq!(finalize_bitstream(&mut recorder, Size::Micro(2), ECCLevel::L),
+                   Err(QrError::DataTooLong { bits: 50, capacity: 40, suggestion: None }));
+    }
+
+    #[test]
+    fn test_fnc1_header() {
+        assert_eq!(Fnc1::second_position("37"), Ok(Fnc1::Second(37)));
+        assert_eq!(Fnc1::second_position("a"), Ok(Fnc1::Second(197)));
+        assert_eq!(Fnc1::second_position("Z"), Ok(Fnc1::Second(190)));
+        assert!(Fnc1::second_position("123").is_err());
+        assert!(Fnc1::second_position("%").is_err());
+
+        let mut recorder = QrBitRecorder::new();
+        write_fnc1_header(&mut recorder, Fnc1::Second(197));
+        write_fnc1_header(&mut recorder, Fnc1::First);
+        assert_eq!(to_bytes(recorder), (vec![0b1001_1100, 0b0101_0101], 0, 0));
+    }
+
+    #[test]
+    fn test_segment_bit_length() {
+        let samples: [(Encoding, &[u8]); 4] = [(Encoding::Numeric, b"0123456789"), (Encoding::Alphanumeric, b"AB:CD-EF"),
+                                               (Encoding::Bytes, b"abc"), (Encoding::Kanji, &[0x93, 0x5F])];
+        for &size in [Size::Micro(1), Size::Micro(2), Size::Micro(4), Size::Standard(1), Size::Standard(10)].iter() {
+            for &(ec, sample) in samples.iter() {
+                for len in [0, 1, 2, 3, 4, 5, 10, 50, 255, 256, 1000, 1100].iter() {
+                    let input: Vec<u8> = sample.iter().copied().cycle().take(*len).collect();
+                    let mut recorder = QrBitRecorder::new();
+                    let expected = encode_data_segment(&mut recorder, &input, ec, size).ok().map(|_| recorder.written() as usize);
+                    assert_eq!(segment_bit_length(*len, ec, size), expected, "{:?} {:?} {}", size, ec, len);
+                }
+            }
+        }
+
+        // lists of segments, as returned by optimal_segmentation
+        for &size in [Size::Micro(4), Size::Standard(1), Size::Standard(27)].iter() {
+            for input in [&b"ORDER-0001234567 ok"[..], b"", b"12345678901234567890abc"].iter() {
+                let segments = optimal_segmentation(input, size);
+                let mut recorder = QrBitRecorder::new();
+                encode_segments(&mut recorder, input, &segments, size).unwrap();
+                assert_eq!(segments_bit_length(&segments, size), Some(recorder.written() as usize));
+            }
+        }
+        assert_eq!(segments_bit_length(&[(Encoding::Numeric, 0..3), (Encoding::Bytes, 3..5)], Size::Micro(2)), None);
+
+        assert_eq!(eci_header_bit_length(26), 12);
+        assert_eq!(eci_header_bit_length(811799), 28);
+        // closed system interpretations need the three byte form
+        let mut recorder = QrBitRecorder::new();
+        write_eci_header(&mut recorder, 810900).unwrap();
+        assert_eq!(recorder.written() as usize, eci_header_bit_length(810900));
+    }
+
+    #[test]
+    fn test_fits() {
+        // 1-L holds 152 bits: 4 + 10 bits of headers and 41 digits in 137 bits
+        assert!(fits(41, Encoding::Numeric, Size::Standard(1), ECCLevel::L, None));
+        assert!(!fits(42, Encoding::Numeric, Size::Standard(1), ECCLevel::L, None));
+        assert!(!fits(41, Encoding::Numeric, Size::Standard(1), ECCLevel::L, Some(3)));
+        assert!(fits(16, Encoding::Bytes, Size::Standard(1), ECCLevel::L, Some(26)));
+        assert!(!fits(17, Encoding::Bytes, Size::Standard(1), ECCLevel::L, Some(26)));
+
+        // unavailable combinations
+        assert!(!fits(1, Encoding::Alphanumeric, Size::Micro(1), ECCLevel::L, None));
+        assert!(!fits(1, Encoding::Numeric, Size::Micro(1), ECCLevel::M, None));
+        assert!(!fits(1, Encoding::Numeric, Size::Micro(4), ECCLevel::L, Some(3)));
+        assert!(!fits(1, Encoding::Numeric, Size::Standard(1), ECCLevel::L, Some(811800)));
+        assert!(!fits(1, Encoding::Numeric, Size::Standard(41), ECCLevel::L, None));
+    }
+
+    #[test]
+    fn test_normalize_numeric() {
+        let normalized = normalize_numeric("1 234\u{a0}567").unwrap();
+        assert_eq!(normalized.digits, b"1234567");
+        assert_eq!(normalized.removed, [(1, ' '), (5, '\u{a0}')]);
+        assert_eq!(normalize_numeric("1'000.000,00").unwrap().digits, b"100000000");
+        assert_eq!(normalize_numeric("0042").unwrap().removed, []);
+
+        assert_eq!(normalize_numeric("INV 1234"), None);
+        assert_eq!(normalize_numeric("12-34"), None);
+        assert_eq!(normalize_numeric(" , "), None);
+    }
+
+    #[test]
+    fn test_encode_info() {
+        // 1-M holds 128 data bits, 8 digits take 4 + 10 + 27 bits, followed by 4 terminator bits
+        // and 3 bits of zero padding, leaving 10 pad codewords
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, b"01234567", Encoding::Numeric, Size::Standard(1)).unwrap();
+        let (data, info) = finalize_bitstream_with_info(&mut recorder, Size::Standard(1), ECCLevel::M).unwrap();
+        assert_eq!(info, EncodeInfo { data_bits: 41, capacity_bits: 128, terminator_bits: 4, alignment_bits: 3, pad_codewords: 10,
+                                      segments: vec![], warnings: vec![] });
+        assert_eq!(data[6..], [0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11]);
+
+        // the terminator is truncated in full symbols
+        let mut recorder = QrBitRecorder::new();
+        encode_data_segment(&mut recorder, b"01234", Encoding::Numeric, Size::Micro(1)).unwrap();
+        let (_, info) = finalize_bitstream_with_info(&mut recorder, Size::Micro(1), ECCLevel::L).unwrap();
+        assert_eq!(info, EncodeInfo { data_bits: 20, capacity_bits: 20, terminator_bits: 0, alignment_bits: 0, pad_codewords: 0,
+                                      segments: vec![], warnings: vec![] });
+    }
+
+    #[test]
+    fn test_finalize_into() {
+        let record = |input: &[u8], size| {
+            let mut recorder = QrBitRecorder::new();
+            encode_data_segment(&mut recorder, input, Encoding::Numeric, size).unwrap();
+            recorder
+        };
+        let expected = finalize_bitstream(&mut record(b"01234567", Size::Standard(1)), Size::Standard(1), ECCLevel::H).unwrap();
+
+        // codewords are appended to the vector
+        let mut arena = vec![0xAA];
+        assert_eq!(finalize_bitstream_into(&mut record(b"01234567", Size::Standard(1)), Size::Standard(1), ECCLevel::H, &mut arena),
+                   Ok(9));
+        assert_eq!(arena[0], 0xAA);
+        assert_eq!(arena[1..], expected[..]);
+
+        // M3 symbols end with a 4 bit codeword, which is padded to a full byte
+        let mut buffer = [0u8; 11];
+        assert_eq!(finalize_bitstream_into(&mut record(b"0123", Size::Micro(3)), Size::Micro(3), ECCLevel::L, &mut buffer[..]),
+                   Ok(11));
+
+        // the sink is too small
+        let mut buffer = [0u8; 4];
+        assert_eq!(finalize_bitstream_into(&mut record(b"01234567", Size::Standard(1)), Size::Standard(1), ECCLevel::H, &mut buffer[..]),
+                   Err(QrError::WriteFailed(io::ErrorKind::WriteZero)));
+    }
 }
\ No newline at end of file
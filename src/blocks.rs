@@ -0,0 +1,138 @@
+//! Division of the codewords of a symbol into error correction blocks, and the interleaving
+//! of the blocks into the order in which the codewords are placed (see 7.6 of the standard).
+//! Interleaving is needed for encoding, de-interleaving for reading symbols.
+use std::ops::Deref;
+
+use crate::config::{ECCLevel, Size};
+use crate::error::{QrError, QrResult};
+use crate::tables::{try_lookup_capacity, SymbolCapacity};
+
+//-------------------------------------------------------------------------------------------------
+
+/// Return the number of data and ecc codewords of each block of the symbol, in block order
+pub fn block_lengths(size: Size, level: ECCLevel) -> QrResult<Vec<(u32, u32)>> {
+    Ok(capacity_block_lengths(&try_lookup_capacity(size, level)?))
+}
+
+/// Same as block_lengths, for the block structure of the given capacity
+pub fn capacity_block_lengths(capacity: &SymbolCapacity) -> Vec<(u32, u32)> {
+    [capacity.block_def1, capacity.block_def2].iter()
+        .flat_map(|def| vec![(def.data_codewords, def.codewords - def.data_codewords); def.num_blocks as usize])
+        .collect()
+}
+
+/// Return the order in which the codewords of blocks with the given lengths are placed
+/// after interleaving, as (block, index in block) pairs.
+pub fn interleaving_order(block_lengths: &[u32]) -> Vec<(u32, u32)> {
+    let max_len = block_lengths.iter().copied().max().unwrap_or(0);
+    (0..max_len)
+        .flat_map(|i| block_lengths.iter().enumerate()
+            .filter(move |&(_, &len)| i < len)
+            .map(move |(b, _)| (b as u32, i)))
+        .collect()
+}
+
+/// Interleave the blocks into a single vector: first the first elements of all blocks,
+/// then the second elements, and so on. Shorter blocks are skipped once they are exhausted.
+pub fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
+    let max_len = blocks.iter().map(|block| block.len()).max().unwrap_or(0);
+    let mut res = Vec::with_capacity(blocks.iter().map(|block| block.len()).sum());
+    for i in 0..max_len {
+        for block in blocks {
+            if i < block.len() {
+                res.push(block[i]);
+            }
+        }
+    }
+    res
+}
+
+/// Inverse of interleave: distribute the interleaved elements back to blocks of the given
+/// lengths. The number of elements must match the total length of the blocks.
+pub fn deinterleave<T: Copy>(interleaved: &[T], block_lengths: &[u32]) -> QrResult<Vec<Vec<T>>> {
+    let expected = block_lengths.iter().sum::<u32>() as usize;
+    if interleaved.len() != expected {
+        return Err(QrError::WrongNumberOfCodewords { expected, actual: interleaved.len() });
+    }
+
+    let mut blocks: Vec<Vec<T>> = block_lengths.iter().map(|&len| Vec::with_capacity(len as usize)).collect();
+    // the indices of each block come in ascending order
+    for (&value, (b, _)) in interleaved.iter().zip(interleaving_order(block_lengths)) {
+        blocks[b as usize].push(value);
+    }
+    Ok(blocks)
+}
+
+/// Split the data codewords of a symbol into its blocks, in block order
+pub fn split_data_blocks(data_codewords: &[u8], size: Size, level: ECCLevel) -> QrResult<Vec<&[u8]>> {
+    split_data_blocks_with_capacity(data_codewords, &try_lookup_capacity(size, level)?)
+}
+
+/// Same as split_data_blocks, for the block structure of the given capacity
+pub fn split_data_blocks_with_capacity<'a>(data_codewords: &'a [u8], capacity: &SymbolCapacity) -> QrResult<Vec<&'a [u8]>> {
+    let lengths = capacity_block_lengths(capacity);
+    let expected = lengths.iter().map(|&(data, _)| data as usize).sum();
+    if data_codewords.len() != expected {
+        return Err(QrError::WrongNumberOfCodewords { expected, actual: data_codewords.len() });
+    }
+
+    let mut rest = data_codewords;
+    Ok(lengths.iter()
+        .map(|&(data, _)| {
+            let (block, tail) = rest.split_at(data as usize);
+            rest = tail;
+            block
+        })
+        .collect())
+}
+
+/// Inverse of the interleaving done by reedsolomon::construct_codewords: split all codewords of a
+/// symbol in placement order (the interleaved data codewords followed by the interleaved ecc
+/// codewords) into the data and ecc codewords of each block.
+pub fn deinterleave_codewords(codewords: &[u8], size: Size, level: ECCLevel) -> QrResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let lengths = block_lengths(size, level)?;
+    let (data_lengths, ecc_lengths): (Vec<u32>, Vec<u32>) = lengths.into_iter().unzip();
+    let num_data = data_lengths.iter().sum::<u32>() as usize;
+    let expected = num_data + ecc_lengths.iter().sum::<u32>() as usize;
+    if codewords.len() != expected {
+        return Err(QrError::WrongNumberOfCodewords { expected, actual: codewords.len() });
+    }
+
+    let data_blocks = deinterleave(&codewords[..num_data], &data_lengths)?;
+    let ecc_blocks = deinterleave(&codewords[num_data..], &ecc_lengths)?;
+    Ok(data_blocks.into_iter().zip(ecc_blocks).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let blocks: [&[u8]; 3] = [&[1, 2], &[3, 4], &[5, 6, 7]];
+        let interleaved = interleave(&blocks);
+        assert_eq!(interleaved, vec![1, 3, 5, 2, 4, 6, 7]);
+        assert_eq!(deinterleave(&interleaved, &[2, 2, 3]), Ok(vec![vec![1, 2], vec![3, 4], vec![5, 6, 7]]));
+        assert_eq!(deinterleave(&interleaved, &[2, 2, 2]), Err(QrError::WrongNumberOfCodewords { expected: 6, actual: 7 }));
+    }
+
+    #[test]
+    fn test_blocks_5q() {
+        // 5-Q: two blocks of 15 data codewords, followed by two blocks of 16, with 18 ecc codewords each
+        assert_eq!(block_lengths(Size::Standard(5), ECCLevel::Q), Ok(vec![(15, 18), (15, 18), (16, 18), (16, 18)]));
+
+        let data: Vec<u8> = (0..62).collect();
+        let blocks = split_data_blocks(&data, Size::Standard(5), ECCLevel::Q).unwrap();
+        assert_eq!(blocks[2], &data[30..46]);
+        assert_eq!(split_data_blocks(&data[1..], Size::Standard(5), ECCLevel::Q),
+                   Err(QrError::WrongNumberOfCodewords { expected: 62, actual: 61 }));
+
+        let ecc: Vec<Vec<u8>> = (0..4).map(|b| vec![100 + b; 18]).collect();
+        let mut codewords = interleave(&blocks);
+        codewords.extend(interleave(&ecc));
+        let deinterleaved = deinterleave_codewords(&codewords, Size::Standard(5), ECCLevel::Q).unwrap();
+        assert_eq!(deinterleaved.len(), 4);
+        assert_eq!(deinterleaved[3], (data[46..].to_vec(), vec![103; 18]));
+    }
+}
@@ -0,0 +1,121 @@
+//! Check digits for numeric payloads such as membership numbers or payment references,
+//! appended before encoding and verified after reading. Payloads stay pure digits, so they
+//! can still be encoded in numeric mode.
+use crate::config::Encoding;
+use crate::error::{QrError, QrResult};
+
+//-------------------------------------------------------------------------------------------------
+
+/// Check digit scheme
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum CheckDigits {
+    Luhn,           // Mod10, a single check digit, as used for card and membership numbers
+    Mod97           // ISO 7064 MOD 97-10, two check digits, as used for payment references
+}
+
+impl CheckDigits {
+    /// Number of check digits appended to the payload
+    pub fn num_digits(self) -> usize {
+        match self {
+            CheckDigits::Luhn => 1,
+            CheckDigits::Mod97 => 2
+        }
+    }
+
+    /// Compute the check digits for the given digits (without check digits)
+    pub fn compute(self, digits: &[u8]) -> QrResult<Vec<u8>> {
+        check_numeric(digits)?;
+        Ok(match self {
+            CheckDigits::Luhn => {
+                // every second digit is doubled, starting with the rightmost one
+                let sum: u32 = digits.iter().rev().enumerate()
+                    .map(|(i, &d)| {
+                        let d = (d - b'0') as u32;
+                        if i % 2 == 0 { if d > 4 { 2 * d - 9 } else { 2 * d } } else { d }
+                    })
+                    .sum();
+                vec![((10 - sum % 10) % 10) as u8 + b'0']
+            },
+            CheckDigits::Mod97 => {
+                // the check digits make the complete number congruent to 1 modulo 97
+                let check = 98 - mod97(digits) * 100 % 97;
+                vec![(check / 10) as u8 + b'0', (check % 10) as u8 + b'0']
+            }
+        })
+    }
+
+    /// Return the digits followed by their check digits
+    pub fn append(self, digits: &[u8]) -> QrResult<Vec<u8>> {
+        let mut payload = digits.to_vec();
+        payload.extend(self.compute(digits)?);
+        Ok(payload)
+    }
+
+    /// Whether the payload consists of digits ending with matching check digits
+    pub fn verify(self, payload: &[u8]) -> bool {
+        self.strip(payload).is_ok()
+    }
+
+    /// Verify the check digits of a payload that was read, and return the digits without them
+    pub fn strip(self, payload: &[u8]) -> QrResult<&[u8]> {
+        check_numeric(payload)?;
+        if payload.len() <= self.num_digits() {
+            return Err(QrError::WrongCheckDigits);
+        }
+        let (digits, check) = payload.split_at(payload.len() - self.num_digits());
+        if self.compute(digits)? != check {
+            return Err(QrError::WrongCheckDigits);
+        }
+        Ok(digits)
+    }
+}
+
+/// remainder of the decimal number modulo 97, computed digit by digit to avoid overflows
+fn mod97(digits: &[u8]) -> u32 {
+    digits.iter().fold(0, |rest, &d| (rest * 10 + (d - b'0') as u32) % 97)
+}
+
+/// report the first character that is not a digit
+fn check_numeric(digits: &[u8]) -> QrResult<()> {
+    match digits.iter().position(|d| !d.is_ascii_digit()) {
+        Some(position) => Err(QrError::InvalidCharacter { encoding: Encoding::Numeric, position, value: digits[position] }),
+        None => Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luhn() {
+        assert_eq!(CheckDigits::Luhn.compute(b"7992739871"), Ok(b"3".to_vec()));
+        assert_eq!(CheckDigits::Luhn.append(b"7992739871"), Ok(b"79927398713".to_vec()));
+        assert!(CheckDigits::Luhn.verify(b"79927398713"));
+        assert!(!CheckDigits::Luhn.verify(b"79927398710"));
+        // swapped neighbours are detected
+        assert!(!CheckDigits::Luhn.verify(b"97927398713"));
+        assert_eq!(CheckDigits::Luhn.strip(b"79927398713"), Ok(&b"7992739871"[..]));
+    }
+
+    #[test]
+    fn test_mod97() {
+        assert_eq!(CheckDigits::Mod97.compute(b"794"), Ok(b"44".to_vec()));
+        assert_eq!(CheckDigits::Mod97.append(b"000000000"), Ok(b"00000000098".to_vec()));
+        assert!(CheckDigits::Mod97.verify(b"79444"));
+        assert!(!CheckDigits::Mod97.verify(b"79454"));
+        assert_eq!(CheckDigits::Mod97.strip(b"79444"), Ok(&b"794"[..]));
+        // long references do not overflow
+        let reference = CheckDigits::Mod97.append(&[b'9'; 40]).unwrap();
+        assert!(CheckDigits::Mod97.verify(&reference));
+    }
+
+    #[test]
+    fn test_invalid_payload() {
+        assert_eq!(CheckDigits::Luhn.compute(b"12a4"),
+                   Err(QrError::InvalidCharacter { encoding: Encoding::Numeric, position: 2, value: b'a' }));
+        assert_eq!(CheckDigits::Mod97.strip(b"44"), Err(QrError::WrongCheckDigits));
+        assert_eq!(CheckDigits::Luhn.strip(b"79927398710"), Err(QrError::WrongCheckDigits));
+    }
+}
@@ -0,0 +1,51 @@
+//! Deflated payloads for large contents, marked by a short header so that readers can tell them
+//! from plain content. See encode_compressed, which only uses them if the symbol gets smaller.
+use crate::error::{QrError, QrResult};
+
+use std::borrow::Cow;
+
+//-------------------------------------------------------------------------------------------------
+
+/// Header of a compressed payload: magic bytes and the format version, followed by a zlib stream
+pub const HEADER: &[u8; 4] = b"QRZ\x01";
+
+/// Compress the data into a payload with header
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut payload = HEADER.to_vec();
+    payload.extend(miniz_oxide::deflate::compress_to_vec_zlib(data, 9));
+    payload
+}
+
+/// Whether the content starts with the header of a compressed payload
+pub fn is_compressed(content: &[u8]) -> bool {
+    content.starts_with(HEADER)
+}
+
+/// Return the decompressed data of a compressed payload, and any other content as it is
+pub fn decompress(content: &[u8]) -> QrResult<Cow<'_, [u8]>> {
+    if !is_compressed(content) {
+        return Ok(Cow::Borrowed(content));
+    }
+    miniz_oxide::inflate::decompress_to_vec_zlib(&content[HEADER.len()..])
+        .map(Cow::Owned)
+        .map_err(|_| QrError::InvalidZlibData)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc";
+        let payload = compress(data);
+        assert!(is_compressed(&payload));
+        assert!(payload.len() < data.len());
+        assert_eq!(decompress(&payload), Ok(Cow::Owned(data.to_vec())));
+
+        // other content is passed through
+        assert_eq!(decompress(data), Ok(Cow::Borrowed(&data[..])));
+        assert_eq!(decompress(b"QRZ\x01abc"), Err(QrError::InvalidZlibData));
+    }
+}
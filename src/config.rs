@@ -1,7 +1,14 @@
 /// Contains enums and structs that will also be exported as the public
 /// API of this crate.
 use itertools::Itertools;
+use std::borrow::Cow;
 use std::cmp::{Ordering, PartialOrd};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{QrError, QrResult};
+use crate::serialization::SymbolInfo;
 
 //-------------------------------------------------------------------------------------------------
 
@@ -51,13 +58,23 @@ impl Encoding {
     /// while in the other encodings it is equivalent with the number of bytes.
     pub fn num_encoded_bits(self, num_chars: usize) -> usize {
         match self {
-            Encoding::Numeric => (10 * num_chars + 2) / 3,
-            Encoding::Alphanumeric => (11 * num_chars + 1) / 2,
+            Encoding::Numeric => (10 * num_chars).div_ceil(3),
+            Encoding::Alphanumeric => (11 * num_chars).div_ceil(2),
             Encoding::Bytes => num_chars * 8,
             Encoding::Kanji => num_chars * 13,
         }
     }
 
+    /// Whether the encoding mode is available in symbols of the given size. M1 symbols only
+    /// support the numeric mode, M2 symbols the numeric and alphanumeric modes.
+    pub fn is_available(self, size: Size) -> bool {
+        match size {
+            Size::Micro(1) => self == Encoding::Numeric,
+            Size::Micro(2) => self == Encoding::Numeric || self == Encoding::Alphanumeric,
+            _ => true
+        }
+    }
+
     /// Compute the lowest common encoding of two encodings in the sense of the partial
     /// ordering defined below.
     pub fn upper_bound(self, other: Self) -> Self {
@@ -100,26 +117,41 @@ impl Size {
     /// Convert a simple string description into a fitting enum
     /// value by parsing it. micro symbols are described as "M1"
     /// through "M4", the standard ones just by their size index, e.g. "6".
-    pub fn from_str(decl: &str) -> Size {
-        if decl.starts_with("M") {
-            match &decl[1..] {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(decl: &str) -> QrResult<Size> {
+        let size = if let Some(num) = decl.strip_prefix('M') {
+            match num {
                 "1" => Size::Micro(1),
                 "2" => Size::Micro(2),
                 "3" => Size::Micro(3),
                 "4" => Size::Micro(4),
-                _ => panic!("Unrecognized symbol configuration string!")
+                _ => return Err(QrError::InvalidConfigString(decl.to_string()))
             }
         }
         else if let Ok(i) = decl.parse::<u8>() {
-            if i >= 1 && i <= 40 {
-                Size::Standard(i)
-            }
-            else {
-                panic!("Unrecognized symbol configuration string!")
-            }
+            Size::Standard(i)
         }
         else {
-            panic!("Unrecognized symbol configuration string!")
+            return Err(QrError::InvalidConfigString(decl.to_string()))
+        };
+
+        if size.is_valid() {
+            Ok(size)
+        } else {
+            Err(QrError::InvalidConfigString(decl.to_string()))
+        }
+    }
+
+    /// Iterate over all valid sizes, M1 to M4 followed by 1 to 40
+    pub fn all() -> impl Iterator<Item = Size> {
+        (1..=4).map(Size::Micro).chain((1..=40).map(Size::Standard))
+    }
+
+    /// Whether the version number is in the range allowed for the kind of symbol
+    pub fn is_valid(self) -> bool {
+        match self {
+            Size::Micro(i) => (1..=4).contains(&i),
+            Size::Standard(i) => (1..=40).contains(&i)
         }
     }
 
@@ -147,6 +179,12 @@ impl Size {
         }
     }
 
+    /// Return the edge length of the symbol including the quiet region on both sides, i.e. the
+    /// number of modules every renderer has to cover
+    pub fn dimensions_with_quiet_region(self) -> u32 {
+        self.dimensions() + 2 * self.quiet_region_size()
+    }
+
     /// Return the number of mode indicator bits
     pub fn num_mode_indicator_bits(self) -> usize {
         match self {
@@ -172,9 +210,35 @@ impl Size {
     }
 }
 
+impl FromStr for Size {
+    type Err = QrError;
+
+    fn from_str(decl: &str) -> QrResult<Size> {
+        Size::from_str(decl)
+    }
+}
+
+impl TryFrom<&str> for Size {
+    type Error = QrError;
+
+    fn try_from(decl: &str) -> QrResult<Size> {
+        Size::from_str(decl)
+    }
+}
+
+impl fmt::Display for Size {
+    /// Formats the size in the form accepted by from_str, e.g. "M3" or "6"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Size::Micro(i) => write!(f, "M{}", i),
+            Size::Standard(i) => write!(f, "{}", i)
+        }
+    }
+}
+
 //-------------------------------------------------------------------------------------------------
 
-#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Ord,PartialOrd,Debug)]
 pub enum ECCLevel {
     L,      // allows recovery of  7% of the data
     M,      // allows recovery of 15% of the data
@@ -185,17 +249,287 @@ pub enum ECCLevel {
 impl ECCLevel {
     /// Convert a simple string denoting the ECC level into
     /// the corresponding enum value
-    pub fn from_str(desc: &str) -> ECCLevel {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(desc: &str) -> QrResult<ECCLevel> {
         match desc {
-            "L" => ECCLevel::L,
-            "M" => ECCLevel::M,
-            "Q" => ECCLevel::Q,
-            "H" => ECCLevel::H,
-            _ => panic!("Unrecognized symbol configuration string!")
+            "L" => Ok(ECCLevel::L),
+            "M" => Ok(ECCLevel::M),
+            "Q" => Ok(ECCLevel::Q),
+            "H" => Ok(ECCLevel::H),
+            _ => Err(QrError::InvalidConfigString(desc.to_string()))
+        }
+    }
+
+    /// Iterate over all levels, from the lowest to the highest
+    pub fn all() -> impl Iterator<Item = ECCLevel> {
+        [ECCLevel::L, ECCLevel::M, ECCLevel::Q, ECCLevel::H].iter().copied()
+    }
+}
+
+impl FromStr for ECCLevel {
+    type Err = QrError;
+
+    fn from_str(desc: &str) -> QrResult<ECCLevel> {
+        ECCLevel::from_str(desc)
+    }
+}
+
+impl TryFrom<&str> for ECCLevel {
+    type Error = QrError;
+
+    fn try_from(desc: &str) -> QrResult<ECCLevel> {
+        ECCLevel::from_str(desc)
+    }
+}
+
+impl fmt::Display for ECCLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+
+/// Version of the rules used for all choices the standard leaves open to the encoder,
+/// such as the segmentation of the content, the tie-breaking between masks with equal scores
+/// and the padding. For a given content, configuration and policy the output stays byte-identical
+/// across releases of this crate; changes to those rules are introduced as a new policy.
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum EncodingPolicy {
+    V1      // optimal segmentation, lowest mask index wins ties for standard symbols and
+            // highest for micro symbols, alternating pad codewords 0xEC/0x11
+}
+
+impl EncodingPolicy {
+    /// The policy used when none is given explicitly
+    pub const LATEST: EncodingPolicy = EncodingPolicy::V1;
+}
+
+impl Default for EncodingPolicy {
+    fn default() -> Self {
+        EncodingPolicy::LATEST
+    }
+}
+
+/// Whether the automatic choice of the symbol size considers micro symbols
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum MicroPolicy {
+    StandardOnly,
+    PreferMicro,        // try all micro sizes before the standard sizes
+    MicroOnly
+}
+
+/// Options for the automatic choice of the symbol size
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct SizeSelection {
+    pub boost_ecc: bool,    // raise the ECC level as far as the content still fits into the chosen size
+    pub min_version: u8,    // smallest standard version to choose, e.g. for fixed label templates
+    pub max_version: u8,    // largest standard version to choose
+    pub micro: MicroPolicy
+}
+
+impl Default for SizeSelection {
+    fn default() -> Self {
+        SizeSelection {
+            boost_ecc: false,
+            min_version: 1,
+            max_version: 40,
+            micro: MicroPolicy::StandardOnly
+        }
+    }
+}
+
+/// All settings for encoding a symbol in one place, see encode_with_options. New settings are
+/// added here with a default that keeps the previous behavior, so construct the options with
+/// struct update syntax from `EncodeOptions::default()`.
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct EncodeOptions {
+    pub level: ECCLevel,
+    pub size: Option<Size>,             // None chooses the smallest size allowed by the selection
+    pub selection: SizeSelection,       // only used without a size
+    pub encoding: Option<Encoding>,     // None chooses the segmentation with the fewest bits
+    pub eci: Option<u32>,
+    pub mask: Option<u8>,               // None chooses the mask with the best score
+    pub policy: EncodingPolicy,
+    pub quiet_zone: Option<u32>,        // width of the quiet region in images, None for the required width
+    pub uppercase_alphanumeric: bool    // uppercase a-z if the encoding is alphanumeric, instead of failing
+}
+
+impl EncodeOptions {
+    /// Return the same options, but with the size, ECC level and mask of the given symbol, e.g. of a
+    /// symbol printed earlier. Encoding the same content with these options reproduces that symbol
+    /// exactly, or fails if it does not fit. Pin the policy the symbol was created with as well,
+    /// as the default policy may choose a different segmentation in later releases.
+    pub fn matching(self, info: SymbolInfo) -> EncodeOptions {
+        EncodeOptions {
+            level: info.level,
+            size: Some(info.size),
+            mask: Some(info.mask),
+            ..self
+        }
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            level: ECCLevel::M,
+            size: None,
+            selection: SizeSelection::default(),
+            encoding: None,
+            eci: None,
+            mask: None,
+            policy: EncodingPolicy::LATEST,
+            quiet_zone: None,
+            uppercase_alphanumeric: false
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Character sets with an ECI assignment number, see write_eci_header
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum Charset {
+    Cp437,
+    Latin1,             // ISO 8859-1, the default interpretation of byte mode
+    Iso8859(u8),        // the other parts 2 to 16 of ISO 8859, part 12 does not exist
+    ShiftJis,
+    Windows1250,
+    Windows1251,
+    Windows1252,
+    Windows1256,
+    Utf16BE,
+    Utf8,
+    Ascii,
+    Big5,
+    Gb18030,
+    EucKr
+}
+
+impl Charset {
+    /// Return the ECI assignment number of the character set
+    pub const fn eci(self) -> u32 {
+        match self {
+            Charset::Cp437 => 2,
+            Charset::Latin1 => 3,
+            Charset::Iso8859(part) => part as u32 + 2,
+            Charset::ShiftJis => 20,
+            Charset::Windows1250 => 21,
+            Charset::Windows1251 => 22,
+            Charset::Windows1252 => 23,
+            Charset::Windows1256 => 24,
+            Charset::Utf16BE => 25,
+            Charset::Utf8 => 26,
+            Charset::Ascii => 27,
+            Charset::Big5 => 28,
+            Charset::Gb18030 => 29,
+            Charset::EucKr => 30
         }
     }
+
+    /// Return the character set of an ECI assignment number, if it is one of the above.
+    /// The obsolete numbers 0 and 1 map to Cp437 and Latin1.
+    pub fn from_eci(assignment: u32) -> Option<Charset> {
+        Some(match assignment {
+            0 | 2 => Charset::Cp437,
+            1 | 3 => Charset::Latin1,
+            4..=13 | 15..=18 => Charset::Iso8859(assignment as u8 - 2),
+            20 => Charset::ShiftJis,
+            21 => Charset::Windows1250,
+            22 => Charset::Windows1251,
+            23 => Charset::Windows1252,
+            24 => Charset::Windows1256,
+            25 => Charset::Utf16BE,
+            26 => Charset::Utf8,
+            27 => Charset::Ascii,
+            28 => Charset::Big5,
+            29 => Charset::Gb18030,
+            30 => Charset::EucKr,
+            _ => return None
+        })
+    }
+}
+
+impl From<Charset> for u32 {
+    fn from(charset: Charset) -> u32 {
+        charset.eci()
+    }
+}
+
+/// ECI assignment number of UTF-8
+pub const ECI_UTF8: u32 = Charset::Utf8.eci();
+
+/// Content that can be encoded into a symbol. Texts are encoded as their UTF-8 bytes.
+pub trait IntoQrData<'a>: Sized {
+    fn into_qr_data(self) -> Cow<'a, [u8]>;
+
+    /// The content in the bytes scanners expect without an ECI header, which are ISO 8859-1,
+    /// together with the ECI assignment needed otherwise. Texts which can be represented in
+    /// ISO 8859-1 are converted; all other texts stay UTF-8 and need ECI 26. Bytes are taken
+    /// as they are.
+    fn into_qr_data_with_eci(self) -> (Cow<'a, [u8]>, Option<u32>) {
+        (self.into_qr_data(), None)
+    }
+}
+
+// convert a text to ISO 8859-1 if all its characters are representable, see into_qr_data_with_eci
+fn text_to_qr_data(text: Cow<str>) -> (Cow<[u8]>, Option<u32>) {
+    if text.is_ascii() {
+        (text.into_qr_data(), None)
+    } else if text.chars().all(|c| (c as u32) <= 0xFF) {
+        (Cow::Owned(text.chars().map(|c| c as u8).collect()), None)
+    } else {
+        (text.into_qr_data(), Some(ECI_UTF8))
+    }
+}
+
+impl<'a> IntoQrData<'a> for &'a [u8] {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Borrowed(self) }
+}
+
+impl<'a, const N: usize> IntoQrData<'a> for &'a [u8; N] {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Borrowed(&self[..]) }
+}
+
+impl<'a> IntoQrData<'a> for &'a Vec<u8> {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Borrowed(self) }
+}
+
+impl<'a> IntoQrData<'a> for Vec<u8> {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Owned(self) }
 }
 
+impl<'a> IntoQrData<'a> for Cow<'a, [u8]> {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { self }
+}
+
+impl<'a> IntoQrData<'a> for &'a str {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Borrowed(self.as_bytes()) }
+    fn into_qr_data_with_eci(self) -> (Cow<'a, [u8]>, Option<u32>) { text_to_qr_data(Cow::Borrowed(self)) }
+}
+
+impl<'a> IntoQrData<'a> for &'a String {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Borrowed(self.as_bytes()) }
+    fn into_qr_data_with_eci(self) -> (Cow<'a, [u8]>, Option<u32>) { text_to_qr_data(Cow::Borrowed(self)) }
+}
+
+impl<'a> IntoQrData<'a> for String {
+    fn into_qr_data(self) -> Cow<'a, [u8]> { Cow::Owned(self.into_bytes()) }
+    fn into_qr_data_with_eci(self) -> (Cow<'a, [u8]>, Option<u32>) { text_to_qr_data(Cow::Owned(self)) }
+}
+
+impl<'a> IntoQrData<'a> for Cow<'a, str> {
+    fn into_qr_data(self) -> Cow<'a, [u8]> {
+        match self {
+            Cow::Borrowed(text) => Cow::Borrowed(text.as_bytes()),
+            Cow::Owned(text) => Cow::Owned(text.into_bytes())
+        }
+    }
+
+    fn into_qr_data_with_eci(self) -> (Cow<'a, [u8]>, Option<u32>) { text_to_qr_data(self) }
+}
+
+//-------------------------------------------------------------------------------------------------
 
 #[derive(Clone,Copy,Hash, Eq, PartialEq,Debug)]
 pub struct SymbolConfig(Size, ECCLevel);
@@ -206,12 +540,77 @@ impl SymbolConfig {
         SymbolConfig(s, e)
     }
 
+    /// Return the size of the configuration
+    pub fn size(self) -> Size {
+        self.0
+    }
+
+    /// Return the ECC level of the configuration
+    pub fn ecc(self) -> ECCLevel {
+        self.1
+    }
+
+    /// Iterate over all valid configurations, ordered by size and then by ECC level
+    pub fn iter_all() -> impl Iterator<Item = SymbolConfig> {
+        Size::all()
+            .flat_map(|size| ECCLevel::all().map(move |level| SymbolConfig::new(size, level)))
+            .filter(|config| config.validate(&[]).is_ok())
+    }
+
+    /// Check up front that the size exists, that the ECC level is available for it and that
+    /// all given encodings can be used in it. M1 symbols only support level L, M2 and M3 symbols
+    /// L and M, M4 symbols L, M and Q; level H is only available for standard symbols.
+    pub fn validate(self, encodings: &[Encoding]) -> QrResult<()> {
+        let SymbolConfig(size, level) = self;
+        if !size.is_valid() {
+            return Err(QrError::InvalidSize(size));
+        }
+        let max_level = match size {
+            Size::Micro(1) => ECCLevel::L,
+            Size::Micro(2) | Size::Micro(3) => ECCLevel::M,
+            Size::Micro(_) => ECCLevel::Q,
+            Size::Standard(_) => ECCLevel::H
+        };
+        if level > max_level {
+            return Err(QrError::InvalidSymbolConfig(size, level));
+        }
+        match encodings.iter().find(|ec| !ec.is_available(size)) {
+            Some(&ec) => Err(QrError::UnsupportedEncoding(ec, size)),
+            None => Ok(())
+        }
+    }
+
     /// Convenience function that creates a SymbolConfig from
     /// a string in the form commonly used in the standard,
     /// such as 1-H, M3-L, 6-M, etc.
-    pub fn from_str(decl: &str) -> SymbolConfig {
-        let (s, e) = decl.split("-").next_tuple().unwrap();
-        SymbolConfig::new(Size::from_str(s), ECCLevel::from_str(e))
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(decl: &str) -> QrResult<SymbolConfig> {
+        let (s, e) = decl.split('-').collect_tuple()
+            .ok_or_else(|| QrError::InvalidConfigString(decl.to_string()))?;
+        Ok(SymbolConfig::new(Size::from_str(s)?, ECCLevel::from_str(e)?))
+    }
+}
+
+impl FromStr for SymbolConfig {
+    type Err = QrError;
+
+    fn from_str(decl: &str) -> QrResult<SymbolConfig> {
+        SymbolConfig::from_str(decl)
+    }
+}
+
+impl TryFrom<&str> for SymbolConfig {
+    type Error = QrError;
+
+    fn try_from(decl: &str) -> QrResult<SymbolConfig> {
+        SymbolConfig::from_str(decl)
+    }
+}
+
+impl fmt::Display for SymbolConfig {
+    /// Formats the configuration in the form accepted by from_str, e.g. "M2-L" or "6-M"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.0, self.1)
     }
 }
 
@@ -223,10 +622,88 @@ mod tests {
 
     #[test]
     fn test_symbol_code_parsing() {
-        assert_eq!(SymbolConfig::from_str("M2-M"), SymbolConfig::new(Size::Micro(2), ECCLevel::M));
-        assert_eq!(SymbolConfig::from_str("M3-H"), SymbolConfig::new(Size::Micro(3), ECCLevel::H));
-        assert_eq!(SymbolConfig::from_str("2-L"), SymbolConfig::new(Size::Standard(2), ECCLevel::L));
-        assert_eq!(SymbolConfig::from_str("20-Q"), SymbolConfig::new(Size::Standard(20), ECCLevel::Q));
-        assert_eq!(SymbolConfig::from_str("38-M"), SymbolConfig::new(Size::Standard(38), ECCLevel::M));
+        assert_eq!(SymbolConfig::from_str("M2-M"), Ok(SymbolConfig::new(Size::Micro(2), ECCLevel::M)));
+        assert_eq!(SymbolConfig::from_str("M3-H"), Ok(SymbolConfig::new(Size::Micro(3), ECCLevel::H)));
+        assert_eq!(SymbolConfig::from_str("2-L"), Ok(SymbolConfig::new(Size::Standard(2), ECCLevel::L)));
+        assert_eq!(SymbolConfig::from_str("20-Q"), Ok(SymbolConfig::new(Size::Standard(20), ECCLevel::Q)));
+        assert_eq!(SymbolConfig::from_str("38-M"), Ok(SymbolConfig::new(Size::Standard(38), ECCLevel::M)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let validate = |decl, encodings: &[Encoding]| SymbolConfig::from_str(decl).unwrap().validate(encodings);
+        assert_eq!(validate("M1-L", &[Encoding::Numeric]), Ok(()));
+        assert_eq!(validate("M1-M", &[]), Err(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::M)));
+        assert_eq!(validate("M1-L", &[Encoding::Alphanumeric]), Err(QrError::UnsupportedEncoding(Encoding::Alphanumeric, Size::Micro(1))));
+        assert_eq!(validate("M2-M", &[Encoding::Numeric, Encoding::Bytes]), Err(QrError::UnsupportedEncoding(Encoding::Bytes, Size::Micro(2))));
+        assert_eq!(validate("M3-Q", &[]), Err(QrError::InvalidSymbolConfig(Size::Micro(3), ECCLevel::Q)));
+        assert_eq!(validate("M4-Q", &[Encoding::Kanji]), Ok(()));
+        assert_eq!(validate("M4-H", &[]), Err(QrError::InvalidSymbolConfig(Size::Micro(4), ECCLevel::H)));
+        assert_eq!(validate("40-H", &[Encoding::Bytes]), Ok(()));
+        assert_eq!(SymbolConfig::new(Size::Standard(41), ECCLevel::L).validate(&[]), Err(QrError::InvalidSize(Size::Standard(41))));
+    }
+
+    #[test]
+    fn test_iter_all() {
+        assert_eq!(Size::all().count(), 44);
+        assert!(Size::all().all(Size::is_valid));
+        assert_eq!(Size::all().next(), Some(Size::Micro(1)));
+        assert_eq!(Size::all().last(), Some(Size::Standard(40)));
+        assert_eq!(ECCLevel::all().collect::<Vec<_>>(), vec![ECCLevel::L, ECCLevel::M, ECCLevel::Q, ECCLevel::H]);
+
+        // M1 has one level, M2 and M3 two, M4 three, the standard sizes all four
+        let configs: Vec<SymbolConfig> = SymbolConfig::iter_all().collect();
+        assert_eq!(configs.len(), 1 + 2 + 2 + 3 + 40 * 4);
+        assert_eq!(configs[..3].iter().map(|c| c.to_string()).collect::<Vec<_>>(), vec!["M1-L", "M2-L", "M2-M"]);
+        assert!(configs.iter().all(|c| c.validate(&[]).is_ok()));
+    }
+
+    #[test]
+    fn test_into_qr_data() {
+        assert_eq!("äb".into_qr_data(), Cow::Borrowed(&[0xC3, 0xA4, b'b'][..]));
+        assert_eq!(String::from("ab").into_qr_data(), b"ab".into_qr_data());
+        assert_eq!(Cow::Borrowed("ab").into_qr_data(), vec![b'a', b'b'].into_qr_data());
+        assert!(matches!(String::from("ab").into_qr_data(), Cow::Owned(_)));
+
+        // texts are downgraded to ISO 8859-1 where possible, bytes are kept
+        assert_eq!("ab".into_qr_data_with_eci(), (Cow::Borrowed(&b"ab"[..]), None));
+        assert_eq!("äb".into_qr_data_with_eci(), (Cow::Owned(vec![0xE4, b'b']), None));
+        assert_eq!(String::from("€5").into_qr_data_with_eci(), (Cow::Owned("€5".as_bytes().to_vec()), Some(ECI_UTF8)));
+        assert_eq!("äb".as_bytes().into_qr_data_with_eci(), (Cow::Borrowed("äb".as_bytes()), None));
+    }
+
+    #[test]
+    fn test_charset() {
+        assert_eq!(Charset::Utf8.eci(), 26);
+        assert_eq!(u32::from(Charset::Iso8859(15)), 17);
+        assert_eq!(Charset::from_eci(1), Some(Charset::Latin1));
+        assert_eq!(Charset::from_eci(14), None);
+        assert_eq!(Charset::from_eci(899), None);
+        for assignment in 2..=30 {
+            if let Some(charset) = Charset::from_eci(assignment) {
+                assert_eq!(charset.eci(), assignment);
+            }
+        }
+    }
+
+    #[test]
+    fn test_std_traits() {
+        assert_eq!("M3".parse::<Size>(), Ok(Size::Micro(3)));
+        assert_eq!(Size::try_from("40"), Ok(Size::Standard(40)));
+        assert_eq!("Q".parse::<ECCLevel>(), Ok(ECCLevel::Q));
+        assert_eq!(ECCLevel::try_from("q"), Err(QrError::InvalidConfigString("q".to_string())));
+        assert_eq!(SymbolConfig::try_from("M2-M"), Ok(SymbolConfig::new(Size::Micro(2), ECCLevel::M)));
+        assert!("41-L".parse::<SymbolConfig>().is_err());
+
+        for decl in ["M1-L", "M4-Q", "1-H", "40-M"].iter() {
+            assert_eq!(decl.parse::<SymbolConfig>().unwrap().to_string(), *decl);
+        }
+    }
+
+    #[test]
+    fn test_invalid_symbol_code() {
+        for decl in ["M5-L", "41-L", "0-M", "2-X", "2", "2-L-M", "abc"].iter() {
+            assert!(matches!(SymbolConfig::from_str(decl), Err(QrError::InvalidConfigString(_))), "{}", decl);
+        }
     }
 }
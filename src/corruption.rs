@@ -0,0 +1,139 @@
+//! Systematically corrupted copies of a finished symbol, for characterizing how much damage
+//! a reader tolerates. The corruptions are enumerated in a fixed order instead of being drawn
+//! at random, so every run of a test sees exactly the same symbols.
+use crate::config::Size;
+use crate::matrix::{Matrix, Module};
+use crate::serialization::info_placement;
+
+//-------------------------------------------------------------------------------------------------
+
+/// A kind of damage done to a symbol
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub enum Corruption {
+    BitFlip { x: u32, y: u32 },                         // a single inverted module
+    BlockErasure { x: u32, y: u32, width: u32 },        // a square with the upper left corner at (x, y), set to light
+    FormatInfo { copy: usize, bits: u16 }               // inverted bits of one copy of the format information
+}
+
+impl Corruption {
+    /// Apply the damage to a symbol of the given size. Modules outside of the symbol are ignored.
+    pub fn apply(&self, symbol: &mut Matrix, size: Size) {
+        match *self {
+            Corruption::BitFlip { x, y } => {
+                if let Some(module) = symbol.get(x as i32, y as i32) {
+                    symbol.set((x, y), module.inverted());
+                }
+            },
+            Corruption::BlockErasure { x, y, width } => {
+                for dy in 0..width {
+                    for dx in 0..width {
+                        symbol.set_clipped((x + dx) as i32, (y + dy) as i32, Module::Light);
+                    }
+                }
+            },
+            Corruption::FormatInfo { copy, bits } => {
+                // bits are given most significant bit first, like the placement
+                if let Some(coords) = info_placement(size).ok().and_then(|p| p.format.get(copy)) {
+                    for (i, &(x, y)) in coords.iter().enumerate() {
+                        if bits & (1 << (14 - i)) != 0 {
+                            symbol.set((x, y), symbol[(x, y)].inverted());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return a damaged copy of the symbol
+    pub fn applied_to(&self, symbol: &Matrix, size: Size) -> Matrix {
+        let mut damaged = symbol.clone();
+        self.apply(&mut damaged, size);
+        damaged
+    }
+}
+
+/// One flipped module at every position of the symbol, in row-major order
+pub fn bit_flips(size: Size) -> impl Iterator<Item = Corruption> {
+    let width = size.dimensions();
+    (0..width * width).map(move |i| Corruption::BitFlip { x: i % width, y: i / width })
+}
+
+/// An erased square of the given width at every position where it fits into the symbol,
+/// in row-major order of the upper left corner
+pub fn block_erasures(size: Size, width: u32) -> impl Iterator<Item = Corruption> {
+    let positions = (size.dimensions() + 1).saturating_sub(width);
+    (0..positions * positions).map(move |i| Corruption::BlockErasure { x: i % positions, y: i / positions, width })
+}
+
+/// Every combination of the given number of inverted bits, in each copy of the format information
+pub fn format_info_damage(size: Size, errors: u32) -> impl Iterator<Item = Corruption> {
+    let copies = info_placement(size).map_or(0, |p| p.format.len());
+    (0..copies).flat_map(move |copy| (0..1u16 << 15)
+        .filter(move |bits| bits.count_ones() == errors)
+        .map(move |bits| Corruption::FormatInfo { copy, bits }))
+}
+
+/// Pair each corruption with the damaged copy of the symbol
+pub fn corrupted_symbols<'a>(symbol: &'a Matrix,
+                             size: Size,
+                             corruptions: impl Iterator<Item = Corruption> + 'a) -> impl Iterator<Item = (Corruption, Matrix)> + 'a {
+    corruptions.map(move |corruption| {
+        let damaged = corruption.applied_to(symbol, size);
+        (corruption, damaged)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ECCLevel;
+    use crate::serialization::{create_qr_canvas, insert_format_info, inspect_matrix, SymbolInfo, MAX_INFO_BIT_ERRORS};
+
+    // a symbol with format information, but without any content
+    fn symbol_without_content(size: Size) -> Matrix {
+        let mut symbol = create_qr_canvas(size);
+        insert_format_info(&mut symbol, size, ECCLevel::L, 1).unwrap();
+        for (_, _, module) in symbol.enumerate_modules_mut() {
+            if module.is_reserved() {
+                *module = Module::Light;
+            }
+        }
+        symbol
+    }
+
+    #[test]
+    fn test_bit_flips_and_erasures() {
+        let symbol = symbol_without_content(Size::Micro(1));
+        let damaged: Vec<_> = corrupted_symbols(&symbol, Size::Micro(1), bit_flips(Size::Micro(1))).collect();
+        assert_eq!(damaged.len(), 11 * 11);
+        for (_, matrix) in &damaged {
+            let changed = matrix.enumerate_modules().filter(|&(x, y, m)| symbol[(x, y)] != m).count();
+            assert_eq!(changed, 1);
+        }
+        assert_eq!(damaged[12].0, Corruption::BitFlip { x: 1, y: 1 });
+
+        assert_eq!(block_erasures(Size::Micro(1), 3).count(), 9 * 9);
+        assert_eq!(block_erasures(Size::Micro(1), 12).count(), 0);
+        let erased = Corruption::BlockErasure { x: 0, y: 0, width: 3 }.applied_to(&symbol, Size::Micro(1));
+        assert!(erased.enumerate_modules().all(|(x, y, m)| (x >= 3 || y >= 3) || m == Module::Light));
+    }
+
+    #[test]
+    fn test_format_info_damage() {
+        // 15 choose 3 combinations in the single copy of micro symbols, all of them correctable
+        let size = Size::Micro(2);
+        let symbol = symbol_without_content(size);
+        let expected = Ok(SymbolInfo { size, level: ECCLevel::L, mask: 1 });
+        assert_eq!(format_info_damage(size, MAX_INFO_BIT_ERRORS).count(), 455);
+        assert!(corrupted_symbols(&symbol, size, format_info_damage(size, MAX_INFO_BIT_ERRORS))
+            .all(|(_, damaged)| inspect_matrix(&damaged) == expected));
+
+        // a standard symbol has two copies, so damage to one of them is always correctable
+        let size = Size::Standard(1);
+        let symbol = symbol_without_content(size);
+        assert_eq!(format_info_damage(size, 5).count(), 2 * 3003);
+        assert!(corrupted_symbols(&symbol, size, format_info_damage(size, 5))
+            .all(|(_, damaged)| inspect_matrix(&damaged).map(|info| info.mask) == Ok(1)));
+    }
+}
@@ -0,0 +1,158 @@
+//! Error type returned by all fallible functions of the crate.
+use crate::config::{ECCLevel, Encoding, Size, SymbolConfig};
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//-------------------------------------------------------------------------------------------------
+
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub enum QrError {
+    InvalidConfigString(String),            // a size, ECC level or symbol configuration string could not be parsed
+    InvalidSize(Size),                      // version number out of range
+    InvalidSymbolConfig(Size, ECCLevel),    // the ECC level is not available for the size
+    UnsupportedEncoding(Encoding, Size),    // the encoding is not available for the (micro) size
+    InvalidCharacter {                      // the content contains a character the encoding cannot represent
+        encoding: Encoding,
+        position: usize,                    // byte offset of the character in the content
+        value: u8                           // first byte of the character
+    },
+    DataTooLong {                           // the encoded data does not fit into the symbol
+        bits: usize,
+        capacity: usize,
+        suggestion: Option<SymbolConfig>    // smallest configuration the content fits into, if known
+    },
+    WrongNumberOfCodewords {                // codewords given for a symbol do not match its capacity
+        expected: usize,
+        actual: usize
+    },
+    InvalidMaskPattern(u8, Size),           // mask pattern index out of range for the size
+    InvalidEciAssignment(u32),              // ECI assignment number out of range or reserved
+    EciNotSupported(Size),                  // micro symbols can not contain ECI headers
+    InvalidStructuredAppend {               // structured append series have 2 to 16 symbols
+        position: u8,
+        total: u8
+    },
+    StructuredAppendNotSupported(Size),     // micro symbols can not be part of a structured append series
+    WriteFailed(io::ErrorKind),             // writing the output to a caller-provided sink failed
+    ReadFailed(io::ErrorKind),              // reading the content from a caller-provided source failed
+    InvalidMatrixWidth(u32),                // no symbol size has a matrix of this width
+    UnreadableFormatInfo,                   // the format information of a symbol has too many errors
+    UnreadableVersionInfo,                  // the version information of a symbol has too many errors
+    InvalidApplicationIndicator(String),    // FNC1 application indicators are a letter or two digits
+    Fnc1NotSupported(Size),                 // micro symbols can not use FNC1 mode
+    UnknownApplicationIdentifier(String),   // the GS1 application identifier is not supported
+    InvalidGs1Value(String),                // the value does not match the format of its GS1 application identifier
+    RegionOutOfBounds {                     // the symbol does not fit into the target image at the given position
+        x: u32,
+        y: u32,
+        size: u32                           // edge length of the symbol in pixels, u32::MAX if it overflows
+    },
+    InvalidChannelCount(usize),             // one to three symbols can be multiplexed into the color channels
+    MultiplexSizeMismatch(Size, Size),      // multiplexed symbols must have the same size
+    LogoTooLarge,                           // no allowed size and ECC level can restore the modules under the logo
+    InvalidLastCodeword(u8),                // the unused low nibble of the last data codeword of M1/M3 is not zero
+    InvalidBase45(usize),                   // position of an invalid character or group in Base45 text
+    InvalidZlibData,                        // a Base45 or compressed payload does not contain a valid zlib stream
+    InvalidBufferLayout {                   // the pixel buffer is too short, or its rows are shorter than its width
+        width: u32,
+        height: u32,
+        stride: usize,
+        len: usize
+    },
+    WrongCheckDigits                        // the check digits of a numeric payload do not match its digits
+}
+
+pub type QrResult<T> = Result<T, QrError>;
+
+impl fmt::Display for QrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QrError::InvalidConfigString(s) =>
+                write!(f, "unrecognized symbol configuration string '{}'", s),
+            QrError::InvalidSize(size) =>
+                write!(f, "invalid symbol size {:?}", size),
+            QrError::InvalidSymbolConfig(size, level) =>
+                write!(f, "ECC level {:?} is not available for symbol size {:?}", level, size),
+            QrError::UnsupportedEncoding(encoding, size) =>
+                write!(f, "encoding {:?} is not available for symbol size {:?}", encoding, size),
+            QrError::InvalidCharacter { encoding, position, value } =>
+                write!(f, "character 0x{:02X} at position {} cannot be encoded in {:?} mode", value, position, encoding),
+            QrError::DataTooLong { bits, capacity, suggestion } => {
+                write!(f, "data needs {} bits, but the symbol only holds {} bits", bits, capacity)?;
+                match suggestion {
+                    Some(config) => write!(f, "; it fits into {}", config),
+                    None => Ok(())
+                }
+            },
+            QrError::WrongNumberOfCodewords { expected, actual } =>
+                write!(f, "expected {} codewords, but got {}", expected, actual),
+            QrError::InvalidMaskPattern(mask, size) =>
+                write!(f, "invalid mask pattern {} for symbol size {:?}", mask, size),
+            QrError::InvalidEciAssignment(assignment) =>
+                write!(f, "invalid ECI assignment number {}", assignment),
+            QrError::EciNotSupported(size) =>
+                write!(f, "symbol size {:?} does not support ECI", size),
+            QrError::InvalidStructuredAppend { position, total } =>
+                write!(f, "invalid structured append position {} in a series of {} symbols", position, total),
+            QrError::StructuredAppendNotSupported(size) =>
+                write!(f, "symbol size {:?} does not support structured append", size),
+            QrError::WriteFailed(kind) =>
+                write!(f, "writing the output failed: {:?}", kind),
+            QrError::ReadFailed(kind) =>
+                write!(f, "reading the content failed: {:?}", kind),
+            QrError::InvalidMatrixWidth(width) =>
+                write!(f, "no symbol size has a matrix width of {} modules", width),
+            QrError::UnreadableFormatInfo =>
+                write!(f, "the format information of the symbol cannot be read"),
+            QrError::UnreadableVersionInfo =>
+                write!(f, "the version information of the symbol cannot be read"),
+            QrError::InvalidApplicationIndicator(s) =>
+                write!(f, "invalid FNC1 application indicator '{}'", s),
+            QrError::Fnc1NotSupported(size) =>
+                write!(f, "symbol size {:?} does not support FNC1 mode", size),
+            QrError::UnknownApplicationIdentifier(ai) =>
+                write!(f, "unknown GS1 application identifier '{}'", ai),
+            QrError::InvalidGs1Value(ai) =>
+                write!(f, "invalid value for GS1 application identifier '{}'", ai),
+            QrError::RegionOutOfBounds { x, y, size } =>
+                write!(f, "a symbol of {} pixels at ({}, {}) does not fit into the target image", size, x, y),
+            QrError::InvalidChannelCount(count) =>
+                write!(f, "cannot multiplex {} symbols into the three color channels", count),
+            QrError::MultiplexSizeMismatch(expected, actual) =>
+                write!(f, "cannot multiplex symbols of sizes {:?} and {:?}", expected, actual),
+            QrError::LogoTooLarge =>
+                write!(f, "the logo covers more modules than the error correction can restore"),
+            QrError::InvalidLastCodeword(codeword) =>
+                write!(f, "the last data codeword 0x{:02X} of an M1 or M3 symbol must have a zero low nibble", codeword),
+            QrError::InvalidBase45(position) =>
+                write!(f, "invalid Base45 text at position {}", position),
+            QrError::InvalidZlibData =>
+                write!(f, "the payload is not valid zlib data"),
+            QrError::InvalidBufferLayout { width, height, stride, len } =>
+                write!(f, "a buffer of {} bytes with a stride of {} cannot hold {}x{} pixels", len, stride, width, height),
+            QrError::WrongCheckDigits =>
+                write!(f, "the check digits do not match the payload")
+        }
+    }
+}
+
+impl Error for QrError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(QrError::InvalidConfigString("M5".to_string()).to_string(),
+                   "unrecognized symbol configuration string 'M5'");
+        assert_eq!(QrError::DataTooLong { bits: 200, capacity: 152, suggestion: None }.to_string(),
+                   "data needs 200 bits, but the symbol only holds 152 bits");
+        let suggestion = Some(SymbolConfig::new(Size::Standard(2), ECCLevel::L));
+        assert_eq!(QrError::DataTooLong { bits: 200, capacity: 152, suggestion }.to_string(),
+                   "data needs 200 bits, but the symbol only holds 152 bits; it fits into 2-L");
+    }
+}
@@ -0,0 +1,264 @@
+//! Builds GS1 element strings from (application identifier, value) pairs, to be encoded
+//! in FNC1 mode with FNC1 in first position (see bitcoding::Fnc1), and parses them back.
+use crate::error::{QrError, QrResult};
+
+//-------------------------------------------------------------------------------------------------
+
+/// Separator between elements with variable length, encoded as FNC1 in byte mode
+pub const GROUP_SEPARATOR: u8 = 0x1D;
+
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+enum ValueFormat {
+    Numeric(usize, usize),          // digits only, minimum and maximum length
+    Alphanumeric(usize, usize),     // GS1 character set 82, minimum and maximum length
+    NumericCheckDigit(usize),       // fixed number of digits, the last one a GS1 check digit
+    Date                            // YYMMDD, with day 00 for the end of the month
+}
+
+impl ValueFormat {
+    // maximum length of the value, which is the length for AIs with predefined length
+    fn max_len(self) -> usize {
+        match self {
+            ValueFormat::Numeric(_, max) | ValueFormat::Alphanumeric(_, max) => max,
+            ValueFormat::NumericCheckDigit(length) => length,
+            ValueFormat::Date => 6
+        }
+    }
+}
+
+// supported application identifiers: prefix of the AI, length of the AI, format of the value.
+// For AIs with 4 digits the last digit is the number of decimal places or a sequence number.
+static AI_FORMATS: [(&str, usize, ValueFormat); 55] = [
+    ("00", 2, ValueFormat::NumericCheckDigit(18)),      // SSCC
+    ("01", 2, ValueFormat::NumericCheckDigit(14)),      // GTIN
+    ("02", 2, ValueFormat::NumericCheckDigit(14)),      // GTIN of contained trade items
+    ("10", 2, ValueFormat::Alphanumeric(1, 20)),        // batch or lot number
+    ("11", 2, ValueFormat::Date),                       // production date
+    ("12", 2, ValueFormat::Date),                       // due date
+    ("13", 2, ValueFormat::Date),                       // packaging date
+    ("15", 2, ValueFormat::Date),                       // best before date
+    ("16", 2, ValueFormat::Date),                       // sell by date
+    ("17", 2, ValueFormat::Date),                       // expiration date
+    ("20", 2, ValueFormat::Numeric(2, 2)),              // internal product variant
+    ("21", 2, ValueFormat::Alphanumeric(1, 20)),        // serial number
+    ("22", 2, ValueFormat::Alphanumeric(1, 20)),        // consumer product variant
+    ("240", 3, ValueFormat::Alphanumeric(1, 30)),       // additional product identification
+    ("241", 3, ValueFormat::Alphanumeric(1, 30)),       // customer part number
+    ("250", 3, ValueFormat::Alphanumeric(1, 30)),       // secondary serial number
+    ("251", 3, ValueFormat::Alphanumeric(1, 30)),       // reference to source entity
+    ("254", 3, ValueFormat::Alphanumeric(1, 20)),       // GLN extension component
+    ("30", 2, ValueFormat::Numeric(1, 8)),              // variable count
+    ("310", 4, ValueFormat::Numeric(6, 6)),             // net weight, kg
+    ("311", 4, ValueFormat::Numeric(6, 6)),             // length, m
+    ("312", 4, ValueFormat::Numeric(6, 6)),             // width, m
+    ("313", 4, ValueFormat::Numeric(6, 6)),             // depth, m
+    ("314", 4, ValueFormat::Numeric(6, 6)),             // area, m²
+    ("315", 4, ValueFormat::Numeric(6, 6)),             // net volume, l
+    ("316", 4, ValueFormat::Numeric(6, 6)),             // net volume, m³
+    ("330", 4, ValueFormat::Numeric(6, 6)),             // gross weight, kg
+    ("37", 2, ValueFormat::Numeric(1, 8)),              // count of trade items
+    ("390", 4, ValueFormat::Numeric(1, 15)),            // amount payable, local currency
+    ("392", 4, ValueFormat::Numeric(1, 15)),            // amount payable, single item
+    ("400", 3, ValueFormat::Alphanumeric(1, 30)),       // customer's purchase order number
+    ("401", 3, ValueFormat::Alphanumeric(1, 30)),       // global identification number for consignment
+    ("402", 3, ValueFormat::NumericCheckDigit(17)),     // global shipment identification number
+    ("403", 3, ValueFormat::Alphanumeric(1, 30)),       // routing code
+    ("410", 3, ValueFormat::NumericCheckDigit(13)),     // ship to GLN
+    ("411", 3, ValueFormat::NumericCheckDigit(13)),     // bill to GLN
+    ("412", 3, ValueFormat::NumericCheckDigit(13)),     // purchased from GLN
+    ("413", 3, ValueFormat::NumericCheckDigit(13)),     // ship for GLN
+    ("414", 3, ValueFormat::NumericCheckDigit(13)),     // physical location GLN
+    ("415", 3, ValueFormat::NumericCheckDigit(13)),     // invoicing party GLN
+    ("420", 3, ValueFormat::Alphanumeric(1, 20)),       // ship to postal code
+    ("422", 3, ValueFormat::Numeric(3, 3)),             // country of origin
+    ("7003", 4, ValueFormat::Numeric(10, 10)),          // expiration date and time
+    ("8004", 4, ValueFormat::Alphanumeric(1, 30)),      // global individual asset identifier
+    ("8020", 4, ValueFormat::Alphanumeric(1, 25)),      // payment slip reference number
+    ("90", 2, ValueFormat::Alphanumeric(1, 30)),        // mutually agreed between trading partners
+    ("91", 2, ValueFormat::Alphanumeric(1, 90)),        // company internal information
+    ("92", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("93", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("94", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("95", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("96", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("97", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("98", 2, ValueFormat::Alphanumeric(1, 90)),
+    ("99", 2, ValueFormat::Alphanumeric(1, 90)),
+];
+
+// AIs starting with these two digits have a predefined length and are never followed by a
+// separator, see figure 7.8.5-2 of the GS1 general specifications
+static PREDEFINED_LENGTH_PREFIXES: [&str; 22] = [
+    "00", "01", "02", "03", "04", "11", "12", "13", "14", "15", "16", "17", "18", "19", "20",
+    "31", "32", "33", "34", "35", "36", "41",
+];
+
+
+/// Whether the character is in the GS1 character set 82
+fn is_cset82(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!\"%&'()*+,-./:;<=>?_".contains(&c)
+}
+
+/// Compute the GS1 check digit for the given digits (without the check digit).
+/// Returns None if the input contains anything but digits.
+pub fn check_digit(digits: &[u8]) -> Option<u8> {
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    // weights alternate 3 and 1, starting with 3 at the rightmost digit
+    let sum: u32 = digits.iter().rev().enumerate()
+        .map(|(i, &d)| (d - b'0') as u32 * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+    Some(((10 - sum % 10) % 10) as u8 + b'0')
+}
+
+fn lookup_format(ai: &str) -> QrResult<ValueFormat> {
+    if ai.bytes().all(|c| c.is_ascii_digit()) {
+        for &(prefix, length, format) in AI_FORMATS.iter() {
+            if ai.len() == length && ai.starts_with(prefix) {
+                return Ok(format);
+            }
+        }
+    }
+    Err(QrError::UnknownApplicationIdentifier(ai.to_string()))
+}
+
+fn is_valid_value(value: &[u8], format: ValueFormat) -> bool {
+    match format {
+        ValueFormat::Numeric(min, max) =>
+            (min..=max).contains(&value.len()) && value.iter().all(u8::is_ascii_digit),
+        ValueFormat::Alphanumeric(min, max) =>
+            (min..=max).contains(&value.len()) && value.iter().all(|&c| is_cset82(c)),
+        ValueFormat::NumericCheckDigit(length) =>
+            value.len() == length && value.iter().all(u8::is_ascii_digit) &&
+                check_digit(&value[..length - 1]) == Some(value[length - 1]),
+        ValueFormat::Date => {
+            let number = |i: usize| (value[i] - b'0') * 10 + value[i + 1] - b'0';
+            value.len() == 6 && value.iter().all(u8::is_ascii_digit) &&
+                (1..=12).contains(&number(2)) && number(4) <= days_in_month(number(0), number(2))
+        }
+    }
+}
+
+// number of days of the month in the year YY. Every year divisible by 4 is a leap year,
+// which holds for all years from 1901 to 2099.
+fn days_in_month(year: u8, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// Validate the given elements and concatenate them to an element string, inserting group
+/// separators after elements whose application identifier has no predefined length.
+/// The result is meant to be encoded with FNC1 in first position.
+pub fn element_string(elements: &[(&str, &str)]) -> QrResult<Vec<u8>> {
+    let mut result = Vec::new();
+    for (i, &(ai, value)) in elements.iter().enumerate() {
+        if !is_valid_value(value.as_bytes(), lookup_format(ai)?) {
+            return Err(QrError::InvalidGs1Value(ai.to_string()));
+        }
+
+        result.extend_from_slice(ai.as_bytes());
+        result.extend_from_slice(value.as_bytes());
+        let predefined = PREDEFINED_LENGTH_PREFIXES.contains(&&ai[..2]);
+        if !predefined && i + 1 < elements.len() {
+            result.push(GROUP_SEPARATOR);
+        }
+    }
+    Ok(result)
+}
+
+/// Split an element string, e.g. the content of a symbol in FNC1 mode, into validated
+/// (application identifier, value) pairs. This is the inverse of element_string; a group
+/// separator after an element with predefined length is accepted as well.
+pub fn parse_element_string(data: &[u8]) -> QrResult<Vec<(String, String)>> {
+    let mut elements = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (ai, format) = (2..=4)
+            .filter(|&len| len <= rest.len())
+            .find_map(|len| {
+                let ai = String::from_utf8_lossy(&rest[..len]).into_owned();
+                lookup_format(&ai).ok().map(|format| (ai, format))
+            })
+            .ok_or_else(|| QrError::UnknownApplicationIdentifier(String::from_utf8_lossy(&rest[..rest.len().min(4)]).into_owned()))?;
+        rest = &rest[ai.len()..];
+
+        let value_len = if PREDEFINED_LENGTH_PREFIXES.contains(&&ai[..2]) {
+            format.max_len().min(rest.len())
+        } else {
+            rest.iter().position(|&c| c == GROUP_SEPARATOR).unwrap_or(rest.len())
+        };
+        let value = &rest[..value_len];
+        if !is_valid_value(value, format) {
+            return Err(QrError::InvalidGs1Value(ai));
+        }
+        elements.push((ai, String::from_utf8_lossy(value).into_owned()));
+
+        rest = &rest[value_len..];
+        if rest.first() == Some(&GROUP_SEPARATOR) {
+            rest = &rest[1..];
+        }
+    }
+    Ok(elements)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_digit() {
+        assert_eq!(check_digit(b"0950110153000"), Some(b'3'));
+        assert_eq!(check_digit(b"400638133393"), Some(b'1'));
+        assert_eq!(check_digit(b"629104150021"), Some(b'3'));
+        // characters below and above the digits
+        assert_eq!(check_digit(b"4006/8133393"), None);
+        assert_eq!(check_digit(b"40063813339A"), None);
+    }
+
+    #[test]
+    fn test_element_string() {
+        assert_eq!(element_string(&[("01", "09501101530003"), ("17", "140704"), ("10", "AB-123"), ("21", "42")]),
+                   Ok(b"01095011015300031714070410AB-123\x1d2142".to_vec()));
+        // no separator after AIs with predefined length, even if their value is variable
+        assert_eq!(element_string(&[("3103", "000189"), ("21", "1")]), Ok(b"3103000189211".to_vec()));
+
+        assert_eq!(element_string(&[("01", "09501101530004")]), Err(QrError::InvalidGs1Value("01".to_string())));
+        assert_eq!(element_string(&[("10", "AB 123")]), Err(QrError::InvalidGs1Value("10".to_string())));
+        assert_eq!(element_string(&[("11", "1407")]), Err(QrError::InvalidGs1Value("11".to_string())));
+        assert_eq!(element_string(&[("23", "x")]), Err(QrError::UnknownApplicationIdentifier("23".to_string())));
+        assert_eq!(element_string(&[("310", "000189")]), Err(QrError::UnknownApplicationIdentifier("310".to_string())));
+        // dates may use day 00, but no month 13
+        assert!(element_string(&[("17", "140700")]).is_ok());
+        assert_eq!(element_string(&[("17", "141304")]), Err(QrError::InvalidGs1Value("17".to_string())));
+        // days beyond the end of the month, February 29 only in leap years
+        assert_eq!(element_string(&[("17", "140231")]), Err(QrError::InvalidGs1Value("17".to_string())));
+        assert_eq!(element_string(&[("17", "140431")]), Err(QrError::InvalidGs1Value("17".to_string())));
+        assert_eq!(element_string(&[("17", "150229")]), Err(QrError::InvalidGs1Value("17".to_string())));
+        assert!(element_string(&[("17", "160229")]).is_ok());
+        assert!(element_string(&[("17", "000229")]).is_ok());
+        assert!(element_string(&[("17", "140430")]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_element_string() {
+        let elements = [("01", "09501101530003"), ("17", "140704"), ("10", "AB-123"), ("21", "42"), ("3103", "000189")];
+        let parsed = parse_element_string(&element_string(&elements).unwrap()).unwrap();
+        assert_eq!(parsed.iter().map(|(ai, value)| (ai.as_str(), value.as_str())).collect::<Vec<_>>(), elements);
+        // superfluous separator after an element with predefined length
+        assert_eq!(parse_element_string(b"0109501101530003\x1d2142"),
+                   Ok(vec![("01".to_string(), "09501101530003".to_string()), ("21".to_string(), "42".to_string())]));
+        assert_eq!(parse_element_string(b""), Ok(vec![]));
+
+        assert_eq!(parse_element_string(b"0109501101530004"), Err(QrError::InvalidGs1Value("01".to_string())));
+        assert_eq!(parse_element_string(b"01095011"), Err(QrError::InvalidGs1Value("01".to_string())));
+        assert_eq!(parse_element_string(b"17141304"), Err(QrError::InvalidGs1Value("17".to_string())));
+        assert_eq!(parse_element_string(b"10AB\x1d\x1d21"), Err(QrError::UnknownApplicationIdentifier("\u{1d}21".to_string())));
+        assert_eq!(parse_element_string(b"2342"), Err(QrError::UnknownApplicationIdentifier("2342".to_string())));
+    }
+}
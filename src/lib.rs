@@ -1,55 +1,1010 @@
-use image;
-
-pub use config::{ECCLevel, Encoding, Size};
+//! The crate is organized in layers, each of which can be used on its own:
+//!   - core: `bitcoding`, `reedsolomon`, `blocks` and `tables` turn content bytes into codewords
+//!   - matrix: `matrix` and `serialization` place codewords into a module matrix
+//!   - render: `render` turns a module matrix into an image
+//!
+//! `plan` combines the layers into a two-step API, which decides the symbol first and renders it later.
+//!
+//! Encoding and rendering are only available with the `encode` feature (enabled by default).
+//! The tables, the symbol layout and the masks are always available, as they are needed
+//! for reading symbols as well. The `decode` feature is reserved for the decoder;
+//! `corruption` generates damaged symbols for testing readers.
+//! The `zlib` feature adds compressed Base45 payloads to `base45` and compressed contents (`compression`).
+pub use config::{Charset, ECCLevel, EncodeOptions, Encoding, EncodingPolicy, IntoQrData, MicroPolicy, Size, SizeSelection, SymbolConfig};
+pub use error::{QrError, QrResult};
+pub use matrix::{EncodeInfo, EncodeWarning, Matrix, Module, QrCode, ValidationIssue};
+pub use serialization::{inspect_matrix, SymbolInfo};
+#[cfg(feature = "encode")]
+pub use bitcoding::{analyze, ContentReport};
+#[cfg(feature = "encode")]
+pub use plan::{plan, EncodingPlan, RenderStyle, Rendered};
+pub use serialization::masking::{compute_penalty_map, find_finder_like_patterns, FinderLikePattern, MaskSelection, MaskTieBreak, PenaltyMap};
 
 #[macro_use]
 extern crate lazy_static;
 
-//pub fn create_qr_code(content: &str)
-
+pub mod base45;
+pub mod blocks;
+pub mod checksum;
+#[cfg(feature = "zlib")]
+pub mod compression;
 pub mod config;
+pub mod corruption;
+pub mod error;
+pub mod gs1;
 pub mod serialization;
+#[cfg(feature = "encode")]
 pub mod reedsolomon;
+#[cfg(feature = "encode")]
 pub mod bitcoding;
 pub mod tables;
+pub mod matrix;
+#[cfg(feature = "encode")]
+pub mod render;
+#[cfg(feature = "encode")]
+pub mod plan;
 
 
+#[cfg(feature = "encode")]
+use {
+    std::borrow::Cow,
+    std::io::{self, Read},
+    std::ops::Range,
+    bitcoding::*,
+    reedsolomon::*,
+    serialization::*,
+    serialization::masking::{apply_custom_mask, apply_given_mask, select_best_mask},
+    tables::{check_data_codewords, try_lookup_capacity}
+};
 
-use bitcoding::*;
-use reedsolomon::*;
-use serialization::*;
-use serialization::masking::apply_best_mask;
 
+/// Core layer: encode the content into the data and ecc codewords of a symbol,
+/// already interleaved in the order in which they are placed into the symbol.
+#[cfg(feature = "encode")]
+pub fn encode_codewords(content: &[u8],
+                        size: Size,
+                        level: ECCLevel,
+                        encoding: Encoding) -> QrResult<(Vec<u8>, Vec<u8>)> {
+    let mut encoder = QrBitRecorder::new();
+    encode_data_segment(&mut encoder, content, encoding, size)?;
+    finish_codewords(&mut encoder, size, level).map(|encoded| (encoded.data_bytes, encoded.ecc_bytes))
+}
 
+/// Core layer: same as encode_codewords, but encode the content as a sequence of segments,
+/// e.g. as computed by bitcoding::optimal_segmentation.
+#[cfg(feature = "encode")]
+pub fn encode_codewords_segmented(content: &[u8],
+                                  size: Size,
+                                  level: ECCLevel,
+                                  segments: &[(Encoding, Range<usize>)]) -> QrResult<(Vec<u8>, Vec<u8>)> {
+    let mut encoder = QrBitRecorder::new();
+    encode_segments(&mut encoder, content, segments, size)?;
+    finish_codewords(&mut encoder, size, level).map(|encoded| (encoded.data_bytes, encoded.ecc_bytes))
+}
 
-pub fn create_qr_code(content: &[u8],
-                      size: Size,
-                      level: ECCLevel,
-                      encoding: Option<Encoding>) -> image::GrayImage {
+/// codewords of encoded content, together with the usage of the capacity
+#[cfg(feature = "encode")]
+struct Encoded {
+    data_bytes: Vec<u8>,
+    ecc_bytes: Vec<u8>,
+    info: EncodeInfo
+}
 
-    // TODO: guess best encoding
+#[cfg(feature = "encode")]
+impl Encoded {
+    /// place the codewords into a symbol, see place_codewords
+    fn place(self, size: Size, level: ECCLevel) -> QrResult<QrCode> {
+        let code = place_codewords(&self.data_bytes, &self.ecc_bytes, size, level)?;
+        Ok(QrCode { encode_info: Some(self.info), ..code })
+    }
 
-    // encode some data
-    let (data_bytes, ecc_bytes) = {
-        let mut encoder = QrBitRecorder::new();
-        encode_data_segment(&mut encoder, content, encoding.unwrap(), size);
-        let data_content = finalize_bitstream(&mut encoder, size, level);
-        construct_codewords(&data_content, size, level)  // compute ecc bytes + interleave
-    };
+    /// place the codewords into a symbol with the given mask, see place_codewords_with_mask
+    fn place_with_mask(self, size: Size, level: ECCLevel, mask: u8) -> QrResult<QrCode> {
+        let code = place_codewords_with_mask(&self.data_bytes, &self.ecc_bytes, size, level, mask)?;
+        Ok(QrCode { encode_info: Some(self.info), ..code })
+    }
+}
+
+/// finalize the recorded bitstream and compute the codewords from it
+#[cfg(feature = "encode")]
+fn finish_codewords(encoder: &mut QrBitRecorder, size: Size, level: ECCLevel) -> QrResult<Encoded> {
+    let (data_content, info) = finalize_bitstream_with_info(encoder, size, level)?;
+    let (data_bytes, ecc_bytes) = construct_codewords(&data_content, size, level)?;  // compute ecc bytes + interleave
+    Ok(Encoded { data_bytes, ecc_bytes, info })
+}
+
+/// check that the number of codewords matches the capacity of the symbol
+#[cfg(feature = "encode")]
+fn check_codewords(data_bytes: &[u8], ecc_bytes: &[u8], size: Size, level: ECCLevel) -> QrResult<()> {
+    check_data_codewords(data_bytes, size, level)?;
+    let expected = try_lookup_capacity(size, level)?.ecc_words() as usize;
+    if expected != ecc_bytes.len() {
+        return Err(QrError::WrongNumberOfCodewords { expected, actual: ecc_bytes.len() });
+    }
+    Ok(())
+}
+
+/// Matrix layer: place the interleaved data and ecc codewords into a symbol, choose
+/// the best mask and add the format and version information.
+#[cfg(feature = "encode")]
+pub fn place_codewords(data_bytes: &[u8],
+                       ecc_bytes: &[u8],
+                       size: Size,
+                       level: ECCLevel) -> QrResult<QrCode> {
+    place_codewords_with_tie_break(data_bytes, ecc_bytes, size, level, MaskTieBreak::default_for(size))
+}
+
+/// Matrix layer: same as place_codewords, but with the given rule to choose between
+/// masks with the same best score.
+#[cfg(feature = "encode")]
+pub fn place_codewords_with_tie_break(data_bytes: &[u8],
+                                      ecc_bytes: &[u8],
+                                      size: Size,
+                                      level: ECCLevel,
+                                      tie_break: MaskTieBreak) -> QrResult<QrCode> {
+    check_codewords(data_bytes, ecc_bytes, size, level)?;
 
     // create a canvas
     let mut canvas = create_qr_canvas(size);
-    insert_data_payload(&mut canvas, size, &data_bytes, &ecc_bytes);
+    insert_data_payload(&mut canvas, size, data_bytes, ecc_bytes)?;
 
     // determine best mask and apply it
-    let (mask_code, mut masked_symbol) = apply_best_mask(&canvas, size);
+    let (mask_code, selection) = select_best_mask(&canvas, size, tie_break)?;
+    let masked_symbol = apply_given_mask(&canvas, mask_code, size)?;
+
+    finish_symbol(masked_symbol, size, level, mask_code, Some(selection))
+}
+
+/// Matrix layer: same as place_codewords, but apply the given mask pattern instead of
+/// choosing the best one. Useful to produce deterministic test fixtures.
+#[cfg(feature = "encode")]
+pub fn place_codewords_with_mask(data_bytes: &[u8],
+                                 ecc_bytes: &[u8],
+                                 size: Size,
+                                 level: ECCLevel,
+                                 mask: u8) -> QrResult<QrCode> {
+    check_codewords(data_bytes, ecc_bytes, size, level)?;
+
+    // create a canvas
+    let mut canvas = create_qr_canvas(size);
+    insert_data_payload(&mut canvas, size, data_bytes, ecc_bytes)?;
+
+    let masked_symbol = apply_given_mask(&canvas, mask, size)?;
+
+    finish_symbol(masked_symbol, size, level, mask, None)
+}
+
+/// Matrix layer: same as place_codewords_with_mask, but mask the encoding region with the given
+/// condition instead of a standard pattern, see masking::apply_custom_mask. The format information
+/// names the given standard pattern index, so the symbol does not scan unless the condition happens
+/// to be that pattern. Meant for research on masks only.
+#[cfg(feature = "encode")]
+pub fn place_codewords_with_custom_mask<F: Fn(i32, i32) -> bool>(data_bytes: &[u8],
+                                                                  ecc_bytes: &[u8],
+                                                                  size: Size,
+                                                                  level: ECCLevel,
+                                                                  condition: F,
+                                                                  format_mask: u8) -> QrResult<QrCode> {
+    check_codewords(data_bytes, ecc_bytes, size, level)?;
+
+    // create a canvas
+    let mut canvas = create_qr_canvas(size);
+    insert_data_payload(&mut canvas, size, data_bytes, ecc_bytes)?;
+
+    apply_custom_mask(&mut canvas, &create_qr_canvas(size), condition);
+
+    finish_symbol(canvas, size, level, format_mask, None)
+}
+
+/// Matrix layer: build a symbol of any family, see serialization::SymbolFamily. The data codewords
+/// must fill the capacity at the given level; the ecc codewords are computed from the block
+/// structure, and all codewords are placed into the encoding region of the family's layout and
+/// masked with the given condition, as in place_codewords_with_custom_mask. Format and version
+/// information, if the family has any, are left to the caller.
+#[cfg(feature = "encode")]
+pub fn create_custom_symbol<S: SymbolFamily, F: Fn(i32, i32) -> bool>(symbol: &S,
+                                                                    data_codewords: &[u8],
+                                                                    level: ECCLevel,
+                                                                    condition: F) -> QrResult<Matrix> {
+    let capacity = symbol.capacity(level)?;
+    let (data_bytes, ecc_bytes) = construct_codewords_with_capacity(data_codewords, &capacity)?;
+
+    let layout = symbol.layout();
+    let mut canvas = create_custom_canvas(&layout);
+    insert_custom_data_payload(&mut canvas, &layout, &data_bytes, &ecc_bytes)?;
+    apply_custom_mask(&mut canvas, &create_custom_canvas(&layout), condition);
+    Ok(canvas)
+}
+
+/// Matrix layer: reconstruct a symbol from the complete sequence of codewords in placement
+/// order, i.e. the interleaved data codewords followed by the interleaved ecc codewords, e.g. as
+/// captured from another encoder. The codewords are placed as given, without any check of their
+/// content. If no mask is given, the best one is chosen.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_from_codewords(codewords: &[u8],
+                                       size: Size,
+                                       level: ECCLevel,
+                                       mask: Option<u8>) -> QrResult<QrCode> {
+    let capacity = try_lookup_capacity(size, level)?;
+    if codewords.len() != capacity.codewords() as usize {
+        return Err(QrError::WrongNumberOfCodewords { expected: capacity.codewords() as usize, actual: codewords.len() });
+    }
 
+    let (data_bytes, ecc_bytes) = codewords.split_at(capacity.data_codewords() as usize);
+    match mask {
+        Some(mask) => place_codewords_with_mask(data_bytes, ecc_bytes, size, level, mask),
+        None => place_codewords(data_bytes, ecc_bytes, size, level)
+    }
+}
+
+/// Matrix layer: create a symbol from caller-supplied data codewords in block order, i.e. before
+/// interleaving, e.g. for testing scanners with unusual bitstreams. Only the error correction,
+/// the interleaving, the placement, the masking and the format information are done by the crate;
+/// the content of the codewords is not checked. For M1 and M3 symbols the low nibble of the last
+/// codeword must be zero. If no mask is given, the best one is chosen.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_from_data_codewords(data_codewords: &[u8],
+                                            size: Size,
+                                            level: ECCLevel,
+                                            mask: Option<u8>) -> QrResult<QrCode> {
+    check_data_codewords(data_codewords, size, level)?;
+    let (data_bytes, ecc_bytes) = construct_codewords(data_codewords, size, level)?;
+    match mask {
+        Some(mask) => place_codewords_with_mask(&data_bytes, &ecc_bytes, size, level, mask),
+        None => place_codewords(&data_bytes, &ecc_bytes, size, level)
+    }
+}
+
+/// Create an image of a symbol reconstructed from its codewords, see create_qr_matrix_from_codewords
+#[cfg(feature = "encode")]
+pub fn create_qr_code_from_codewords(codewords: &[u8],
+                                     size: Size,
+                                     level: ECCLevel,
+                                     mask: Option<u8>) -> QrResult<image::GrayImage> {
+    Ok(render::to_gray_image(&create_qr_matrix_from_codewords(codewords, size, level, mask)?))
+}
+
+/// add format and version information to a masked symbol
+#[cfg(feature = "encode")]
+fn finish_symbol(mut masked_symbol: Matrix,
+                 size: Size,
+                 level: ECCLevel,
+                 mask_code: u8,
+                 mask_selection: Option<MaskSelection>) -> QrResult<QrCode> {
     // apply format bits
-    insert_format_info(&mut masked_symbol, size, level, mask_code);
+    insert_format_info(&mut masked_symbol, size, level, mask_code)?;
 
     // apply version info
-    insert_version_info(&mut masked_symbol, size);
+    insert_version_info(&mut masked_symbol, size)?;
+    debug_assert!(masked_symbol.is_complete(), "Not all reserved modules were filled!");
+
+    Ok(QrCode {
+        size,
+        level,
+        mask: mask_code,
+        mask_selection,
+        encode_info: None,
+        matrix: masked_symbol
+    })
+}
+
+/// headers preceding the encoded content, in this order
+#[cfg(feature = "encode")]
+#[derive(Clone,Copy,Default)]
+struct Headers {
+    structured_append: Option<StructuredAppend>,
+    eci: Option<u32>,
+    fnc1: Option<Fnc1>
+}
+
+/// encode the content into codewords, preceded by the given headers
+#[cfg(feature = "encode")]
+fn encode_content(content: &[u8],
+                  size: Size,
+                  level: ECCLevel,
+                  encoding: Option<Encoding>,
+                  headers: Headers) -> QrResult<Encoded> {
+    SymbolConfig::new(size, level).validate(encoding.as_slice())?;
+    let mut encoder = QrBitRecorder::new();
+    write_headers(&mut encoder, size, headers)?;
+
+    let segments = match encoding {
+        Some(encoding) => {
+            encode_data_segment(&mut encoder, content, encoding, size)?;
+            vec![(encoding, 0..content.len())]
+        },
+        None => {
+            let segments = optimal_segmentation(content, size);
+            encode_segments(&mut encoder, content, &segments, size)?;
+            segments
+        }
+    };
+    let mut encoded = finish_codewords(&mut encoder, size, level)?;
+    encoded.info.segments = segments;
+
+    // forcing the byte mode on digits is the most common cause of needlessly large symbols
+    encoded.info.warnings.extend(encoding
+        .and_then(|forced| denser_encoding(content, forced, size)
+            .map(|possible| EncodeWarning::LessDenseEncoding { forced, possible })));
+    add_scannability_warnings(&mut encoded.info, content, size, headers);
+    Ok(encoded)
+}
+
+/// same as encode_content, but with the content split into the given segments
+#[cfg(feature = "encode")]
+fn encode_content_segmented(content: &[u8],
+                            size: Size,
+                            level: ECCLevel,
+                            segments: &[(Encoding, Range<usize>)],
+                            headers: Headers) -> QrResult<Encoded> {
+    let encodings: Vec<Encoding> = segments.iter().map(|(ec, _)| *ec).collect();
+    SymbolConfig::new(size, level).validate(&encodings)?;
+    let mut encoder = QrBitRecorder::new();
+    write_headers(&mut encoder, size, headers)?;
+    encode_segments(&mut encoder, content, segments, size)?;
+    let mut encoded = finish_codewords(&mut encoder, size, level)?;
+    // report the segments relative to the data carried by this symbol
+    let mut offset = 0;
+    encoded.info.segments = segments.iter()
+        .map(|(encoding, range)| {
+            offset += range.len();
+            (*encoding, offset - range.len()..offset)
+        })
+        .collect();
+    let data: Vec<u8> = segments.iter().flat_map(|(_, range)| content[range.clone()].iter().copied()).collect();
+    add_scannability_warnings(&mut encoded.info, &data, size, headers);
+    Ok(encoded)
+}
+
+/// add the warnings which do not depend on how the content was encoded
+#[cfg(feature = "encode")]
+fn add_scannability_warnings(info: &mut EncodeInfo, data: &[u8], size: Size, headers: Headers) {
+    if info.data_bits * 10 > info.capacity_bits * 9 {
+        info.warnings.push(EncodeWarning::NearCapacity);
+    }
+    if size.is_micro() {
+        info.warnings.push(EncodeWarning::MicroSymbol(size));
+    }
+    let in_bytes = info.segments.iter()
+        .any(|(encoding, range)| *encoding == Encoding::Bytes && !data[range.clone()].is_ascii());
+    if headers.eci.is_none() && in_bytes && std::str::from_utf8(data).is_ok() {
+        info.warnings.push(EncodeWarning::Utf8WithoutEci);
+    }
+}
+
+/// write the headers preceding the content
+#[cfg(feature = "encode")]
+fn write_headers(encoder: &mut QrBitRecorder, size: Size, headers: Headers) -> QrResult<()> {
+    if let Some(header) = headers.structured_append {
+        if size.is_micro() {
+            return Err(QrError::StructuredAppendNotSupported(size));
+        }
+        write_structured_append_header(encoder, header)?;
+    }
+    if let Some(assignment) = headers.eci {
+        if size.is_micro() {
+            return Err(QrError::EciNotSupported(size));
+        }
+        write_eci_header(encoder, assignment)?;
+    }
+    if let Some(fnc1) = headers.fnc1 {
+        if size.is_micro() {
+            return Err(QrError::Fnc1NotSupported(size));
+        }
+        write_fnc1_header(encoder, fnc1);
+    }
+    Ok(())
+}
+
+/// encode the content into the first of the given sizes it fits into. Micro sizes which do not
+/// support the level, the encoding or the headers are skipped. If it fits into none of them,
+/// the error of the last size is returned.
+#[cfg(feature = "encode")]
+fn encode_content_smallest(content: &[u8],
+                           sizes: impl Iterator<Item = Size>,
+                           level: ECCLevel,
+                           encoding: Option<Encoding>,
+                           headers: Headers) -> QrResult<(Size, Encoded)> {
+    let mut result = Err(QrError::DataTooLong { bits: 0, capacity: 0, suggestion: None });
+    for size in sizes {
+        result = encode_content(content, size, level, encoding, headers).map(|encoded| (size, encoded));
+        match result {
+            Err(QrError::DataTooLong { .. }) => continue,
+            // micro symbols do not support all levels, encodings and headers
+            Err(QrError::InvalidSymbolConfig(..)) |
+            Err(QrError::UnsupportedEncoding(..)) |
+            Err(QrError::EciNotSupported(_)) if size.is_micro() => continue,
+            _ => break
+        }
+    }
+    result
+}
+
+/// same as encode_content, but if the content does not fit, suggest the smallest size that fits
+/// it at the given level in the error. If there is none, the highest lower level is tried.
+/// Micro sizes are only suggested if a micro size was requested.
+#[cfg(feature = "encode")]
+fn encode_content_or_suggest(content: &[u8],
+                             size: Size,
+                             level: ECCLevel,
+                             encoding: Option<Encoding>,
+                             headers: Headers) -> QrResult<Encoded> {
+    encode_content(content, size, level, encoding, headers).map_err(|err| match err {
+        QrError::DataTooLong { bits, capacity, .. } => {
+            let sizes: Vec<Size> = Size::all().filter(|s| size.is_micro() || !s.is_micro()).collect();
+            let suggestion = [ECCLevel::H, ECCLevel::Q, ECCLevel::M, ECCLevel::L].iter()
+                .filter(|&&l| l <= level)
+                .find_map(|&l| encode_content_smallest(content, sizes.iter().copied(), l, encoding, headers)
+                    .ok()
+                    .map(|(size, _)| SymbolConfig::new(size, l)));
+            QrError::DataTooLong { bits, capacity, suggestion }
+        },
+        err => err
+    })
+}
+
+/// Create the finished module matrix of a symbol for the given content.
+/// If no encoding is given, the content is split into the segments which need the fewest bits.
+/// If an ECI assignment number is given, the content is preceded by an ECI header, changing
+/// the interpretation of the content (not available for micro symbols).
+/// Uses the latest encoding policy, see create_qr_matrix_with_policy.
+/// The content can be given as bytes or as text, see content_with_eci.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix<'a>(content: impl IntoQrData<'a>,
+                            size: Size,
+                            level: ECCLevel,
+                            encoding: Option<Encoding>,
+                            eci: Option<u32>) -> QrResult<QrCode> {
+    let (content, eci) = content_with_eci(content, eci, !size.is_micro());
+    create_qr_matrix_with_policy(&content, size, level, encoding, eci, EncodingPolicy::LATEST)
+}
+
+/// Without an ECI assignment, texts are converted to ISO 8859-1 if possible, which scanners
+/// assume for byte mode; other texts are encoded as UTF-8 with ECI 26 if the symbol supports
+/// it, and as plain UTF-8 in micro symbols. With an ECI assignment, texts are always UTF-8.
+#[cfg(feature = "encode")]
+fn content_with_eci<'a>(content: impl IntoQrData<'a>, eci: Option<u32>, eci_allowed: bool) -> (Cow<'a, [u8]>, Option<u32>) {
+    match eci {
+        Some(eci) => (content.into_qr_data(), Some(eci)),
+        None => {
+            let (content, implied) = content.into_qr_data_with_eci();
+            (content, implied.filter(|_| eci_allowed))
+        }
+    }
+}
+
+/// Same as create_qr_matrix, but with a pinned encoding policy. The result for a given
+/// policy never changes, so symbols can be reproduced exactly with later releases.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_with_policy(content: &[u8],
+                                    size: Size,
+                                    level: ECCLevel,
+                                    encoding: Option<Encoding>,
+                                    eci: Option<u32>,
+                                    policy: EncodingPolicy) -> QrResult<QrCode> {
+    match policy {
+        EncodingPolicy::V1 => {
+            // encode some data and place it
+            encode_content_or_suggest(content, size, level, encoding, Headers { eci, ..Headers::default() })?.place(size, level)
+        }
+    }
+}
+
+/// Return the sizes the selection options allow, from smallest to largest preference
+#[cfg(feature = "encode")]
+fn candidate_sizes(selection: SizeSelection) -> QrResult<Vec<Size>> {
+    for &version in [selection.min_version, selection.max_version].iter() {
+        if !Size::Standard(version).is_valid() {
+            return Err(QrError::InvalidSize(Size::Standard(version)));
+        }
+    }
+    if selection.min_version > selection.max_version {
+        return Err(QrError::InvalidSize(Size::Standard(selection.min_version)));
+    }
+
+    let micro_sizes = (1..=4).map(Size::Micro);
+    let standard_sizes = (selection.min_version..=selection.max_version).map(Size::Standard);
+    Ok(match selection.micro {
+        MicroPolicy::StandardOnly => standard_sizes.collect(),
+        MicroPolicy::PreferMicro => micro_sizes.chain(standard_sizes).collect(),
+        MicroPolicy::MicroOnly => micro_sizes.collect()
+    })
+}
+
+/// Same as create_qr_matrix, but choose the smallest size the content fits into at the given
+/// ECC level. The selection options restrict the standard versions and whether micro sizes are
+/// considered; micro sizes are skipped if they do not support the level, encoding or ECI header.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_auto(content: &[u8],
+                             level: ECCLevel,
+                             encoding: Option<Encoding>,
+                             eci: Option<u32>,
+                             selection: SizeSelection) -> QrResult<QrCode> {
+    let (size, level, encoded) = encode_content_auto(content, level, encoding, Headers { eci, ..Headers::default() }, selection)?;
+    encoded.place(size, level)
+}
+
+/// encode the content into the smallest size the selection options allow and boost the level
+/// if requested, see create_qr_matrix_auto
+#[cfg(feature = "encode")]
+fn encode_content_auto(content: &[u8],
+                       level: ECCLevel,
+                       encoding: Option<Encoding>,
+                       headers: Headers,
+                       selection: SizeSelection) -> QrResult<(Size, ECCLevel, Encoded)> {
+    let sizes = candidate_sizes(selection)?;
+    let (size, mut encoded) = encode_content_smallest(content, sizes.into_iter(), level, encoding, headers)?;
+
+    let mut boosted = level;
+    if selection.boost_ecc {
+        for &higher in [ECCLevel::M, ECCLevel::Q, ECCLevel::H].iter().filter(|&&l| l > level) {
+            match encode_content(content, size, higher, encoding, headers) {
+                Ok(higher_encoded) => {
+                    boosted = higher;
+                    encoded = higher_encoded;
+                },
+                Err(_) => break
+            }
+        }
+    }
+    Ok((size, boosted, encoded))
+}
+
+/// Same as create_qr_matrix_auto, but reserve a centered square for a logo, whose edge covers
+/// the given fraction of the symbol's edge. The version and, if needed, the ECC level are raised
+/// until the modules under the logo can be restored by the error correction. The ECC level is
+/// never lowered below the given one, and boost_ecc in the selection options is ignored.
+/// The area to draw the logo into is returned by serialization::centered_area.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_with_logo(content: &[u8],
+                                  level: ECCLevel,
+                                  encoding: Option<Encoding>,
+                                  eci: Option<u32>,
+                                  selection: SizeSelection,
+                                  logo_ratio: f64) -> QrResult<QrCode> {
+    let headers = Headers { eci, ..Headers::default() };
+    // the error of the last rejected candidate, reported if none fits
+    let mut last_error = None;
+    for size in candidate_sizes(selection)? {
+        for candidate in ECCLevel::all().filter(|&l| l >= level) {
+            match area_is_correctable(size, candidate, centered_area(size, logo_ratio)) {
+                Ok(true) => (),
+                Ok(false) => {
+                    last_error = Some(QrError::LogoTooLarge);
+                    continue;
+                },
+                // micro symbols do not support all levels
+                Err(err) => {
+                    last_error = Some(err);
+                    continue;
+                }
+            }
+            match encode_content(content, size, candidate, encoding, headers) {
+                Ok(encoded) => return encoded.place(size, candidate),
+                Err(err @ QrError::UnsupportedEncoding(..)) |
+                Err(err @ QrError::EciNotSupported(_)) if size.is_micro() => {
+                    last_error = Some(err);
+                    break;
+                },
+                // a higher level only leaves less room for the data
+                Err(err @ QrError::DataTooLong { .. }) => {
+                    last_error = Some(err);
+                    break;
+                },
+                Err(err) => return Err(err)
+            }
+        }
+    }
+    // there is always at least one candidate size and level
+    Err(last_error.expect("No candidate was tried"))
+}
+
+/// Same as create_qr_matrix, but apply the given mask pattern (0-7 for standard symbols,
+/// 0-3 for micro symbols) instead of choosing the best one, for reproducible output.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_with_mask(content: &[u8],
+                                  size: Size,
+                                  level: ECCLevel,
+                                  encoding: Option<Encoding>,
+                                  eci: Option<u32>,
+                                  mask: u8) -> QrResult<QrCode> {
+    encode_content_or_suggest(content, size, level, encoding, Headers { eci, ..Headers::default() })?
+        .place_with_mask(size, level, mask)
+}
+
+/// Create the finished module matrix of a symbol with all settings given as options,
+/// see EncodeOptions. Without a size in the options, the size is chosen automatically.
+#[cfg(feature = "encode")]
+pub fn encode_with_options<'a>(content: impl IntoQrData<'a>, options: EncodeOptions) -> QrResult<QrCode> {
+    let (mut content, eci) = content_with_eci(content, options.eci, eci_allowed(options));
+    let mut uppercased = 0;
+    if options.uppercase_alphanumeric && options.encoding == Some(Encoding::Alphanumeric) {
+        uppercased = content.iter().filter(|c| c.is_ascii_lowercase()).count();
+        if uppercased > 0 {
+            content = Cow::Owned(content.to_ascii_uppercase());
+        }
+    }
+
+    let headers = Headers { eci, ..Headers::default() };
+    let (size, level, mut encoded) = match (options.size, options.policy) {
+        (Some(size), EncodingPolicy::V1) =>
+            (size, options.level, encode_content_or_suggest(&content, size, options.level, options.encoding, headers)?),
+        (None, EncodingPolicy::V1) =>
+            encode_content_auto(&content, options.level, options.encoding, headers, options.selection)?
+    };
+    if uppercased > 0 {
+        encoded.info.warnings.push(EncodeWarning::Uppercased(uppercased));
+    }
+    match options.mask {
+        Some(mask) => encoded.place_with_mask(size, level, mask),
+        None => encoded.place(size, level)
+    }
+}
+
+// whether the symbols allowed by the options can have an ECI header
+#[cfg(feature = "encode")]
+fn eci_allowed(options: EncodeOptions) -> bool {
+    match options.size {
+        Some(size) => !size.is_micro(),
+        None => options.selection.micro != MicroPolicy::MicroOnly
+    }
+}
+
+/// Check the content against the constraints given as options before encoding it, e.g. to
+/// validate input fields while they are edited. All invalid characters of a forced encoding
+/// are reported; the length is only checked if all characters are valid. Without issues,
+/// encode_with_options succeeds with the same content and options.
+#[cfg(feature = "encode")]
+pub fn validate<'a>(content: impl IntoQrData<'a>, options: EncodeOptions) -> Vec<ValidationIssue> {
+    let (content, eci) = content_with_eci(content, options.eci, eci_allowed(options));
+    let mut issues = Vec::new();
+    if content.is_empty() {
+        issues.push(ValidationIssue::Empty);
+    }
+
+    if let Some(encoding) = options.encoding {
+        let checked = if options.uppercase_alphanumeric && encoding == Encoding::Alphanumeric {
+            Cow::Owned(content.to_ascii_uppercase())
+        } else {
+            Cow::Borrowed(&content[..])
+        };
+        let step = if encoding == Encoding::Kanji { 2 } else { 1 };
+        let mut positions = Vec::new();
+        let mut start = 0;
+        while let Err(QrError::InvalidCharacter { position, .. }) = validate_content(&checked[start..], encoding) {
+            positions.push(start + position);
+            start = (start + position + step).min(checked.len());
+        }
+        if !positions.is_empty() {
+            issues.push(ValidationIssue::InvalidCharacters { encoding, positions });
+            return issues;
+        }
+    }
+
+    match encode_with_options(&content[..], EncodeOptions { eci, ..options }) {
+        Ok(_) => (),
+        Err(QrError::DataTooLong { bits, capacity, .. }) => issues.push(ValidationIssue::TooLong { bits, capacity }),
+        Err(error) => issues.push(ValidationIssue::Unusable(error))
+    }
+    issues
+}
+
+/// Create an image of a symbol with all settings given as options, with one pixel per module
+/// and the quiet region given in the options.
+#[cfg(feature = "encode")]
+pub fn encode_image_with_options<'a>(content: impl IntoQrData<'a>, options: EncodeOptions) -> QrResult<image::GrayImage> {
+    let code = encode_with_options(content, options)?;
+    let quiet_zone = options.quiet_zone.unwrap_or_else(|| code.quiet_zone());
+    Ok(render::render_matrix(&code.matrix, quiet_zone))
+}
+
+/// Like encode_image_with_options, but never fails: if the content can not be encoded, a
+/// placeholder image with the given edge length is returned together with the error, so
+/// layout pipelines can go on while the failure stays visible, see render::render_placeholder.
+#[cfg(feature = "encode")]
+pub fn encode_image_or_placeholder<'a>(content: impl IntoQrData<'a>,
+                                       options: EncodeOptions,
+                                       placeholder_edge: u32) -> (image::GrayImage, Option<QrError>) {
+    match encode_image_with_options(content, options) {
+        Ok(img) => (img, None),
+        Err(e) => (render::render_placeholder(placeholder_edge), Some(e))
+    }
+}
+
+/// Like encode_with_options, but deflate the content into a compressed payload (see the compression
+/// module) if that needs a smaller symbol, or if the content does not fit otherwise. Readers get
+/// the original content back with compression::decompress.
+#[cfg(all(feature = "encode", feature = "zlib"))]
+pub fn encode_compressed(content: &[u8], options: EncodeOptions) -> QrResult<QrCode> {
+    let plain = encode_with_options(content, options);
+    if let Err(ref error) = plain {
+        if !matches!(error, QrError::DataTooLong { .. }) {
+            return plain;
+        }
+    }
+
+    // the payload is binary, so neither ECI nor a forced encoding apply
+    let payload = compression::compress(content);
+    let options = EncodeOptions { encoding: None, eci: None, uppercase_alphanumeric: false, ..options };
+    match (plain, encode_with_options(&payload[..], options)) {
+        (Ok(plain), Ok(compressed)) if compressed.size.dimensions() < plain.size.dimensions() => Ok(compressed),
+        (Err(_), Ok(compressed)) => Ok(compressed),
+        (plain, _) => plain
+    }
+}
+
+/// Create an image of a symbol for the given content, with one pixel per module
+/// and including the quiet region.
+#[cfg(feature = "encode")]
+pub fn create_qr_code<'a>(content: impl IntoQrData<'a>,
+                          size: Size,
+                          level: ECCLevel,
+                          encoding: Option<Encoding>,
+                          eci: Option<u32>) -> QrResult<image::GrayImage> {
+    Ok(render::to_gray_image(&create_qr_matrix(content, size, level, encoding, eci)?))
+}
+
+/// Create the finished module matrix of a symbol for the content of a reader, e.g. a file.
+/// At most as many bytes are read as the symbol could possibly hold; if the reader has more,
+/// DataTooLong is returned, with the bits counted only for the part that was read.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_from_reader<R: io::Read>(reader: R,
+                                                 size: Size,
+                                                 level: ECCLevel,
+                                                 encoding: Option<Encoding>,
+                                                 eci: Option<u32>) -> QrResult<QrCode> {
+    let capacity = try_lookup_capacity(size, level)?;
+    // the numeric mode holds the most characters, kanji characters take two bytes each
+    let max_len = match encoding {
+        Some(Encoding::Kanji) => 2 * capacity[Encoding::Kanji],
+        Some(encoding) => capacity[encoding],
+        None => capacity[Encoding::Numeric]
+    };
+
+    let mut content = Vec::with_capacity(max_len as usize);
+    reader.take(max_len as u64 + 1).read_to_end(&mut content).map_err(|e| QrError::ReadFailed(e.kind()))?;
+    encode_content(&content, size, level, encoding, Headers { eci, ..Headers::default() })?.place(size, level)
+}
+
+/// Create the finished module matrix of a symbol for a text. The text is encoded as UTF-8
+/// in byte mode, preceded by the ECI header for UTF-8 (config::ECI_UTF8), which most
+/// scanners understand. The smallest standard size that fits the text is chosen.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_utf8(text: &str, level: ECCLevel) -> QrResult<QrCode> {
+    let (size, encoded) =
+        encode_content_smallest(text.as_bytes(), (1..=40).map(Size::Standard), level, Some(Encoding::Bytes), Headers { eci: Some(config::ECI_UTF8), ..Headers::default() })?;
+    encoded.place(size, level)
+}
+
+/// Create an image of a symbol for a text, see create_qr_matrix_utf8
+#[cfg(feature = "encode")]
+pub fn create_qr_code_utf8(text: &str, level: ECCLevel) -> QrResult<image::GrayImage> {
+    Ok(render::to_gray_image(&create_qr_matrix_utf8(text, level)?))
+}
+
+/// Create the finished module matrix of a symbol for a Japanese text, which is converted to
+/// Shift JIS and encoded in kanji mode. Fails if the text contains characters that are not
+/// available in kanji mode.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_kanji(text: &str, size: Size, level: ECCLevel) -> QrResult<QrCode> {
+    create_qr_matrix(&shift_jis_from_str(text)?, size, level, Some(Encoding::Kanji), None)
+}
+
+/// Create the finished module matrix of a symbol in FNC1 mode, for data formatted according to
+/// the GS1 (FNC1 in first position) or an AIM application standard (FNC1 in second position).
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_fnc1(content: &[u8],
+                             size: Size,
+                             level: ECCLevel,
+                             encoding: Option<Encoding>,
+                             fnc1: Fnc1) -> QrResult<QrCode> {
+    encode_content_or_suggest(content, size, level, encoding, Headers { fnc1: Some(fnc1), ..Headers::default() })?.place(size, level)
+}
+
+/// encode a list of segments, each of which may switch the interpretation with its own ECI header
+#[cfg(feature = "encode")]
+fn encode_segment_list(segments: &[Segment], size: Size, level: ECCLevel) -> QrResult<Encoded> {
+    let encodings: Vec<Encoding> = segments.iter().map(|segment| segment.encoding).collect();
+    SymbolConfig::new(size, level).validate(&encodings)?;
+    let mut encoder = QrBitRecorder::new();
+    for segment in segments {
+        segment.write_to(&mut encoder, size)?;
+    }
+    let mut encoded = finish_codewords(&mut encoder, size, level)?;
+    // report the segments relative to the concatenated data
+    let mut offset = 0;
+    encoded.info.segments = segments.iter()
+        .map(|segment| {
+            offset += segment.data.len();
+            (segment.encoding, offset - segment.data.len()..offset)
+        })
+        .collect();
+    let data: Vec<u8> = segments.iter().flat_map(|segment| segment.data.iter().copied()).collect();
+    let eci = segments.iter().find_map(|segment| segment.eci);
+    add_scannability_warnings(&mut encoded.info, &data, size, Headers { eci, ..Headers::default() });
+    Ok(encoded)
+}
+
+/// Create the finished module matrix of a symbol for a list of segments, each of which can carry
+/// its own ECI header to switch the interpretation mid-message, see bitcoding::Segment.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_from_segments(segments: &[Segment], size: Size, level: ECCLevel) -> QrResult<QrCode> {
+    encode_segment_list(segments, size, level)?.place(size, level)
+}
+
+/// Same as create_qr_matrix_from_segments, but choose the smallest size the segments fit into,
+/// including their ECI headers. Micro sizes are skipped if they do not support the level,
+/// an encoding or the ECI headers.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_from_segments_auto(segments: &[Segment],
+                                           level: ECCLevel,
+                                           selection: SizeSelection) -> QrResult<QrCode> {
+    let mut result = Err(QrError::DataTooLong { bits: 0, capacity: 0, suggestion: None });
+    for size in candidate_sizes(selection)? {
+        result = encode_segment_list(segments, size, level).map(|encoded| (size, encoded));
+        match result {
+            Err(QrError::DataTooLong { .. }) => continue,
+            Err(QrError::InvalidSymbolConfig(..)) |
+            Err(QrError::UnsupportedEncoding(..)) |
+            Err(QrError::EciNotSupported(_)) if size.is_micro() => continue,
+            _ => break
+        }
+    }
+    let (size, encoded) = result?;
+    encoded.place(size, level)
+}
+
+/// Create a structured append series of symbols, one for each of the given parts of a message.
+/// Scanners supporting structured append join the contents of the symbols back into the
+/// complete message. All symbols use the same size and level; the series can hold up to 16 parts.
+#[cfg(feature = "encode")]
+pub fn create_structured_append(parts: &[&[u8]],
+                                size: Size,
+                                level: ECCLevel,
+                                encoding: Option<Encoding>) -> QrResult<Vec<QrCode>> {
+    let parity = structured_append_parity_of_parts(parts);
+    let total = parts.len().min(u8::MAX as usize) as u8;
+
+    parts.iter().enumerate()
+        .map(|(i, part)| {
+            let header = StructuredAppend { position: i as u8, total, parity };
+            let headers = Headers { structured_append: Some(header), ..Headers::default() };
+            encode_content(part, size, level, encoding, headers)?.place(size, level)
+        })
+        .collect()
+}
+
+/// Create a single symbol of a structured append series whose message was split by the caller.
+/// The parity in the header must be computed over the complete message, see
+/// structured_append_parity_of_parts, and be the same for all symbols of the series.
+#[cfg(feature = "encode")]
+pub fn create_structured_append_symbol(part: &[u8],
+                                       size: Size,
+                                       level: ECCLevel,
+                                       encoding: Option<Encoding>,
+                                       header: StructuredAppend) -> QrResult<QrCode> {
+    let headers = Headers { structured_append: Some(header), ..Headers::default() };
+    encode_content(part, size, level, encoding, headers)?.place(size, level)
+}
+
+/// Create the module matrices for content which may be too long for a single symbol of the given
+/// size. If it fits, a single symbol is returned. Otherwise the content is split into a structured
+/// append series of up to 16 symbols, filling each symbol as far as possible.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_split(content: &[u8],
+                              size: Size,
+                              level: ECCLevel,
+                              encoding: Option<Encoding>) -> QrResult<Vec<QrCode>> {
+    let error = match create_qr_matrix(content, size, level, encoding, None) {
+        Ok(code) => return Ok(vec![code]),
+        Err(error @ QrError::DataTooLong { .. }) => error,
+        Err(error) => return Err(error)
+    };
+    if size.is_micro() {
+        return Err(QrError::StructuredAppendNotSupported(size));
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let length = longest_fitting_part(rest, size, level, encoding)?;
+        if length == 0 || parts.len() == 16 {
+            // does not even fit into a complete series
+            return Err(error);
+        }
+        let (part, tail) = rest.split_at(length);
+        parts.push(part);
+        rest = tail;
+    }
+    create_structured_append(&parts, size, level, encoding)
+}
+
+/// find the length of the longest prefix of the content that fits into a symbol
+/// of a structured append series
+#[cfg(feature = "encode")]
+fn longest_fitting_part(content: &[u8], size: Size, level: ECCLevel, encoding: Option<Encoding>) -> QrResult<usize> {
+    // kanji characters must not be split; the header has the same length for all positions
+    let step = if encoding == Some(Encoding::Kanji) { 2 } else { 1 };
+    let headers = Headers {
+        structured_append: Some(StructuredAppend { position: 0, total: 16, parity: 0 }),
+        ..Headers::default()
+    };
+
+    // binary search over the number of characters
+    let (mut lo, mut hi) = (0, content.len() / step);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        match encode_content(&content[..mid * step], size, level, encoding, headers) {
+            Ok(_) => lo = mid,
+            Err(QrError::DataTooLong { .. }) => hi = mid - 1,
+            Err(error) => return Err(error)
+        }
+    }
+    Ok(lo * step)
+}
+
+/// Same as create_qr_matrix_split, but with the content split into the given segments, e.g. the
+/// logical fields of a message. Whole segments are packed into the symbols of the series, so no
+/// segment is split across symbols unless it does not fit into a symbol on its own.
+#[cfg(feature = "encode")]
+pub fn create_qr_matrix_split_segmented(content: &[u8],
+                                        size: Size,
+                                        level: ECCLevel,
+                                        segments: &[(Encoding, Range<usize>)]) -> QrResult<Vec<QrCode>> {
+    let error = match encode_content_segmented(content, size, level, segments, Headers::default()) {
+        Ok(encoded) => return Ok(vec![encoded.place(size, level)?]),
+        Err(error @ QrError::DataTooLong { .. }) => error,
+        Err(error) => return Err(error)
+    };
+    if size.is_micro() {
+        return Err(QrError::StructuredAppendNotSupported(size));
+    }
+
+    // pack the segments greedily, the header has the same length for all positions
+    let probe = Headers {
+        structured_append: Some(StructuredAppend { position: 0, total: 16, parity: 0 }),
+        ..Headers::default()
+    };
+    let mut parts: Vec<Vec<(Encoding, Range<usize>)>> = vec![Vec::new()];
+    let mut pending: Vec<(Encoding, Range<usize>)> = segments.iter().rev().cloned().collect();
+    while let Some((encoding, range)) = pending.pop() {
+        let part = parts.last_mut().unwrap();
+        part.push((encoding, range.clone()));
+        match encode_content_segmented(content, size, level, part, probe) {
+            Ok(_) => continue,
+            Err(QrError::DataTooLong { .. }) => { part.pop(); },
+            Err(error) => return Err(error)
+        }
+
+        if part.is_empty() {
+            // the segment does not fit into a symbol on its own, split it
+            let length = longest_fitting_part(&content[range.clone()], size, level, Some(encoding))?;
+            if length == 0 {
+                return Err(error);
+            }
+            pending.push((encoding, range.start + length..range.end));
+            pending.push((encoding, range.start..range.start + length));
+        } else {
+            pending.push((encoding, range));
+            if parts.len() == 16 {
+                // does not even fit into a complete series
+                return Err(error);
+            }
+            parts.push(Vec::new());
+        }
+    }
+
+    let message: Vec<u8> = segments.iter().flat_map(|(_, range)| content[range.clone()].iter().copied()).collect();
+    let parity = structured_append_parity(&message);
+    let total = parts.len() as u8;
 
-    // done, return
-    masked_symbol
+    parts.iter().enumerate()
+        .map(|(i, part)| {
+            let headers = Headers {
+                structured_append: Some(StructuredAppend { position: i as u8, total, parity }),
+                ..Headers::default()
+            };
+            encode_content_segmented(content, size, level, part, headers)?.place(size, level)
+        })
+        .collect()
 }
@@ -0,0 +1,273 @@
+//! The module matrix of a QR symbol, which is the result of placing the codewords
+//! into the symbol. The matrix does not know anything about pixels or image formats,
+//! turning it into an image (or any other output) is the job of the render module.
+use crate::config::{ECCLevel, Encoding, Size};
+use crate::error::QrError;
+use crate::serialization::SymbolInfo;
+use crate::serialization::masking::MaskSelection;
+
+use std::ops::{Index, Range};
+
+//-------------------------------------------------------------------------------------------------
+
+/// A single module (cell) of the symbol. While assembling the symbol, some modules are only
+/// reserved for content that is filled in by a later stage.
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum Module {
+    Light,
+    Dark,
+    EncodingRegion,         // reserved for the data and ecc codewords
+    FormatInformation,      // reserved for the format information bits (2x 15 bits)
+    VersionInformation      // reserved for the version information bits (2x 18 bits)
+}
+
+impl Module {
+    /// Return the module for a bit value, i.e. dark for a one and light for a zero
+    pub fn from_bit(bit: bool) -> Module {
+        if bit { Module::Dark } else { Module::Light }
+    }
+
+    /// Whether this module is dark. Modules which are still reserved count as light.
+    pub fn is_dark(self) -> bool {
+        self == Module::Dark
+    }
+
+    /// Whether this module is still reserved for a later stage of the symbol assembly
+    pub fn is_reserved(self) -> bool {
+        !matches!(self, Module::Light | Module::Dark)
+    }
+
+    /// Flip a dark module to light and vice versa. Reserved modules are left untouched.
+    pub fn inverted(self) -> Module {
+        match self {
+            Module::Light => Module::Dark,
+            Module::Dark => Module::Light,
+            m => m
+        }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Square matrix of modules. Coordinates are (x, y) with the origin in the upper left
+/// corner of the symbol; the quiet region is not part of the matrix.
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct Matrix {
+    width: u32,
+    modules: Vec<Module>
+}
+
+impl Matrix {
+    /// Constructor, creates a matrix of the given width with all modules set to the same value
+    pub fn new(width: u32, module: Module) -> Matrix {
+        Matrix {
+            width,
+            modules: vec![module; (width * width) as usize]
+        }
+    }
+
+    /// Return width (and height) of the matrix in modules
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Return the module at the given position, or None if the position lies outside of
+    /// the matrix. Convenient for looking at the neighbourhood of border modules.
+    pub fn get(&self, x: i32, y: i32) -> Option<Module> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.width as i32 {
+            None
+        } else {
+            Some(self[(x as u32, y as u32)])
+        }
+    }
+
+    /// Set the module at the given position. Only the canvas creation, the masking and the
+    /// corruptions write modules directly; the stages of the symbol assembly fill the modules
+    /// reserved for them with fill_reserved.
+    pub(crate) fn set(&mut self, (x, y): (u32, u32), module: Module) {
+        assert!(x < self.width && y < self.width, "Module ({}, {}) outside of matrix!", x, y);
+        self.modules[(y * self.width + x) as usize] = module;
+    }
+
+    /// Set the module at the given position, silently ignoring positions outside
+    /// of the matrix. Used to paint patterns which are partly cut off at the borders.
+    pub(crate) fn set_clipped(&mut self, x: i32, y: i32, module: Module) {
+        if x >= 0 && y >= 0 && x < self.width as i32 && y < self.width as i32 {
+            self.set((x as u32, y as u32), module);
+        }
+    }
+
+    /// Reserve a module for the given region again, so a stage can overwrite it in a
+    /// finished symbol, see serialization::insert_raw_format_info
+    pub(crate) fn reserve(&mut self, pos: (u32, u32), region: Module) {
+        debug_assert!(region.is_reserved(), "{:?} is not a reserved region!", region);
+        self.set(pos, region);
+    }
+
+    /// Fill a module reserved for the given region with a bit. Each stage of the symbol assembly
+    /// may only write into the modules reserved for it, and every module only once; in debug
+    /// builds a violation of this rule panics. Modules can not be written any other way from
+    /// outside of the crate.
+    pub fn fill_reserved(&mut self, (x, y): (u32, u32), region: Module, bit: bool) {
+        debug_assert!(region.is_reserved(), "{:?} is not a reserved region!", region);
+        debug_assert_eq!(self[(x, y)], region, "Module ({}, {}) is not reserved for {:?}!", x, y, region);
+        self.set((x, y), Module::from_bit(bit));
+    }
+
+    /// Whether all reserved modules have been filled in
+    pub fn is_complete(&self) -> bool {
+        !self.modules.iter().any(|m| m.is_reserved())
+    }
+
+    /// Iterate over all modules in row-major order, together with their coordinates
+    pub fn enumerate_modules(&self) -> impl Iterator<Item = (u32, u32, Module)> + '_ {
+        let width = self.width;
+        self.modules.iter().enumerate().map(move |(i, &m)| (i as u32 % width, i as u32 / width, m))
+    }
+
+    /// Iterate mutably over all modules in row-major order, together with their coordinates
+    pub(crate) fn enumerate_modules_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut Module)> + '_ {
+        let width = self.width;
+        self.modules.iter_mut().enumerate().map(move |(i, m)| (i as u32 % width, i as u32 / width, m))
+    }
+}
+
+impl Index<(u32, u32)> for Matrix {
+    type Output = Module;
+
+    fn index(&self, (x, y): (u32, u32)) -> &Self::Output {
+        assert!(x < self.width && y < self.width, "Module ({}, {}) outside of matrix!", x, y);
+        &self.modules[(y * self.width + x) as usize]
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// A finished QR symbol, i.e. the masked module matrix including format and
+/// version information, together with the parameters it was created with.
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct QrCode {
+    pub size: Size,
+    pub level: ECCLevel,
+    pub mask: u8,           // index of the applied mask pattern
+    pub mask_selection: Option<MaskSelection>,  // how the mask was chosen, None if it was given
+    pub encode_info: Option<EncodeInfo>,        // None if the symbol was created from codewords
+    pub matrix: Matrix
+}
+
+impl QrCode {
+    /// Return the size, ECC level and mask of the symbol, e.g. to store them for a reprint,
+    /// see EncodeOptions::matching
+    pub fn symbol_info(&self) -> SymbolInfo {
+        SymbolInfo { size: self.size, level: self.level, mask: self.mask }
+    }
+
+    /// Width of the quiet region required for the size of the symbol, in modules.
+    /// Micro symbols only need 2 modules, standard symbols 4.
+    pub fn quiet_zone(&self) -> u32 {
+        self.size.quiet_region_size()
+    }
+}
+
+/// How much of the data capacity of a symbol is used by the content, and how it was encoded
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct EncodeInfo {
+    pub data_bits: usize,       // bits used by the headers and segments
+    pub capacity_bits: usize,   // data bits the symbol holds
+    pub terminator_bits: usize, // zero bits of the terminator, shortened if the symbol is almost full
+    pub alignment_bits: usize,  // zero bits padding the terminated data to a codeword boundary
+    pub pad_codewords: usize,   // pad codewords filling the unused capacity
+    pub segments: Vec<(Encoding, Range<usize>)>,    // encodings of the data carried by this symbol, empty
+                                                    // for bitstreams finalized with the low-level functions
+    pub warnings: Vec<EncodeWarning>    // advice about the scannability, which did not prevent the encoding
+}
+
+/// Something about the encoding that works, but is likely not what the caller intended
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum EncodeWarning {
+    LessDenseEncoding {         // the whole content could have been encoded in a denser mode
+        forced: Encoding,
+        possible: Encoding
+    },
+    Uppercased(usize),          // number of lowercase letters uppercased for the alphanumeric mode
+    NearCapacity,               // the data fills more than 90% of the capacity, longer content needs a larger symbol
+    MicroSymbol(Size),          // micro symbols are not supported by all scanners
+    Utf8WithoutEci              // bytes look like UTF-8, but without ECI header scanners may assume ISO 8859-1
+}
+
+/// Problem with a content found before encoding it, e.g. to validate input fields of a form
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub enum ValidationIssue {
+    Empty,                      // there is no content
+    InvalidCharacters {         // characters the forced encoding can not represent
+        encoding: Encoding,
+        positions: Vec<usize>   // byte offsets of the characters, of the first byte for kanji pairs
+    },
+    TooLong {                   // the content does not fit into the largest symbol the constraints allow
+        bits: usize,
+        capacity: usize
+    },
+    Unusable(QrError)           // the constraints can not be met for any content, e.g. an invalid size
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indexing() {
+        let mut matrix = Matrix::new(11, Module::Light);
+        matrix.set((10, 2), Module::Dark);
+        assert_eq!(matrix[(10, 2)], Module::Dark);
+        assert_eq!(matrix.get(10, 2), Some(Module::Dark));
+        assert_eq!(matrix.get(-1, 2), None);
+        assert_eq!(matrix.get(2, 11), None);
+
+        // clipped writes outside of the matrix are ignored
+        matrix.set_clipped(-1, 0, Module::Dark);
+        matrix.set_clipped(0, 0, Module::Dark);
+        assert_eq!(matrix.enumerate_modules().filter(|&(_, _, m)| m.is_dark()).count(), 2);
+        assert_eq!(matrix.enumerate_modules().nth(2 * 11 + 10), Some((10, 2, Module::Dark)));
+    }
+
+    #[test]
+    fn test_inverted() {
+        assert_eq!(Module::Dark.inverted(), Module::Light);
+        assert_eq!(Module::Light.inverted(), Module::Dark);
+        assert_eq!(Module::EncodingRegion.inverted(), Module::EncodingRegion);
+    }
+
+    #[test]
+    fn test_fill_reserved() {
+        let mut matrix = Matrix::new(11, Module::EncodingRegion);
+        matrix.reserve((0, 0), Module::FormatInformation);
+        matrix.fill_reserved((1, 0), Module::EncodingRegion, true);
+        assert_eq!(matrix[(1, 0)], Module::Dark);
+        assert!(!matrix.is_complete());
+
+        for (x, y, m) in matrix.clone().enumerate_modules() {
+            if m.is_reserved() {
+                matrix.fill_reserved((x, y), m, false);
+            }
+        }
+        assert!(matrix.is_complete());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_double_write() {
+        let mut matrix = Matrix::new(11, Module::EncodingRegion);
+        matrix.fill_reserved((1, 0), Module::EncodingRegion, true);
+        matrix.fill_reserved((1, 0), Module::EncodingRegion, true);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_write_outside_region() {
+        let mut matrix = Matrix::new(11, Module::EncodingRegion);
+        matrix.fill_reserved((1, 0), Module::FormatInformation, true);
+    }
+}
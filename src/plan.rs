@@ -0,0 +1,81 @@
+//! Two-step API for user interfaces: `plan` decides the size, level and segmentation of a
+//! symbol, which can be shown for approval, and `EncodingPlan::render` produces the artwork.
+use std::ops::Range;
+
+use crate::{content_with_eci, encode_content_auto, encode_content_segmented, Headers};
+use crate::config::{ECCLevel, Encoding, IntoQrData, MicroPolicy, Size, SizeSelection};
+use crate::error::QrResult;
+use crate::matrix::{EncodeInfo, QrCode};
+use crate::render::{self, VectorOptions};
+
+//-------------------------------------------------------------------------------------------------
+
+/// Everything that determines a symbol, decided before any module is placed
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct EncodingPlan {
+    pub size: Size,
+    pub level: ECCLevel,
+    pub eci: Option<u32>,
+    pub segments: Vec<(Encoding, Range<usize>)>,    // segmentation of the content
+    pub info: EncodeInfo,                           // usage of the data capacity
+    content: Vec<u8>
+}
+
+/// Output format of EncodingPlan::render
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum RenderStyle {
+    Matrix,                 // only the finished module matrix
+    Image,                  // grayscale image with one pixel per module
+    Svg(VectorOptions),
+    Eps(VectorOptions)
+}
+
+/// Artwork produced by EncodingPlan::render, depending on the style
+#[derive(Clone,PartialEq,Debug)]
+pub enum Rendered {
+    Matrix(QrCode),
+    Image(image::GrayImage),
+    Vector(String)          // SVG or EPS document
+}
+
+/// Plan a symbol for the content: choose the smallest size the selection options allow,
+/// boost the level if requested and split the content into segments, see create_qr_matrix_auto.
+pub fn plan<'a>(content: impl IntoQrData<'a>,
+                level: ECCLevel,
+                encoding: Option<Encoding>,
+                eci: Option<u32>,
+                selection: SizeSelection) -> QrResult<EncodingPlan> {
+    let (content, eci) = content_with_eci(content, eci, selection.micro != MicroPolicy::MicroOnly);
+    let content = content.into_owned();
+    let headers = Headers { eci, ..Headers::default() };
+    let (size, level, encoded) = encode_content_auto(&content, level, encoding, headers, selection)?;
+
+    let segments = encoded.info.segments.clone();
+    Ok(EncodingPlan { size, level, eci, segments, info: encoded.info, content })
+}
+
+impl EncodingPlan {
+    /// The planned content
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Edge length of the symbol in modules, including the quiet region
+    pub fn dimensions(&self) -> u32 {
+        self.size.dimensions_with_quiet_region()
+    }
+
+    /// Encode the content exactly as planned and render it in the given style
+    pub fn render(&self, style: RenderStyle) -> QrResult<Rendered> {
+        let headers = Headers { eci: self.eci, ..Headers::default() };
+        let code = encode_content_segmented(&self.content, self.size, self.level, &self.segments, headers)?
+            .place(self.size, self.level)?;
+
+        Ok(match style {
+            RenderStyle::Matrix => Rendered::Matrix(code),
+            RenderStyle::Image => Rendered::Image(render::to_gray_image(&code)),
+            RenderStyle::Svg(options) => Rendered::Vector(render::svg::to_svg(&code, &options)),
+            RenderStyle::Eps(options) => Rendered::Vector(render::eps::to_eps(&code, &options))
+        })
+    }
+}
@@ -1,58 +1,45 @@
 
-use std::ops::Deref;
+use std::cmp;
 
+use crate::blocks::{interleave, split_data_blocks_with_capacity};
 use crate::config::{ECCLevel, Size};
-use crate::tables::lookup_capacity;
+use crate::error::QrResult;
+use crate::tables::{try_lookup_capacity, SymbolCapacity};
 
-/// Almost this whole module is stolen from the ec module of https://github.com/kennytm/qrcode-rust
+// Almost this whole module is stolen from the ec module of https://github.com/kennytm/qrcode-rust
 
 /// Constructs data and error correction codewords ready to be put in the QR
 /// code matrix. For rawbits expects the byte array that fills the whole capacity of the symbol,
 /// potentially padded as specified in the standard. Returns the data and error correction codewords
 /// already interleaved in the specified way, to be put into the image matrix.
-pub fn construct_codewords(rawbits: &[u8], size: Size, ec_level: ECCLevel) -> (Vec<u8>, Vec<u8>) {
-
-    let config_data = lookup_capacity(size, ec_level);
-
-    let block_1_end = (config_data.block_def1.data_codewords * config_data.block_def1.num_blocks) as usize;
+pub fn construct_codewords(rawbits: &[u8], size: Size, ec_level: ECCLevel) -> QrResult<(Vec<u8>, Vec<u8>)> {
+    construct_codewords_with_capacity(rawbits, &try_lookup_capacity(size, ec_level)?)
+}
 
-    debug_assert_eq!(rawbits.len(), config_data.data_codewords() as usize);
+/// Same as construct_codewords, for the block structure of the given capacity, e.g. of a
+/// symbol family plugged in by another crate (see serialization::SymbolFamily).
+pub fn construct_codewords_with_capacity(rawbits: &[u8], config_data: &SymbolCapacity) -> QrResult<(Vec<u8>, Vec<u8>)> {
 
     // Divide the data into blocks.
-    let mut blocks = Vec::with_capacity(config_data.num_blocks() as usize);
-    blocks.extend(rawbits[..block_1_end].chunks(config_data.block_def1.data_codewords as usize));
-    if config_data.block_def2.num_blocks > 0 {
-        blocks.extend(rawbits[block_1_end..].chunks(config_data.block_def2.data_codewords as usize));
-    }
+    let blocks = split_data_blocks_with_capacity(rawbits, config_data)?;
 
-    // Generate EC codes.
+    // Generate EC codes. All blocks have the same number of EC codewords, so they are written
+    // straight to their interleaved positions, using one scratch buffer for all blocks.
     let ec_bytes = config_data.ecc_words_per_block() as usize;
-    let ec_codes = blocks.iter().map(|block| create_reedsolomon_code(*block, ec_bytes)).collect::<Vec<Vec<u8>>>();
+    let num_blocks = blocks.len();
+    let longest_block = cmp::max(config_data.block_def1.codewords, config_data.block_def2.codewords) as usize;
+    let mut scratch = Vec::with_capacity(longest_block);
+    let mut ec_vec = vec![0u8; config_data.ecc_words() as usize];
+    for (b, block) in blocks.iter().enumerate() {
+        let ec_code = create_reedsolomon_code(block, ec_bytes, &mut scratch);
+        for (i, &word) in ec_code.iter().enumerate() {
+            ec_vec[i * num_blocks + b] = word;
+        }
+    }
 
     let blocks_vec = interleave(&blocks);
-    let ec_vec = interleave(&ec_codes);
 
-    (blocks_vec, ec_vec)
-}
-
-/// This method interleaves a vector of slices into a single vector.
-///
-/// It will first insert all the first elements of the slices in `blocks`, then
-/// all the second elements, then all the third elements, and so on.
-///
-/// The longest slice must be at the last of `blocks`, and `blocks` must not be
-/// empty.
-fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
-    let last_block_len = blocks.last().expect("non-empty blocks").len();
-    let mut res = Vec::with_capacity(last_block_len * blocks.len());
-    for i in 0..last_block_len {
-        for t in blocks {
-            if i < t.len() {
-                res.push(t[i]);
-            }
-        }
-    }
-    res
+    Ok((blocks_vec, ec_vec))
 }
 
 
@@ -65,11 +52,15 @@ fn interleave<T: Copy, V: Deref<Target = [T]>>(blocks: &[V]) -> Vec<T> {
 /// (a\[0\] x<sup>m+n</sup> + a\[1\] x<sup>m+n-1</sup> + … + a\[m\] x<sup>n</sup>)
 /// in GF(2<sup>8</sup>), and then computes the polynomial modulus with a
 /// generator polynomial of degree N.
-fn create_reedsolomon_code(data: &[u8], ec_code_size: usize) -> Vec<u8> {
+///
+/// The computation happens in the given scratch buffer, which holds the code afterwards.
+fn create_reedsolomon_code<'a>(data: &[u8], ec_code_size: usize, scratch: &'a mut Vec<u8>) -> &'a [u8] {
     let data_len = data.len();
     let log_den = GENERATOR_POLYNOMIALS[ec_code_size];
 
-    let mut res = data.to_vec();
+    let res = scratch;
+    res.clear();
+    res.extend_from_slice(data);
     res.resize(ec_code_size + data_len, 0);
 
     // rust-lang-nursery/rust-clippy#2213
@@ -85,7 +76,7 @@ fn create_reedsolomon_code(data: &[u8], ec_code_size: usize) -> Vec<u8> {
         }
     }
 
-    res.split_off(data_len)
+    &res[data_len..]
 }
 
 
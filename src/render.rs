@@ -0,0 +1,605 @@
+//! Turns module matrices into images. This is the only part of the crate
+//! that knows about pixels; everything up to the finished module matrix is
+//! independent of the output format.
+use image;
+
+use crate::error::{QrError, QrResult};
+use crate::matrix::{Matrix, Module, QrCode};
+use crate::serialization::{alignment_pattern_positions, size_from_width, timing_pattern_positions};
+use crate::serialization::masking::compute_penalty_map;
+
+pub mod svg;
+pub mod eps;
+
+// CONSTANTS
+pub const BIT_WHITE: image::Luma<u8> = image::Luma([255u8]);
+pub const BIT_BLACK: image::Luma<u8> = image::Luma([0u8]);
+
+// gray values for modules that are still reserved, only visible when rendering
+// unfinished canvases during debugging
+pub const MARKER_ENCODING_REGION: image::Luma<u8> = image::Luma([100u8]);
+pub const MARKER_FORMAT_INFORMATION: image::Luma<u8> = image::Luma([120u8]);
+pub const MARKER_VERSION_INFORMATION: image::Luma<u8> = image::Luma([140u8]);
+
+
+/// Return the pixel value used to paint the given module
+pub fn module_color(module: Module) -> image::Luma<u8> {
+    match module {
+        Module::Light => BIT_WHITE,
+        Module::Dark => BIT_BLACK,
+        Module::EncodingRegion => MARKER_ENCODING_REGION,
+        Module::FormatInformation => MARKER_FORMAT_INFORMATION,
+        Module::VersionInformation => MARKER_VERSION_INFORMATION
+    }
+}
+
+/// Render a module matrix into a grayscale image with one pixel per module,
+/// surrounded by a light quiet region of the given width.
+pub fn render_matrix(matrix: &Matrix, quiet_zone: u32) -> image::GrayImage {
+    let s = matrix.width() + 2 * quiet_zone;
+    let mut img = image::GrayImage::from_pixel(s, s, BIT_WHITE);
+
+    for (x, y, module) in matrix.enumerate_modules() {
+        img[(x + quiet_zone, y + quiet_zone)] = module_color(module);
+    }
+
+    img
+}
+
+/// Render a finished symbol into a grayscale image with one pixel per module,
+/// including the quiet region required for its size.
+pub fn to_gray_image(code: &QrCode) -> image::GrayImage {
+    render_matrix(&code.matrix, code.quiet_zone())
+}
+
+/// Render a placeholder for a symbol that could not be encoded: a dark frame with both
+/// diagonals on a light background, with the given edge length in pixels. It is clearly
+/// not a symbol, so a failure stays visible when the image is used in a layout anyway.
+pub fn render_placeholder(edge: u32) -> image::GrayImage {
+    let last = edge.saturating_sub(1);
+    image::GrayImage::from_fn(edge, edge, |x, y| {
+        if x == 0 || y == 0 || x == last || y == last || x == y || x + y == last {
+            BIT_BLACK
+        } else {
+            BIT_WHITE
+        }
+    })
+}
+
+// edge length of a raster render including the quiet region in modules and in pixels,
+// or None if it does not fit into u32
+fn render_size(width: u32, quiet_zone: u32, scale: u32) -> Option<(u32, u32)> {
+    let modules = quiet_zone.checked_mul(2)?.checked_add(width)?;
+    Some((modules, modules.checked_mul(scale)?))
+}
+
+/// Render a module matrix into a region of an existing image, e.g. a sheet composed of many
+/// symbols. The symbol including the quiet region is drawn with its upper left corner at
+/// `offset`, using `scale` pixels per module and the given colors for light and dark modules.
+/// Pixels outside of the region are not touched.
+pub fn render_matrix_into<I: image::GenericImage>(matrix: &Matrix,
+                                                  quiet_zone: u32,
+                                                  target: &mut I,
+                                                  offset: (u32, u32),
+                                                  scale: u32,
+                                                  colors: [I::Pixel; 2]) -> QrResult<()> {
+    let (x0, y0) = offset;
+    let (_, s) = render_size(matrix.width(), quiet_zone, scale)
+        .ok_or(QrError::RegionOutOfBounds { x: x0, y: y0, size: u32::MAX })?;
+    if x0.checked_add(s).is_none_or(|x1| x1 > target.width()) ||
+       y0.checked_add(s).is_none_or(|y1| y1 > target.height()) {
+        return Err(QrError::RegionOutOfBounds { x: x0, y: y0, size: s });
+    }
+
+    for y in 0..s {
+        for x in 0..s {
+            let (mx, my) = (x / scale, y / scale);
+            let dark = (quiet_zone..quiet_zone + matrix.width()).contains(&mx) &&
+                       (quiet_zone..quiet_zone + matrix.width()).contains(&my) &&
+                       matrix[(mx - quiet_zone, my - quiet_zone)].is_dark();
+            target.put_pixel(x0 + x, y0 + y, colors[dark as usize]);
+        }
+    }
+    Ok(())
+}
+
+/// Render a finished symbol into a region of an existing image, including the quiet region
+/// required for its size, see render_matrix_into.
+pub fn to_image_region<I: image::GenericImage>(code: &QrCode,
+                                               target: &mut I,
+                                               offset: (u32, u32),
+                                               scale: u32,
+                                               colors: [I::Pixel; 2]) -> QrResult<()> {
+    render_matrix_into(&code.matrix, code.quiet_zone(), target, offset, scale, colors)
+}
+
+// default colors of the function patterns in diagnostic renders, for light and dark modules
+pub const DIAGNOSTIC_TIMING: [image::Rgb<u8>; 2] = [image::Rgb([255u8, 190, 190]), image::Rgb([200u8, 0, 0])];
+pub const DIAGNOSTIC_ALIGNMENT: [image::Rgb<u8>; 2] = [image::Rgb([190u8, 190, 255]), image::Rgb([0u8, 0, 200])];
+
+/// Colors of the function patterns in diagnostic renders, see render_matrix_diagnostic
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct DiagnosticColors {
+    pub timing: [image::Rgb<u8>; 2],        // light and dark modules of the timing patterns
+    pub alignment: [image::Rgb<u8>; 2]      // light and dark modules of the alignment patterns
+}
+
+impl Default for DiagnosticColors {
+    fn default() -> Self {
+        DiagnosticColors {
+            timing: DIAGNOSTIC_TIMING,
+            alignment: DIAGNOSTIC_ALIGNMENT
+        }
+    }
+}
+
+/// Render a module matrix for diagnostic purposes, with the timing and alignment patterns
+/// highlighted in the given colors. All other modules are rendered as in render_matrix. Makes it
+/// easy to verify the layout of the function patterns visually; not meant for production output.
+/// The size of the symbol is derived from the width of the matrix.
+pub fn render_matrix_diagnostic(matrix: &Matrix, quiet_zone: u32, colors: DiagnosticColors) -> QrResult<image::RgbImage> {
+    let size = size_from_width(matrix.width())?;
+    let gray = render_matrix(matrix, quiet_zone);
+    let mut img = image::RgbImage::from_fn(gray.width(), gray.height(), |x, y| {
+        let v = gray[(x, y)][0];
+        image::Rgb([v, v, v])
+    });
+
+    for (colors, positions) in [(colors.timing, timing_pattern_positions(size)),
+                                (colors.alignment, alignment_pattern_positions(size))] {
+        for (x, y) in positions {
+            img[(x + quiet_zone, y + quiet_zone)] = colors[matrix[(x, y)].is_dark() as usize];
+        }
+    }
+
+    Ok(img)
+}
+
+/// Render a masked standard symbol as a heatmap of its penalty score, see compute_penalty_map:
+/// the more points a module contributes, the redder it is, relative to the hottest module.
+/// Light modules go from white to red, dark modules from black to dark red. Meant for
+/// understanding the mask selection, not for production output.
+pub fn render_penalty_heatmap(matrix: &Matrix, quiet_zone: u32) -> image::RgbImage {
+    let map = compute_penalty_map(matrix);
+    let max = (0..matrix.width())
+        .flat_map(|y| (0..matrix.width()).map(move |x| (x, y)))
+        .map(|(x, y)| map.total(x, y))
+        .fold(0.0, f64::max);
+
+    let s = matrix.width() + 2 * quiet_zone;
+    let mut img = image::RgbImage::from_pixel(s, s, image::Rgb([255u8; 3]));
+    for (x, y, module) in matrix.enumerate_modules() {
+        let heat = if max > 0.0 { map.total(x, y) / max } else { 0.0 };
+        img[(x + quiet_zone, y + quiet_zone)] = if module.is_dark() {
+            image::Rgb([(160.0 * heat) as u8, 0, 0])
+        } else {
+            let v = (255.0 * (1.0 - heat)) as u8;
+            image::Rgb([255, v, v])
+        };
+    }
+
+    img
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Experimental: overlay up to three symbols of the same size into the red, green and blue
+/// channels of one image, with one pixel per module. Each channel on its own is a valid symbol;
+/// channels without a symbol are light. Ordinary scanners can not read the result, the channels
+/// have to be separated with demultiplex_channel first.
+pub fn render_multiplexed(codes: &[QrCode]) -> QrResult<image::RgbImage> {
+    if codes.is_empty() || codes.len() > 3 {
+        return Err(QrError::InvalidChannelCount(codes.len()));
+    }
+    if let Some(code) = codes.iter().find(|code| code.size != codes[0].size) {
+        return Err(QrError::MultiplexSizeMismatch(codes[0].size, code.size));
+    }
+
+    let channels: Vec<image::GrayImage> = codes.iter().map(to_gray_image).collect();
+    Ok(image::RgbImage::from_fn(channels[0].width(), channels[0].height(), |x, y| {
+        let mut pixel = image::Rgb([255u8; 3]);
+        for (i, channel) in channels.iter().enumerate() {
+            pixel[i] = channel[(x, y)][0];
+        }
+        pixel
+    }))
+}
+
+/// Experimental: extract the symbol in the given channel (0 to 2 for red, green and blue)
+/// of an image created by render_multiplexed.
+pub fn demultiplex_channel(img: &image::RgbImage, channel: usize) -> image::GrayImage {
+    image::GrayImage::from_fn(img.width(), img.height(), |x, y| image::Luma([img[(x, y)][channel]]))
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Options for rendering into caller-owned grayscale buffers, see render_gray_into
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct GrayBufferOptions {
+    pub quiet_zone: u32,        // width of the quiet region in modules
+    pub scale: u32,             // pixels per module
+    pub colors: [u8; 2]         // gray values of light and dark modules
+}
+
+impl GrayBufferOptions {
+    /// Constructor, creates options with black modules on white
+    pub fn new(quiet_zone: u32, scale: u32) -> GrayBufferOptions {
+        GrayBufferOptions {
+            quiet_zone,
+            scale,
+            colors: [BIT_WHITE[0], BIT_BLACK[0]]
+        }
+    }
+
+    /// Options for a finished symbol, with the quiet region required for its size
+    pub fn for_code(code: &QrCode, scale: u32) -> GrayBufferOptions {
+        GrayBufferOptions::new(code.quiet_zone(), scale)
+    }
+}
+
+/// Render a module matrix directly into an externally owned 8 bit grayscale buffer of
+/// `width` x `height` pixels, whose rows start `stride` bytes apart, e.g. a mapped staging buffer.
+/// The symbol including the quiet region is drawn into the upper left corner; all other pixels
+/// are not touched. No intermediate image is allocated.
+pub fn render_gray_into(matrix: &Matrix,
+                        buf: &mut [u8],
+                        width: u32,
+                        height: u32,
+                        stride: usize,
+                        opts: GrayBufferOptions) -> QrResult<()> {
+    let (w, h) = (width as usize, height as usize);
+    if stride < w || (h > 0 && buf.len() < stride * (h - 1) + w) {
+        return Err(QrError::InvalidBufferLayout { width, height, stride, len: buf.len() });
+    }
+    let (modules, s) = render_size(matrix.width(), opts.quiet_zone, opts.scale)
+        .ok_or(QrError::RegionOutOfBounds { x: 0, y: 0, size: u32::MAX })?;
+    if s > width || s > height {
+        return Err(QrError::RegionOutOfBounds { x: 0, y: 0, size: s });
+    }
+
+    // render each row of modules once, and copy it to all pixel rows it covers
+    let scale = opts.scale as usize;
+    let mut row = vec![opts.colors[0]; s as usize];
+    for my in 0..modules {
+        for mx in 0..modules {
+            let dark = (opts.quiet_zone..opts.quiet_zone + matrix.width()).contains(&mx) &&
+                       (opts.quiet_zone..opts.quiet_zone + matrix.width()).contains(&my) &&
+                       matrix[(mx - opts.quiet_zone, my - opts.quiet_zone)].is_dark();
+            let start = mx as usize * scale;
+            row[start..start + scale].fill(opts.colors[dark as usize]);
+        }
+        for y in my as usize * scale..(my as usize + 1) * scale {
+            buf[y * stride..y * stride + row.len()].copy_from_slice(&row);
+        }
+    }
+    Ok(())
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Mapping between module and pixel coordinates of a raster render with `scale` pixels per module,
+/// as done by render_matrix_into and render_gray_into. Useful for drawing overlays, e.g. the
+/// area reserved for a logo, without repeating the layout computations.
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct PixelGeometry {
+    pub width: u32,             // width of the matrix in modules, without quiet region
+    pub quiet_zone: u32,        // width of the quiet region in modules
+    pub scale: u32,             // pixels per module
+    pub offset: (u32, u32)      // upper left corner of the render (including quiet region) in the image
+}
+
+impl PixelGeometry {
+    /// Constructor, creates the geometry of a render into the upper left corner of an image
+    pub fn new(matrix: &Matrix, quiet_zone: u32, scale: u32) -> PixelGeometry {
+        PixelGeometry {
+            width: matrix.width(),
+            quiet_zone,
+            scale,
+            offset: (0, 0)
+        }
+    }
+
+    /// Geometry of a finished symbol, with the quiet region required for its size
+    pub fn for_code(code: &QrCode, scale: u32) -> PixelGeometry {
+        PixelGeometry::new(&code.matrix, code.quiet_zone(), scale)
+    }
+
+    /// Return the same geometry for a render with its upper left corner at the given position
+    pub fn with_offset(self, offset: (u32, u32)) -> PixelGeometry {
+        PixelGeometry { offset, ..self }
+    }
+
+    /// Edge length of a single module in pixels
+    pub fn module_size(&self) -> u32 {
+        self.scale
+    }
+
+    /// Edge length of the whole render in pixels, including the quiet region.
+    /// The pixel coordinates of this and the following functions are None if they
+    /// do not fit into u32.
+    pub fn total_size(&self) -> Option<u32> {
+        render_size(self.width, self.quiet_zone, self.scale).map(|(_, s)| s)
+    }
+
+    /// Square covered by the modules of the symbol (x, y, edge length in pixels), without quiet region
+    pub fn symbol_bounds(&self) -> Option<(u32, u32, u32)> {
+        self.area_to_pixels((0, 0, self.width))
+    }
+
+    /// Upper left pixel of the module at the given position
+    pub fn module_to_pixel(&self, (x, y): (u32, u32)) -> Option<(u32, u32)> {
+        let margin = self.quiet_zone.checked_mul(self.scale)?;
+        let to_pixel = |offset: u32, i: u32| i.checked_mul(self.scale)?.checked_add(margin)?.checked_add(offset);
+        Some((to_pixel(self.offset.0, x)?, to_pixel(self.offset.1, y)?))
+    }
+
+    /// Square in pixels covered by a square area of modules (x, y, edge length in modules),
+    /// e.g. as returned by serialization::centered_area
+    pub fn area_to_pixels(&self, (x, y, edge): (u32, u32, u32)) -> Option<(u32, u32, u32)> {
+        let (px, py) = self.module_to_pixel((x, y))?;
+        Some((px, py, edge.checked_mul(self.scale)?))
+    }
+
+    /// Module covering the given pixel, or None if the pixel lies in the quiet region
+    /// or outside of the render. A render with scale 0 has no pixels, so it is always None.
+    pub fn pixel_to_module(&self, (px, py): (u32, u32)) -> Option<(u32, u32)> {
+        // a symbol starting beyond u32 covers no pixel
+        let margin = self.quiet_zone.checked_mul(self.scale)?;
+        let x = px.checked_sub(self.offset.0.checked_add(margin)?)?.checked_div(self.scale)?;
+        let y = py.checked_sub(self.offset.1.checked_add(margin)?)?.checked_div(self.scale)?;
+        if x < self.width && y < self.width { Some((x, y)) } else { None }
+    }
+}
+
+//-------------------------------------------------------------------------------------------------
+
+/// Physical unit used by the vector renderers for the document size and all geometry
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum Unit {
+    Millimeter,
+    Inch,
+    Point           // PostScript point, 1/72 inch
+}
+
+impl Unit {
+    /// Suffix of the unit as used in SVG length attributes
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Unit::Millimeter => "mm",
+            Unit::Inch => "in",
+            Unit::Point => "pt"
+        }
+    }
+
+    /// Number of PostScript points in one unit
+    pub fn points(self) -> f64 {
+        match self {
+            Unit::Millimeter => 72.0 / 25.4,
+            Unit::Inch => 72.0,
+            Unit::Point => 1.0
+        }
+    }
+}
+
+/// Options for the vector renderers. All lengths are given in `unit`.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct VectorOptions {
+    pub unit: Unit,
+    pub module_size: f64,       // edge length of a single module
+    pub bleed: f64              // light margin added around the quiet region, for trimming in print
+}
+
+impl VectorOptions {
+    /// Constructor, creates options without bleed
+    pub fn new(unit: Unit, module_size: f64) -> VectorOptions {
+        VectorOptions {
+            unit,
+            module_size,
+            bleed: 0.0
+        }
+    }
+
+    /// Return the same options with the given bleed margin
+    pub fn with_bleed(self, bleed: f64) -> VectorOptions {
+        VectorOptions { bleed, ..self }
+    }
+
+    /// Total edge length of the artwork for a matrix with the given quiet region,
+    /// including the bleed on both sides.
+    pub fn total_size(&self, matrix: &Matrix, quiet_zone: u32) -> f64 {
+        (matrix.width() + 2 * quiet_zone) as f64 * self.module_size + 2.0 * self.bleed
+    }
+
+    /// Offset of the first module of the matrix from the edge of the artwork
+    fn matrix_offset(&self, quiet_zone: u32) -> f64 {
+        quiet_zone as f64 * self.module_size + self.bleed
+    }
+}
+
+/// Format a length for vector output. Rounds to 4 decimals to hide floating point noise
+/// and strips trailing zeros, so whole numbers are printed without a decimal point.
+fn format_length(value: f64) -> String {
+    let s = format!("{:.4}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quiet_region() {
+        let mut matrix = Matrix::new(11, Module::Dark);
+        matrix.set((3, 4), Module::Light);
+        let img = render_matrix(&matrix, 2);
+
+        assert_eq!(img.dimensions(), (15, 15));
+        assert_eq!(img[(0, 0)], BIT_WHITE);
+        assert_eq!(img[(1, 14)], BIT_WHITE);
+        assert_eq!(img[(2, 2)], BIT_BLACK);
+        assert_eq!(img[(12, 12)], BIT_BLACK);
+        assert_eq!(img[(5, 6)], BIT_WHITE);
+        assert_eq!(img.pixels().filter(|&&px| px == BIT_BLACK).count(), 11 * 11 - 1);
+    }
+
+    #[test]
+    fn test_penalty_heatmap() {
+        // a light symbol with a dark 2x2 block: the light modules are part of long runs,
+        // the block only gets block points
+        let mut matrix = Matrix::new(21, Module::Light);
+        for &(x, y) in [(10, 10), (11, 10), (10, 11), (11, 11)].iter() {
+            matrix.set((x, y), Module::Dark);
+        }
+        let img = render_penalty_heatmap(&matrix, 1);
+        assert_eq!(img.dimensions(), (23, 23));
+        assert_eq!(img[(0, 0)], image::Rgb([255, 255, 255]));
+        assert_eq!((img[(11, 11)][1], img[(11, 11)][2]), (0, 0));
+        assert!(img[(11, 11)][0] < 160);
+        assert_eq!(img[(1, 1)][0], 255);
+        assert!(img[(1, 1)][1] < 255);
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let img = render_placeholder(5);
+        let dark: Vec<u32> = img.pixels().map(|p| (*p == BIT_BLACK) as u32).collect();
+        assert_eq!(dark, [1, 1, 1, 1, 1,
+                          1, 1, 0, 1, 1,
+                          1, 0, 1, 0, 1,
+                          1, 1, 0, 1, 1,
+                          1, 1, 1, 1, 1]);
+        assert_eq!(render_placeholder(0).dimensions(), (0, 0));
+    }
+
+    #[test]
+    fn test_render_into() {
+        let mut matrix = Matrix::new(11, Module::Light);
+        matrix.set((0, 0), Module::Dark);
+        matrix.set((10, 10), Module::Dark);
+
+        let colors = [image::Rgba([255u8, 255, 255, 255]), image::Rgba([0u8, 0, 0, 255])];
+        let mut sheet = image::RgbaImage::from_pixel(100, 60, image::Rgba([0, 0, 0, 0]));
+        render_matrix_into(&matrix, 2, &mut sheet, (10, 5), 3, colors).unwrap();
+
+        // untouched outside of the region
+        assert_eq!(sheet[(9, 5)], image::Rgba([0, 0, 0, 0]));
+        assert_eq!(sheet[(10 + 45, 5)], image::Rgba([0, 0, 0, 0]));
+        // quiet region, first and last module
+        assert_eq!(sheet[(10, 5)], colors[0]);
+        assert_eq!(sheet[(10 + 6, 5 + 6)], colors[1]);
+        assert_eq!(sheet[(10 + 8, 5 + 8)], colors[1]);
+        assert_eq!(sheet[(10 + 9, 5 + 6)], colors[0]);
+        assert_eq!(sheet[(10 + 38, 5 + 38)], colors[1]);
+        assert_eq!(sheet[(10 + 39, 5 + 39)], colors[0]);
+
+        assert_eq!(render_matrix_into(&matrix, 2, &mut sheet, (60, 5), 3, colors),
+                   Err(QrError::RegionOutOfBounds { x: 60, y: 5, size: 45 }));
+        // sizes that do not even fit into u32 are out of bounds as well
+        assert_eq!(render_matrix_into(&matrix, u32::MAX / 2, &mut sheet, (0, 0), 3, colors),
+                   Err(QrError::RegionOutOfBounds { x: 0, y: 0, size: u32::MAX }));
+        assert_eq!(render_matrix_into(&matrix, 2, &mut sheet, (0, 0), u32::MAX / 4, colors),
+                   Err(QrError::RegionOutOfBounds { x: 0, y: 0, size: u32::MAX }));
+    }
+
+    #[test]
+    fn test_pixel_geometry() {
+        // same layout as in test_render_into
+        let matrix = Matrix::new(11, Module::Light);
+        let geometry = PixelGeometry::new(&matrix, 2, 3).with_offset((10, 5));
+        assert_eq!(geometry.module_size(), 3);
+        assert_eq!(geometry.total_size(), Some(45));
+        assert_eq!(geometry.symbol_bounds(), Some((16, 11, 33)));
+        assert_eq!(geometry.module_to_pixel((10, 10)), Some((10 + 36, 5 + 36)));
+        assert_eq!(geometry.area_to_pixels((4, 4, 3)), Some((28, 23, 9)));
+
+        assert_eq!(geometry.pixel_to_module((16, 11)), Some((0, 0)));
+        assert_eq!(geometry.pixel_to_module((18, 13)), Some((0, 0)));
+        assert_eq!(geometry.pixel_to_module((19, 11)), Some((1, 0)));
+        assert_eq!(geometry.pixel_to_module((15, 11)), None);
+        assert_eq!(geometry.pixel_to_module((16 + 33, 11)), None);
+
+        // a zero scale maps no pixel to a module
+        let geometry = PixelGeometry::new(&matrix, 2, 0).with_offset((10, 5));
+        assert_eq!(geometry.total_size(), Some(0));
+        assert_eq!(geometry.pixel_to_module((10, 5)), None);
+        assert_eq!(geometry.pixel_to_module((100, 100)), None);
+
+        // pixel coordinates beyond u32 do not overflow
+        let geometry = PixelGeometry::new(&matrix, u32::MAX / 2, 3);
+        assert_eq!(geometry.total_size(), None);
+        assert_eq!(geometry.symbol_bounds(), None);
+        assert_eq!(geometry.pixel_to_module((u32::MAX, u32::MAX)), None);
+        // the symbol itself starts beyond u32
+        let geometry = PixelGeometry::new(&matrix, 2, 3).with_offset((u32::MAX - 3, 0));
+        assert_eq!(geometry.module_to_pixel((0, 0)), None);
+        assert_eq!(geometry.area_to_pixels((0, 0, 1)), None);
+        assert_eq!(geometry.pixel_to_module((u32::MAX, 6)), None);
+        assert_eq!(PixelGeometry::new(&matrix, 2, 3).area_to_pixels((0, 0, u32::MAX)), None);
+    }
+
+    #[test]
+    fn test_render_gray_into() {
+        let mut matrix = Matrix::new(11, Module::Light);
+        matrix.set((0, 0), Module::Dark);
+        matrix.set((10, 10), Module::Dark);
+
+        // same pixels as the image renderer, rows padded to the stride are not touched
+        let opts = GrayBufferOptions::new(2, 3);
+        let mut buf = vec![7u8; 48 * 45];
+        render_gray_into(&matrix, &mut buf, 45, 45, 48, opts).unwrap();
+        let mut img = image::GrayImage::from_pixel(45, 45, image::Luma([0]));
+        render_matrix_into(&matrix, 2, &mut img, (0, 0), 3, [BIT_WHITE, BIT_BLACK]).unwrap();
+        for (x, y, p) in img.enumerate_pixels() {
+            assert_eq!(buf[y as usize * 48 + x as usize], p[0]);
+        }
+        assert!(buf.chunks(48).all(|row| row[45..] == [7, 7, 7]));
+
+        // the buffer must hold the rows, and the rows the symbol
+        assert_eq!(render_gray_into(&matrix, &mut buf[..48 * 44 + 44], 45, 45, 48, opts),
+                   Err(QrError::InvalidBufferLayout { width: 45, height: 45, stride: 48, len: 48 * 44 + 44 }));
+        assert_eq!(render_gray_into(&matrix, &mut buf, 45, 45, 40, opts),
+                   Err(QrError::InvalidBufferLayout { width: 45, height: 45, stride: 40, len: 48 * 45 }));
+        assert_eq!(render_gray_into(&matrix, &mut buf, 44, 45, 48, opts),
+                   Err(QrError::RegionOutOfBounds { x: 0, y: 0, size: 45 }));
+        assert_eq!(render_gray_into(&matrix, &mut buf, 45, 45, 48, GrayBufferOptions::new(2, u32::MAX / 4)),
+                   Err(QrError::RegionOutOfBounds { x: 0, y: 0, size: u32::MAX }));
+    }
+
+    #[test]
+    fn test_diagnostic_colors() {
+        let canvas = crate::serialization::create_qr_canvas(crate::config::Size::Standard(2));
+        let img = render_matrix_diagnostic(&canvas, 4, DiagnosticColors::default()).unwrap();
+
+        // timing patterns start dark, the alignment pattern is centered at (18, 18)
+        assert_eq!(img[(4 + 8, 4 + 6)], DIAGNOSTIC_TIMING[1]);
+        assert_eq!(img[(4 + 6, 4 + 9)], DIAGNOSTIC_TIMING[0]);
+        assert_eq!(img[(4 + 18, 4 + 18)], DIAGNOSTIC_ALIGNMENT[1]);
+        assert_eq!(img[(4 + 17, 4 + 18)], DIAGNOSTIC_ALIGNMENT[0]);
+        assert_eq!(img[(4, 4)], image::Rgb([0, 0, 0]));
+
+        // custom colors
+        let colors = DiagnosticColors { timing: [image::Rgb([0, 255, 0]), image::Rgb([0, 100, 0])], ..DiagnosticColors::default() };
+        let img = render_matrix_diagnostic(&canvas, 4, colors).unwrap();
+        assert_eq!(img[(4 + 8, 4 + 6)], image::Rgb([0, 100, 0]));
+        assert_eq!(img[(4 + 18, 4 + 18)], DIAGNOSTIC_ALIGNMENT[1]);
+
+        // the size is derived from the matrix
+        assert_eq!(render_matrix_diagnostic(&Matrix::new(20, Module::Light), 4, colors),
+                   Err(QrError::InvalidMatrixWidth(20)));
+    }
+
+    #[test]
+    fn test_vector_options() {
+        let matrix = Matrix::new(21, Module::Light);
+        let options = VectorOptions::new(Unit::Millimeter, 0.5).with_bleed(3.0);
+        assert_eq!(options.total_size(&matrix, 4), 29.0 * 0.5 + 6.0);
+        assert_eq!(options.matrix_offset(4), 5.0);
+
+        assert_eq!(format_length(14.5), "14.5");
+        assert_eq!(format_length(21.0), "21");
+        assert_eq!(format_length(0.1 * 3.0), "0.3");
+        assert_eq!(format_length(72.0 / 25.4), "2.8346");
+    }
+}
@@ -0,0 +1,66 @@
+//! Encapsulated PostScript output. PostScript works in points, so all lengths
+//! from the options are converted from their unit into points.
+use super::*;
+
+
+/// Render a module matrix as an EPS document, surrounded by a light quiet region
+/// of the given width (in modules) and the bleed given in the options.
+pub fn render_eps(matrix: &Matrix, quiet_zone: u32, options: &VectorOptions) -> String {
+    let scale = options.unit.points();
+    let total = options.total_size(matrix, quiet_zone) * scale;
+    let offset = options.matrix_offset(quiet_zone) * scale;
+    let module_size = options.module_size * scale;
+    let w = matrix.width();
+
+    let mut eps = String::new();
+    eps.push_str("%!PS-Adobe-3.0 EPSF-3.0\n");
+    eps.push_str(&format!("%%BoundingBox: 0 0 {t} {t}\n", t = total.ceil() as u32));
+    eps.push_str(&format!("%%HiResBoundingBox: 0 0 {t} {t}\n", t = format_length(total)));
+    eps.push_str("%%EndComments\n");
+
+    // light background, covering quiet region and bleed
+    eps.push_str("1 setgray\n");
+    eps.push_str(&format!("0 0 {t} {t} rectfill\n", t = format_length(total)));
+
+    // one square per dark module. The y axis of PostScript points upwards,
+    // so rows are counted from the top edge of the artwork.
+    eps.push_str("0 setgray\n");
+    for (x, y, module) in matrix.enumerate_modules() {
+        if module.is_dark() {
+            eps.push_str(&format!("{} {} {s} {s} rectfill\n",
+                format_length(offset + x as f64 * module_size),
+                format_length(offset + (w - 1 - y) as f64 * module_size),
+                s = format_length(module_size)));
+        }
+    }
+
+    eps.push_str("showpage\n%%EOF\n");
+    eps
+}
+
+/// Render a finished symbol as an EPS document, including the quiet region
+/// required for its size.
+pub fn to_eps(code: &QrCode, options: &VectorOptions) -> String {
+    render_eps(&code.matrix, code.quiet_zone(), options)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eps_units() {
+        let mut matrix = Matrix::new(11, Module::Light);
+        matrix.set((0, 0), Module::Dark);
+
+        // 1/72 inch per module gives exactly one point per module
+        let eps = render_eps(&matrix, 2, &VectorOptions::new(Unit::Inch, 1.0 / 72.0).with_bleed(0.25));
+        assert!(eps.contains("%%BoundingBox: 0 0 51 51\n"));
+        assert!(eps.contains("\n20 30 1 1 rectfill\n"));
+
+        let eps = render_eps(&matrix, 2, &VectorOptions::new(Unit::Millimeter, 1.0));
+        assert!(eps.contains("%%BoundingBox: 0 0 43 43\n"));
+        assert!(eps.contains("%%HiResBoundingBox: 0 0 42.5197 42.5197\n"));
+    }
+}
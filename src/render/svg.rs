@@ -0,0 +1,64 @@
+//! SVG output. The root element carries the physical size of the artwork and the
+//! view box uses the same unit, so all module coordinates are real-world lengths.
+use super::*;
+
+
+/// Render a module matrix as an SVG document, surrounded by a light quiet region
+/// of the given width (in modules) and the bleed given in the options.
+pub fn render_svg(matrix: &Matrix, quiet_zone: u32, options: &VectorOptions) -> String {
+    let total = format_length(options.total_size(matrix, quiet_zone));
+    let unit = options.unit.suffix();
+    let offset = options.matrix_offset(quiet_zone);
+    let module_size = format_length(options.module_size);
+
+    let mut svg = String::new();
+    svg.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{t}{u}\" height=\"{t}{u}\" viewBox=\"0 0 {t} {t}\">\n",
+        t = total, u = unit));
+
+    // light background, covering quiet region and bleed
+    svg.push_str(&format!("<rect x=\"0\" y=\"0\" width=\"{t}\" height=\"{t}\" fill=\"#fff\"/>\n", t = total));
+
+    // one square per dark module
+    for (x, y, module) in matrix.enumerate_modules() {
+        if module.is_dark() {
+            svg.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" fill=\"#000\"/>\n",
+                format_length(offset + x as f64 * options.module_size),
+                format_length(offset + y as f64 * options.module_size),
+                s = module_size));
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Render a finished symbol as an SVG document, including the quiet region
+/// required for its size.
+pub fn to_svg(code: &QrCode, options: &VectorOptions) -> String {
+    render_svg(&code.matrix, code.quiet_zone(), options)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_units() {
+        let mut matrix = Matrix::new(11, Module::Light);
+        matrix.set((0, 0), Module::Dark);
+        matrix.set((10, 1), Module::Dark);
+
+        let svg = render_svg(&matrix, 2, &VectorOptions::new(Unit::Millimeter, 0.5).with_bleed(2.0));
+        assert!(svg.contains("width=\"11.5mm\" height=\"11.5mm\" viewBox=\"0 0 11.5 11.5\""));
+        assert!(svg.contains("<rect x=\"3\" y=\"3\" width=\"0.5\" height=\"0.5\" fill=\"#000\"/>"));
+        assert!(svg.contains("<rect x=\"8\" y=\"3.5\" width=\"0.5\" height=\"0.5\" fill=\"#000\"/>"));
+        assert_eq!(svg.matches("fill=\"#000\"").count(), 2);
+
+        let svg = render_svg(&matrix, 2, &VectorOptions::new(Unit::Inch, 0.02));
+        assert!(svg.contains("width=\"0.3in\""));
+    }
+}
@@ -1,46 +1,37 @@
 use bitstream_io::{BigEndian, BitRead, BitReader};
-use image;
 
 use std::cmp;
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
+use crate::blocks::{block_lengths, interleaving_order};
 use crate::config::{ECCLevel, Size};
-
-// CONSTANTS
-pub const MARKER_ENCODING_REGION: image::Luma<u8> = image::Luma([100u8]);
-pub const MARKER_FORMAT_INFORMATION: image::Luma<u8> = image::Luma([120u8]);
-pub const MARKER_VERSION_INFORMATION: image::Luma<u8> = image::Luma([140u8]);
-
-pub const BIT_WHITE: image::Luma<u8> = image::Luma([255u8]);
-pub const BIT_BLACK: image::Luma<u8> = image::Luma([0u8]);
+use crate::error::{QrError, QrResult};
+use crate::matrix::{Matrix, Module};
+use crate::tables::SymbolCapacity;
 
 
 pub mod masking;
 
-/// Creates a finder pattern image (concentric squares
-/// including the white separator around the finder
-/// pattern)
-fn create_finder_pattern() -> image::GrayImage {
-    image::GrayImage::from_fn(9,9, |x, y| {
-        let r = cmp::max((x as i32 - 4).abs(), (y as i32 - 4).abs());
-        if r < 2 || r == 3 {
-            BIT_BLACK
-        } else {
-            BIT_WHITE
+/// Paints a finder pattern (concentric squares including
+/// the light separator around the finder pattern) with its
+/// upper left corner (of the separator) at (x, y)
+fn paint_finder_pattern(canvas: &mut Matrix, x: i32, y: i32) {
+    for i in 0..9i32 {
+        for j in 0..9i32 {
+            let r = cmp::max((i - 4).abs(), (j - 4).abs());
+            canvas.set_clipped(x + i, y + j, Module::from_bit(r < 2 || r == 3));
         }
-    })
+    }
 }
 
-/// Creates an alignment pattern image
-fn create_alignment_pattern() -> image::GrayImage {
-    image::GrayImage::from_fn(5, 5, |x, y| {
-        let r = cmp::max((x as i32 - 2).abs(), (y as i32 - 2).abs());
-        if r % 2 == 0 {
-            BIT_BLACK
-        } else {
-            BIT_WHITE
+/// Paints an alignment pattern centered at (x, y)
+fn paint_alignment_pattern(canvas: &mut Matrix, x: i32, y: i32) {
+    for i in -2..=2i32 {
+        for j in -2..=2i32 {
+            let r = cmp::max(i.abs(), j.abs());
+            canvas.set_clipped(x + i, y + j, Module::from_bit(r % 2 == 0));
         }
-    })
+    }
 }
 
 /// Creates a vector with alignment coordinates, i.e. the
@@ -48,18 +39,18 @@ fn create_alignment_pattern() -> image::GrayImage {
 fn create_alignment_pattern_coord_list(size: u8) -> Vec<i32> {
     let mut row = Vec::new();
     row.push(6);
-    if size >= 2 && size < 7 {
+    if (2..7).contains(&size) {
         row.push((size as i32 - 2) * 4 + 18);
-    } else if size >= 7 && size < 14  {
+    } else if (7..14).contains(&size) {
         row.push((size as i32 - 7) * 2 + 22);
         row.push((size as i32 - 7) * 4 + 38);
-    } else if size >= 14 && size < 21  {
+    } else if (14..21).contains(&size) {
         let a = ((size as i32 - 14) / 3) * 4 + 26;
         let b = (size as i32 - 14) * 4 + 66;
         row.push(a);
         row.push((a+b) / 2);
         row.push(b);
-    } else if size >= 21 && size < 28 {
+    } else if (21..28).contains(&size) {
         let b = ((size as i32 - 21) / 2) * 4 + 50;
         let d = (size as i32 - 21) * 4 + 94;
         row.push(match size {
@@ -75,7 +66,7 @@ fn create_alignment_pattern_coord_list(size: u8) -> Vec<i32> {
         row.push(b);
         row.push((b+d) / 2);
         row.push(d);
-    } else if size >= 28 && size < 35 {
+    } else if (28..35).contains(&size) {
         row.extend_from_slice(match size {
             28 => &[26, 50, 74, 98, 122],
             29 => &[30, 54, 78, 102, 126],
@@ -86,7 +77,7 @@ fn create_alignment_pattern_coord_list(size: u8) -> Vec<i32> {
             34 => &[34, 62, 90, 118, 146],
             _ => panic!("Can never get here")
         });
-    } else if size >= 35 && size <= 40 {
+    } else if (35..=40).contains(&size) {
         row.extend_from_slice(match size {
             35 => &[30, 54, 78, 102, 126, 150],
             36 => &[24, 50, 76, 102, 128, 154],
@@ -109,8 +100,7 @@ fn get_alignment_pattern_points(size: u8) -> Vec<(i32, i32)> {
     let mut points = Vec::new();
     for (i, &s) in coords[..].iter().enumerate() {
         for (j, &t) in coords[..].iter().enumerate() {
-            if (i == 0 && j == 0) ||
-               (i == 0 && j == last_coord_index) ||
+            if (i == 0 && (j == 0 || j == last_coord_index)) ||
                (i == last_coord_index && j == 0) {
                 continue;
             }
@@ -121,277 +111,408 @@ fn get_alignment_pattern_points(size: u8) -> Vec<(i32, i32)> {
 }
 
 
-/// Return a basic QR image with all the functional patterns
+/// Return the positions of all modules of the timing patterns, excluding the modules
+/// which are covered by alignment patterns.
+pub fn timing_pattern_positions(size: Size) -> Vec<(u32, u32)> {
+    let s = size.dimensions();
+    let (t_off, t_start, t_end) = if size.is_micro() { (0, 8, s) } else { (6, 6, s - 8) };
+    let alignment = alignment_pattern_positions(size);
+
+    (t_start..t_end)
+        .flat_map(|i| vec![(t_off, i), (i, t_off)])
+        .filter(|pos| !alignment.contains(pos))
+        .collect()
+}
+
+/// Return the positions of all modules of the alignment patterns
+pub fn alignment_pattern_positions(size: Size) -> Vec<(u32, u32)> {
+    if size.is_micro() || size.version() < 2 {
+        return Vec::new();
+    }
+    get_alignment_pattern_points(size.version()).into_iter()
+        .flat_map(|(x, y)| (-2..=2).flat_map(move |i| (-2..=2).map(move |j| ((x + i) as u32, (y + j) as u32))))
+        .collect()
+}
+
+
+/// Return a basic QR module matrix with all the functional patterns
 /// painted in: the finder patterns, alignment patterns
 /// and timing patterns.
 ///
-/// During the assembly of the QR code pixel matrix
-/// there are different value codes used as pixel values
-/// to indicate pixels that will be filled in later.
-/// As such those later stages can identify those pixels
-/// easier. Final values are only 0 (black) and 255 (white).
-/// All other values are codes, and are used in the following way:
-///   100: the encoding region, which receives the binary code
-///   120: marks the format information bits (stripes along finders),
+/// During the assembly of the QR code module matrix
+/// there are reserved modules which will be filled in later.
+/// As such those later stages can identify those modules
+/// easier. The reserved modules are marked in the following way:
+///   EncodingRegion: the encoding region, which receives the binary code
+///   FormatInformation: marks the format information bits (stripes along finders),
 ///        2x 15 bits
-///   140: marks the version information bits (blocks near upper
+///   VersionInformation: marks the version information bits (blocks near upper
 ///        right and lower left finder) 2x 18bits
 ///        (only present in codes of version 7 or up)
-pub fn create_qr_canvas(size: Size) -> image::GrayImage {
-    let q = size.quiet_region_size();
-    let s =  size.dimensions() + 2 * q;
-    let mut mask = image::GrayImage::from_pixel(s, s, MARKER_ENCODING_REGION);
-
-    // mark quiet area
-    for i in 0..s {
-        for j in 0..q {
-            mask[(j, i)] = BIT_WHITE;
-            mask[(i, j)] = BIT_WHITE;
-            mask[(s - j - 1, i)] = BIT_WHITE;
-            mask[(i, s - j - 1)] = BIT_WHITE;
-        }
-    }
-
-    // apply finder patterns
-    let finder = create_finder_pattern();
-    image::imageops::overlay(&mut mask, &finder, q - 1, q - 1);
+///
+/// Panics for invalid sizes, see try_create_qr_canvas.
+pub fn create_qr_canvas(size: Size) -> Matrix {
+    let s = size.dimensions();
+    let mut canvas = Matrix::new(s, Module::EncodingRegion);
+
+    // apply finder patterns. The upper left one is cut off at the quiet region,
+    // as its separator is only needed on the sides facing the symbol
+    paint_finder_pattern(&mut canvas, -1, -1);
     if !size.is_micro() {
-        image::imageops::overlay(&mut mask, &finder, q - 1, s - 12);
-        image::imageops::overlay(&mut mask, &finder, s - 12, q - 1);
+        paint_finder_pattern(&mut canvas, -1, s as i32 - 8);
+        paint_finder_pattern(&mut canvas, s as i32 - 8, -1);
     }
 
     // mark timing patterns
     {
-        let t_off = if size.is_micro() { 2 } else { 10 };
-        let s_end = if size.is_micro() { s - 2 } else { s - 12 };
+        let (t_off, t_start, t_end) = if size.is_micro() { (0, 8, s) } else { (6, 6, s - 8) };
 
-        for i in 10..s_end {
-            let val = if i % 2 == 0 {BIT_BLACK} else {BIT_WHITE};
-            mask[(t_off, i)] = val;
-            mask[(i, t_off)] = val;
+        for i in t_start..t_end {
+            let val = Module::from_bit(i % 2 == 0);
+            canvas.set((t_off, i), val);
+            canvas.set((i, t_off), val);
         }
     }
 
     // alignment patterns only for version >= 2
     if !size.is_micro() && size.version() >= 2 {
         // retrieve point list of alignment pattern center points
-        let points = get_alignment_pattern_points(size.version());
-        // get a pattern image
-        let pattern = create_alignment_pattern();
-        // paint them onto canvas
-        for (x, y) in points {
-            // the offset +2 we get by +4 from the quiet border
-            // and -2 from the pattern center offset
-            image::imageops::overlay(&mut mask, &pattern, x as u32 + 2, y as u32 + 2);
+        // and paint them onto canvas
+        for (x, y) in get_alignment_pattern_points(size.version()) {
+            paint_alignment_pattern(&mut canvas, x, y);
         }
     }
 
-    // mark format bits
-    if size.is_micro() {
-        for i in 3..11 {
-            mask[(10, i)] = MARKER_FORMAT_INFORMATION;
-            mask[(i, 10)] = MARKER_FORMAT_INFORMATION;
-        }
-    } else {
-        for i in 0..6 {
-            mask[(12, 4+i)] = MARKER_FORMAT_INFORMATION;
-            mask[(4+i, 12)] = MARKER_FORMAT_INFORMATION;
-            mask[(s-5-i, 12)] = MARKER_FORMAT_INFORMATION;
-            mask[(12, s-5-i)] = MARKER_FORMAT_INFORMATION;
+    // mark format and version bits
+    let placement = info_placement(size).expect("Invalid symbol size");
+    for &pos in placement.format.iter().flatten().chain(placement.dark_module.iter()) {
+        canvas.reserve(pos, Module::FormatInformation);
+    }
+    for &pos in placement.version.iter().flatten() {
+        canvas.reserve(pos, Module::VersionInformation);
+    }
+
+    // return canvas
+    canvas
+}
+
+/// Same as create_qr_canvas, but returns an error for sizes that do not exist
+pub fn try_create_qr_canvas(size: Size) -> QrResult<Matrix> {
+    if !size.is_valid() {
+        return Err(QrError::InvalidSize(size));
+    }
+    Ok(create_qr_canvas(size))
+}
+
+
+/// Return the positions of all modules of the encoding region of a canvas created by the
+/// create_qr_canvas function, in the order in which the codeword bits are placed (see 7.7.3).
+///
+/// The symbol is traversed in columns of two modules width, starting in the lower right corner
+/// and moving alternately upwards and downwards. In standard symbols the vertical timing pattern
+/// is skipped entirely, i.e. the column left of it becomes the right column of the next pair.
+pub fn encoding_region_positions(canvas: &Matrix, size: Size) -> Vec<(u32, u32)> {
+    placement_order(canvas, if size.is_micro() { None } else { Some(6) })
+}
+
+// positions of the encoding region in placement order, skipping the given column entirely
+fn placement_order(canvas: &Matrix, skipped_column: Option<i32>) -> Vec<(u32, u32)> {
+    let w = canvas.width() as i32;
+    let mut positions = Vec::new();
+
+    let mut right = w - 1;      // x coordinate of the right column in the current pair
+    let mut upwards = true;
+    while right >= 0 {
+        if Some(right) == skipped_column {
+            right -= 1;
         }
-        mask[(12, 11)] = MARKER_FORMAT_INFORMATION;
-        mask[(11, 12)] = MARKER_FORMAT_INFORMATION;
-        mask[(12, 12)] = MARKER_FORMAT_INFORMATION;
-        mask[(12, s-11)] = MARKER_FORMAT_INFORMATION;
-        mask[(12, s-12)] = MARKER_FORMAT_INFORMATION;
-        mask[(s-11, 12)] = MARKER_FORMAT_INFORMATION;
-        mask[(s-12, 12)] = MARKER_FORMAT_INFORMATION;
-    }
-
-    // mark version bits if applicable
-    if !size.is_micro() && size.version() >= 7 {
-        for i in 0..6 {
-            for j in 0..3 {
-                mask[(q+i, s-13-j)] = MARKER_VERSION_INFORMATION;
-                mask[(s-13-j, q+i)] = MARKER_VERSION_INFORMATION;
+
+        for i in 0..w {
+            let y = if upwards { w - 1 - i } else { i };
+            for x in [right, right - 1] {
+                if canvas.get(x, y) == Some(Module::EncodingRegion) {
+                    positions.push((x as u32, y as u32));
+                }
             }
         }
+
+        upwards = !upwards;
+        right -= 2;
     }
 
-    // return canvas
-    mask
+    positions
 }
 
 
 /// Insert the data into the encoding region of a QR canvas created by the create_qr_canvas function
 ///
-pub fn insert_data_payload(canvas: &mut image::GrayImage, size: Size, data_words: &[u8], ecc_words: &[u8]) {
-    // the variables used to step through the cells/modules of the QR symbol.
-    // x_step inverts from 1 to -1 and back in each step, no matter whether the symbol could be placed or not,
-    // y_step inverts only when reaching the borders of the symbol.
-    let mut x_step: i32 = -1;
-    let mut y_step: i32 = -1;
+pub fn insert_data_payload(canvas: &mut Matrix, size: Size, data_words: &[u8], ecc_words: &[u8]) -> QrResult<()> {
+    let positions = encoding_region_positions(canvas, size);
+
+    // the number of bits to read from the data_words. For M1 and M3, only the first four bits of
+    // the last byte is used.
+    let data_bits = match size {
+        Size::Micro(1) | Size::Micro(3) => data_words.len() * 8 - 4,
+        _ => data_words.len() * 8
+    };
+    fill_encoding_region(canvas, positions, data_words, data_bits, ecc_words)
+}
 
-    let mut x_cur: i32 = size.quiet_region_size() as i32 + size.dimensions() as i32 - 1;
-    let mut y_cur: i32 = x_cur;  // the symbol is square, and we start off from the lower right corner
+// place the data bits followed by the ecc bits at the given positions, and zero out the rest
+fn fill_encoding_region(canvas: &mut Matrix,
+                        positions: Vec<(u32, u32)>,
+                        data_words: &[u8],
+                        data_bits: usize,
+                        ecc_words: &[u8]) -> QrResult<()> {
+    let ecc_bits = ecc_words.len() * 8;
+    if data_bits + ecc_bits > positions.len() {
+        return Err(QrError::DataTooLong { bits: data_bits + ecc_bits, capacity: positions.len(), suggestion: None });
+    }
+    let mut positions = positions.into_iter();
 
-    // write all data bits
-    {
-        // the number of bits to read from the data_words. For M1 and M3, only the first four bits of
-        // the last byte is used.
-        let bits_to_read = match size {
-            Size::Micro(1) | Size::Micro(3) => data_words.len() * 8 - 4,
-            _ => data_words.len() * 8
-        };
+    // create readers and place all data bits, followed by all ecc bits
+    let mut data_reader = BitReader::endian(Cursor::new(&data_words), BigEndian);
+    let mut ecc_reader = BitReader::endian(Cursor::new(&ecc_words), BigEndian);
 
-        // create reader and start the process
-        let mut reader =  BitReader::endian(Cursor::new(&data_words), BigEndian);
+    let bits = (0..data_bits).map(|_| data_reader.read_bit().unwrap())
+        .chain((0..ecc_bits).map(|_| ecc_reader.read_bit().unwrap()));
 
-        for _i in 0..bits_to_read {
-            let bit = reader.read_bit().unwrap();
+    for (bit, pos) in bits.zip(positions.by_ref()) {
+        canvas.fill_reserved(pos, Module::EncodingRegion, bit);
+    }
 
-            // place bit
-            canvas[(x_cur as u32, y_cur as u32)] = if bit { BIT_BLACK } else { BIT_WHITE };
+    // if there are still encoding region modules left, zero them out (remainder bits)
+    for pos in positions {
+        canvas.fill_reserved(pos, Module::EncodingRegion, false);
+    }
+    Ok(())
+}
 
-            // find next valid place for next bit
-            loop {
-                // check next candidate. Next step is either applying
-                // just x_step (if it is negative) or both x_step and y_step (if x_step is positive)
-                if x_step == -1 {
-                    x_cur = x_cur + x_step;
-                } else {
-                    x_cur = x_cur + x_step;
-                    y_cur = y_cur + y_step;
-                }
+//-------------------------------------------------------------------
+// CUSTOM LAYOUTS
+//-------------------------------------------------------------------
 
-                // flip x_step
-                x_step = -x_step;
-
-                // see if we need to turn around on the borders
-                if y_cur < 0 {
-                    y_cur = 0;
-                    y_step = 1;
-                    x_cur = x_cur - 2;
-                } else if y_cur >= canvas.height() as i32 {
-                    y_cur = canvas.height() as i32 - 1;
-                    y_step = -1;
-                    x_cur = x_cur - 2;
-                }
+/// Function patterns of a hypothetical symbol, for experiments with the placement and masking.
+/// Symbols built on such a canvas are NOT conformant to the standard, and scanners will not
+/// read them. No format or version information is reserved.
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct CustomLayout {
+    pub width: u32,
+    pub finders: Vec<(i32, i32)>,       // upper left corners of the finder patterns, including the separator
+    pub alignments: Vec<(i32, i32)>,    // centers of the alignment patterns
+    pub timing: Option<u32>             // row and column of the timing patterns, None for no timing patterns
+}
 
-                // if x_cur is negative, there is no chance of finding another
-                // valid encoding pixel
-                if x_cur < 0 {
-                    // this should never happen here, since the EC blocks are not even placed yet!
-                    panic!("Should never get here!");
-                }
+impl CustomLayout {
+    /// Return the function patterns of a standard size, as a starting point for modifications
+    pub fn of_size(size: Size) -> CustomLayout {
+        let s = size.dimensions() as i32;
+        let alignments = if size.is_micro() || size.version() < 2 {
+            Vec::new()
+        } else {
+            get_alignment_pattern_points(size.version())
+        };
+        CustomLayout {
+            width: s as u32,
+            finders: if size.is_micro() { vec![(-1, -1)] } else { vec![(-1, -1), (-1, s - 8), (s - 8, -1)] },
+            alignments,
+            timing: Some(if size.is_micro() { 0 } else { 6 })
+        }
+    }
+}
 
-                if canvas[(x_cur as u32, y_cur as u32)] == MARKER_ENCODING_REGION {
-                    // found a valid pixel!
-                    break;
+/// Return a canvas with the function patterns of the custom layout painted in, and everything
+/// else marked as encoding region. Timing patterns run along the whole row and column, but do not
+/// cover finder and alignment patterns.
+pub fn create_custom_canvas(layout: &CustomLayout) -> Matrix {
+    let mut canvas = Matrix::new(layout.width, Module::EncodingRegion);
+    for &(x, y) in layout.finders.iter() {
+        paint_finder_pattern(&mut canvas, x, y);
+    }
+    for &(x, y) in layout.alignments.iter() {
+        paint_alignment_pattern(&mut canvas, x, y);
+    }
+    if let Some(t) = layout.timing.filter(|&t| t < layout.width) {
+        for i in 0..layout.width {
+            let val = Module::from_bit(i % 2 == 0);
+            for pos in [(t, i), (i, t)] {
+                if canvas[pos] == Module::EncodingRegion {
+                    canvas.set(pos, val);
                 }
-                // else: go on looping
             }
         }
     }
+    canvas
+}
 
-    // now write all ECC bits. Very similar to data bits. We will also just take the current position
-    // x_cur, y_cur and just go on from there.
-    {
-        // the number of bits to read from the data_words. For M1 and M3, only the first four bits of
-        // the last byte is used.
-        let bits_to_read = ecc_words.len() * 8;
+/// Same as encoding_region_positions for a canvas created by create_custom_canvas. The column of
+/// the vertical timing pattern is skipped, like in standard symbols.
+pub fn custom_encoding_region_positions(canvas: &Matrix, layout: &CustomLayout) -> Vec<(u32, u32)> {
+    placement_order(canvas, layout.timing.map(|t| t as i32))
+}
 
-        // create reader and start the process
-        let mut reader =  BitReader::endian(Cursor::new(&ecc_words), BigEndian);
+/// Same as insert_data_payload for a canvas created by create_custom_canvas. All codewords
+/// are placed with their full eight bits.
+pub fn insert_custom_data_payload(canvas: &mut Matrix, layout: &CustomLayout, data_words: &[u8], ecc_words: &[u8]) -> QrResult<()> {
+    let positions = custom_encoding_region_positions(canvas, layout);
+    fill_encoding_region(canvas, positions, data_words, data_words.len() * 8, ecc_words)
+}
 
-        for _i in 0..bits_to_read {
-            let bit = reader.read_bit().unwrap();
+/// Extension point for symbols beyond the standard sizes, e.g. a family defined by another
+/// crate: the function patterns and the capacity with the block structure at each ECC level.
+/// The standard sizes implement it as well. See create_custom_symbol for building symbols.
+pub trait SymbolFamily {
+    /// Function patterns of the symbol
+    fn layout(&self) -> CustomLayout;
 
-            // place bit
-            canvas[(x_cur as u32, y_cur as u32)] = if bit { BIT_BLACK } else { BIT_WHITE };
+    /// Capacity and block structure at the given level, or an error if the symbol
+    /// does not support the level
+    fn capacity(&self, level: ECCLevel) -> QrResult<SymbolCapacity>;
+}
 
-            // find next valid place for next bit
-            loop {
-                // check next candidate. Next step is either applying
-                // just x_step (if it is negative) or both x_step and y_step (if x_step is positive)
-                if x_step == -1 {
-                    x_cur = x_cur + x_step;
-                } else {
-                    x_cur = x_cur + x_step;
-                    y_cur = y_cur + y_step;
-                }
+impl SymbolFamily for Size {
+    fn layout(&self) -> CustomLayout {
+        CustomLayout::of_size(*self)
+    }
 
-                // flip x_step
-                x_step = -x_step;
-
-                // see if we need to turn around on the borders
-                if y_cur < 0 {
-                    y_cur = 0;
-                    y_step = 1;
-                    x_cur = x_cur - 2;
-                } else if y_cur >= canvas.height() as i32 {
-                    y_cur = canvas.height() as i32 - 1;
-                    y_step = -1;
-                    x_cur = x_cur - 2;
-                }
+    fn capacity(&self, level: ECCLevel) -> QrResult<SymbolCapacity> {
+        crate::tables::try_lookup_capacity(*self, level)
+    }
+}
 
-                // if x_cur is negative, there is no chance of finding another
-                // valid encoding pixel
-                if x_cur < 0 {
-                    // we can only get here under normal circumstances if the total number of
-                    // codewords fits the symbol exactly, ie. there are no zero padding bits.
-                    break;
-                }
+//-------------------------------------------------------------------
+// CODEWORD PLACEMENT MAP
+//-------------------------------------------------------------------
 
-                if canvas[(x_cur as u32, y_cur as u32)] == MARKER_ENCODING_REGION {
-                    // found a valid pixel!
-                    break;
-                }
-                // else: go on looping
-            }
-        }
-    }
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum CodewordKind {
+    Data,
+    Ecc
+}
 
-    if x_cur > 0 {
-        // if there are still encoding region bits, find the rest of them and zero them out (padding)
-        loop {
-            if canvas[(x_cur as u32, y_cur as u32)] == MARKER_ENCODING_REGION {
-                // found a valid pixel!
-                // set to zero
-                canvas[(x_cur as u32, y_cur as u32)] = BIT_WHITE;
-            }
+/// Location of a single codeword in the symbol
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct CodewordPlacement {
+    pub kind: CodewordKind,
+    pub block: u32,                 // index of the block the codeword belongs to
+    pub index: u32,                 // index of the codeword inside its block (before interleaving)
+    pub modules: Vec<(u32, u32)>    // modules occupied by the bits of the codeword, most significant bit first
+}
 
-            // check next candidate. Next step is either applying
-            // just x_step (if it is negative) or both x_step and y_step (if x_step is positive)
-            if x_step == -1 {
-                x_cur = x_cur + x_step;
-            } else {
-                x_cur = x_cur + x_step;
-                y_cur = y_cur + y_step;
+/// Return, for each codeword of a symbol, the modules its bits occupy in the final symbol,
+/// taking the interleaving of the blocks and the placement order into account. The codewords
+/// are returned in the order they are placed: all data codewords, followed by all ecc codewords.
+/// Note that the last data codeword of M1 and M3 symbols only occupies four modules.
+pub fn codeword_module_map(size: Size, level: ECCLevel) -> QrResult<Vec<CodewordPlacement>> {
+    let (data_lengths, ecc_lengths): (Vec<u32>, Vec<u32>) = block_lengths(size, level)?.into_iter().unzip();
+    let mut positions = encoding_region_positions(&create_qr_canvas(size), size).into_iter();
+
+    let data_order = interleaving_order(&data_lengths);
+    let ecc_order = interleaving_order(&ecc_lengths);
+
+    let num_data = data_order.len();
+    let last_word_bits = crate::tables::last_data_codeword_bits(size) as usize;
+
+    let order = data_order.into_iter().map(|pos| (CodewordKind::Data, pos))
+        .chain(ecc_order.into_iter().map(|pos| (CodewordKind::Ecc, pos)));
+    Ok(order.enumerate()
+        .map(|(i, (kind, (block, index)))| {
+            let num_bits = if i + 1 == num_data { last_word_bits } else { 8 };
+            CodewordPlacement {
+                kind,
+                block,
+                index,
+                modules: positions.by_ref().take(num_bits).collect()
             }
+        })
+        .collect())
+}
 
-            // flip x_step
-            x_step = -x_step;
-
-            // see if we need to turn around on the borders
-            if y_cur < 0 {
-                y_cur = 0;
-                y_step = 1;
-                x_cur = x_cur - 2;
-            } else if y_cur >= canvas.height() as i32 {
-                y_cur = canvas.height() as i32 - 1;
-                y_step = -1;
-                x_cur = x_cur - 2;
-            }
+/// Return the square area (x, y, edge length in modules, without quiet region) in the center
+/// of the symbol whose edge covers the given fraction of the symbol's edge, e.g. for a logo.
+pub fn centered_area(size: Size, ratio: f64) -> (u32, u32, u32) {
+    let w = size.dimensions();
+    let edge = ((ratio.max(0.0) * w as f64).ceil() as u32).min(w);
+    let offset = (w - edge) / 2;
+    (offset, offset, edge)
+}
 
-            // now we are really done
-            if x_cur < 0 {
-                break;
-            }
+/// Whether the symbol can still be read if all modules in the given square area
+/// (x, y, edge length) are obscured, see OverlayReport::is_correctable.
+pub fn area_is_correctable(size: Size, level: ECCLevel, area: (u32, u32, u32)) -> QrResult<bool> {
+    let (x, y, edge) = area;
+    overlay_report(size, level, (x, y, edge, edge)).map(|report| report.is_correctable())
+}
+
+/// A codeword with some of its bits obscured by an overlay
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct ObscuredCodeword {
+    pub kind: CodewordKind,
+    pub block: u32,
+    pub index: u32,                 // index of the codeword inside its block
+    pub bits: Vec<u32>              // obscured bits, 0 for the most significant bit
+}
+
+/// Effect of obscuring a rectangular area of a symbol, as returned by overlay_report
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct OverlayReport {
+    pub codewords: Vec<ObscuredCodeword>,   // in placement order
+    pub lost_per_block: Vec<u32>,           // number of obscured codewords of each block
+    pub correctable_per_block: u32,         // codewords the error correction repairs in each block
+    pub hits_function_info: bool            // whether format or version information is obscured
+}
+
+impl OverlayReport {
+    /// For each block, whether its error correction repairs the obscured codewords
+    pub fn correctable_blocks(&self) -> Vec<bool> {
+        self.lost_per_block.iter().map(|&n| n <= self.correctable_per_block).collect()
+    }
+
+    /// Whether the symbol can still be read. This is the case if the area leaves the format and
+    /// version information (and with them the finder patterns) intact, and every block loses no
+    /// more codewords than its error correction can repair.
+    pub fn is_correctable(&self) -> bool {
+        !self.hits_function_info && self.correctable_blocks().into_iter().all(|ok| ok)
+    }
+}
+
+/// Report which codeword bits are obscured if all modules in the given rectangle
+/// (x, y, width, height, in modules without quiet region) are covered, e.g. by a logo.
+pub fn overlay_report(size: Size, level: ECCLevel, area: (u32, u32, u32, u32)) -> QrResult<OverlayReport> {
+    let (x0, y0, width, height) = area;
+    let covered = |&(x, y): &(u32, u32)| (x0..x0 + width).contains(&x) && (y0..y0 + height).contains(&y);
+
+    // the lookups fail for sizes and levels that do not exist, before the canvas is created
+    let placements = codeword_module_map(size, level)?;
+    let correctable_per_block = crate::tables::try_correction_capacity(size, level)?.correctable_codewords_per_block;
+    let num_blocks = crate::tables::try_lookup_capacity(size, level)?.num_blocks() as usize;
+
+    let canvas = create_qr_canvas(size);
+    let hits_function_info = canvas.enumerate_modules()
+        .any(|(x, y, m)| (m == Module::FormatInformation || m == Module::VersionInformation) && covered(&(x, y)));
+
+    let mut lost_per_block = vec![0u32; num_blocks];
+    let mut codewords = Vec::new();
+    for placement in placements {
+        let bits: Vec<u32> = (0..).zip(placement.modules.iter())
+            .filter(|(_, module)| covered(module))
+            .map(|(bit, _)| bit)
+            .collect();
+        if !bits.is_empty() {
+            lost_per_block[placement.block as usize] += 1;
+            codewords.push(ObscuredCodeword { kind: placement.kind, block: placement.block, index: placement.index, bits });
         }
     }
+
+    Ok(OverlayReport {
+        codewords,
+        lost_per_block,
+        correctable_per_block,
+        hits_function_info
+    })
 }
 
+
 //-------------------------------------------------------------------
 // FORMAT & VERSION INFO BITS
 //-------------------------------------------------------------------
@@ -399,74 +520,139 @@ pub fn insert_data_payload(canvas: &mut image::GrayImage, size: Size, data_words
 // micro QR codes. See table C.1 in Annex C of the standard.
 // The value of the 5 data bits is the index into the lookup table.
 
-const FORMAT_INFOS_QR: [u16; 32] = [
+pub const FORMAT_INFOS_QR: [u16; 32] = [
     0x5412, 0x5125, 0x5e7c, 0x5b4b, 0x45f9, 0x40ce, 0x4f97, 0x4aa0, 0x77c4, 0x72f3, 0x7daa, 0x789d, 0x662f, 0x6318,
     0x6c41, 0x6976, 0x1689, 0x13be, 0x1ce7, 0x19d0, 0x0762, 0x0255, 0x0d0c, 0x083b, 0x355f, 0x3068, 0x3f31, 0x3a06,
     0x24b4, 0x2183, 0x2eda, 0x2bed,
 ];
 
-const FORMAT_INFOS_MICRO_QR: [u16; 32] = [
+pub const FORMAT_INFOS_MICRO_QR: [u16; 32] = [
     0x4445, 0x4172, 0x4e2b, 0x4b1c, 0x55ae, 0x5099, 0x5fc0, 0x5af7, 0x6793, 0x62a4, 0x6dfd, 0x68ca, 0x7678, 0x734f,
     0x7c16, 0x7921, 0x06de, 0x03e9, 0x0cb0, 0x0987, 0x1735, 0x1202, 0x1d5b, 0x186c, 0x2508, 0x203f, 0x2f66, 0x2a51,
     0x34e3, 0x31d4, 0x3e8d, 0x3bba,
 ];
 
-// lookup table for version info bits, works similar to format info
-static VERSION_INFOS: [u32; 34] = [
+// generator polynomial of the BCH(15,5) code of the format information, see Annex C
+const FORMAT_INFO_GENERATOR: u32 = 0b101_0011_0111;
+// XOR masks of the format information, so that it is never all zero
+pub const FORMAT_INFO_MASK_QR: u16 = 0x5412;
+pub const FORMAT_INFO_MASK_MICRO_QR: u16 = 0x4445;
+
+/// Compute the BCH(15,5) code of the lower 5 bits of the given value: the data bits followed by
+/// the 10 ECC bits, without the XOR mask. The lookup tables hold the masked codes of all values.
+pub fn bch_format_code(data: u8) -> u16 {
+    let data = (data & 0b11111) as u32;
+    let mut remainder = data << 10;
+    for i in (10..15).rev() {
+        if remainder & (1 << i) != 0 {
+            remainder ^= FORMAT_INFO_GENERATOR << (i - 10);
+        }
+    }
+    ((data << 10) | remainder) as u16
+}
+
+/// Compute the 15 format information bits for the 5 data bits (ECC level or symbol number,
+/// followed by the mask), including the XOR mask of standard or micro symbols.
+pub fn format_info_from_data(data: u8, micro: bool) -> u16 {
+    bch_format_code(data) ^ if micro { FORMAT_INFO_MASK_MICRO_QR } else { FORMAT_INFO_MASK_QR }
+}
+
+// generator polynomial of the BCH(18,6) code of the version information, see Annex D
+const VERSION_INFO_GENERATOR: u32 = 0b1_1111_0010_0101;
+
+/// Compute the BCH(18,6) code of the lower 6 bits of the given version number: the version
+/// followed by the 12 ECC bits. Unlike the format information, it is not masked.
+pub fn bch_version_code(version: u8) -> u32 {
+    let data = (version & 0b111111) as u32;
+    let mut remainder = data << 12;
+    for i in (12..18).rev() {
+        if remainder & (1 << i) != 0 {
+            remainder ^= VERSION_INFO_GENERATOR << (i - 12);
+        }
+    }
+    (data << 12) | remainder
+}
+
+// lookup table for version info bits, works similar to format info. The index is the version minus 7.
+pub static VERSION_INFOS: [u32; 34] = [
     0x07c94, 0x085bc, 0x09a99, 0x0a4d3, 0x0bbf6, 0x0c762, 0x0d847, 0x0e60d, 0x0f928, 0x10b78, 0x1145d, 0x12a17,
     0x13532, 0x149a6, 0x15683, 0x168c9, 0x177ec, 0x18ec4, 0x191e1, 0x1afab, 0x1b08e, 0x1cc1a, 0x1d33f, 0x1ed75,
     0x1f250, 0x209d5, 0x216f0, 0x228ba, 0x2379f, 0x24b0b, 0x2542e, 0x26a64, 0x27541, 0x28c69,
 ];
 
 
-// coordinates in the QR symbol where to write format and version bits. Do not include quiet region.
-static VERSION_INFO_COORDS_BL: [(i16, i16); 18] = [
-    (5, -9), (5, -10), (5, -11),
-    (4, -9), (4, -10), (4, -11),
-    (3, -9), (3, -10), (3, -11),
-    (2, -9), (2, -10), (2, -11),
-    (1, -9), (1, -10), (1, -11),
-    (0, -9), (0, -10), (0, -11),
-];
+/// Modules holding the format and version information of a symbol size, in absolute module
+/// coordinates without quiet region. Shared by the canvas, the encoder and the inspection.
+#[derive(Clone,Hash,Eq,PartialEq,Debug)]
+pub struct InfoPlacement {
+    pub format: Vec<Vec<(u32, u32)>>,       // copies of the 15 format bits, most significant bit first
+    pub version: Vec<Vec<(u32, u32)>>,      // copies of the 18 version bits (versions 7 and up)
+    pub dark_module: Option<(u32, u32)>     // always dark, reserved together with the format information
+}
 
-static VERSION_INFO_COORDS_TR: [(i16, i16); 18] = [
-    (-9, 5), (-10, 5), (-11, 5),
-    (-9, 4), (-10, 4), (-11, 4),
-    (-9, 3), (-10, 3), (-11, 3),
-    (-9, 2), (-10, 2), (-11, 2),
-    (-9, 1), (-10, 1), (-11, 1),
-    (-9, 0), (-10, 0), (-11, 0),
-];
+impl InfoPlacement {
+    // generate the placement for a size, see 7.9.1 and 7.10 of the standard
+    fn generate(size: Size) -> InfoPlacement {
+        let s = size.dimensions();
+        match size {
+            Size::Micro(_) => InfoPlacement {
+                // along the separator of the finder pattern, from the bottom left to the top right
+                format: vec![(0..15).map(|i| if i < 8 { (i + 1, 8) } else { (8, 15 - i) }).collect()],
+                version: Vec::new(),
+                dark_module: None
+            },
+            Size::Standard(i) => {
+                // around the upper left finder pattern, skipping the timing patterns
+                let main = (0..15).map(|i| match i {
+                    0..=5 => (i, 8),
+                    6 => (7, 8),
+                    7 => (8, 8),
+                    8 => (8, 7),
+                    _ => (8, 14 - i)
+                }).collect();
+                // split between the lower left and the upper right finder pattern
+                let side = (0..15).map(|i| if i < 7 { (8, s - 1 - i) } else { (s - 15 + i, 8) }).collect();
+
+                let version = if i >= 7 {
+                    let bottom_left: Vec<(u32, u32)> = (0..18).map(|k| (5 - k / 3, s - 9 - k % 3)).collect();
+                    let top_right = bottom_left.iter().map(|&(x, y)| (y, x)).collect();
+                    vec![bottom_left, top_right]
+                } else {
+                    Vec::new()
+                };
 
-static FORMAT_INFO_COORDS_QR_MAIN: [(i16, i16); 15] = [
-    (0, 8), (1, 8), (2, 8), (3, 8), (4, 8), (5, 8), (7, 8), (8, 8),
-    (8, 7), (8, 5), (8, 4), (8, 3), (8, 2), (8, 1), (8, 0),
-];
+                InfoPlacement { format: vec![main, side], version, dark_module: Some((8, s - 8)) }
+            }
+        }
+    }
+}
 
-static FORMAT_INFO_COORDS_QR_SIDE: [(i16, i16); 15] = [
-    (8, -1), (8, -2), (8, -3), (8, -4), (8, -5), (8, -6), (8, -7), (-8, 8),
-    (-7, 8), (-6, 8), (-5, 8), (-4, 8), (-3, 8), (-2, 8), (-1, 8),
-];
+lazy_static! {
+    // placements of all sizes, M1 to M4 followed by 1 to 40
+    static ref INFO_PLACEMENTS: Vec<InfoPlacement> = Size::all()
+        .map(InfoPlacement::generate)
+        .collect();
+}
 
-static FORMAT_INFO_COORDS_MICRO_QR: [(i16, i16); 15] = [
-    (1, 8), (2, 8), (3, 8), (4, 8), (5, 8), (6, 8), (7, 8), (8, 8),
-    (8, 7), (8, 6), (8, 5), (8, 4), (8, 3), (8, 2), (8, 1),
-];
+/// Return the placement of the format and version information of the given size
+pub fn info_placement(size: Size) -> QrResult<&'static InfoPlacement> {
+    if !size.is_valid() {
+        return Err(QrError::InvalidSize(size));
+    }
+    Ok(match size {
+        Size::Micro(i) => &INFO_PLACEMENTS[i as usize - 1],
+        Size::Standard(i) => &INFO_PLACEMENTS[4 + i as usize - 1]
+    })
+}
 
 // helper function to write format or version bits to given coordinates in QR code
 // bits are the bits actually to be written (big-endian order), num_bits is how many
-// bits to write. Obviously this function supports writing only up to 32 bits at a time
-fn insert_bits_at(symbol: &mut image::GrayImage, bits: u32, num_bits: u32, coords: &[(i16, i16)], size: Size) {
+// bits to write. Obviously this function supports writing only up to 32 bits at a time.
+// The coordinates must be reserved for the given region.
+fn insert_bits_at(symbol: &mut Matrix, bits: u32, num_bits: u32, coords: &[(u32, u32)], region: Module) {
     let mut mask = 1 << (num_bits - 1);
-
-    let symbol_size = size.dimensions() as i16;
-    let quiet_offset = size.quiet_region_size() as i16;
-
-    for &(xoff, yoff) in coords {
-        let color = if (mask & bits) == 0 { BIT_WHITE } else { BIT_BLACK };
-        let x = quiet_offset + if xoff < 0 { xoff + symbol_size } else { xoff };
-        let y = quiet_offset + if yoff < 0 { yoff + symbol_size } else { yoff };
-        symbol[(x as u32, y as u32)] = color;
+    for &pos in coords {
+        symbol.fill_reserved(pos, region, (mask & bits) != 0);
         mask >>= 1;
     }
 }
@@ -474,7 +660,14 @@ fn insert_bits_at(symbol: &mut image::GrayImage, bits: u32, num_bits: u32, coord
 
 /// compute the 15bits long format bits "format info" specifier, which contains
 /// information about the used mask and ECCLevel
-fn compute_format_info_bits(size: Size, ecl: ECCLevel, mask_pattern: u8) -> u16 {
+fn compute_format_info_bits(size: Size, ecl: ECCLevel, mask_pattern: u8) -> QrResult<u16> {
+    if !size.is_valid() {
+        return Err(QrError::InvalidSize(size));
+    }
+    if mask_pattern >= if size.is_micro() { 4 } else { 8 } {
+        return Err(QrError::InvalidMaskPattern(mask_pattern, size));
+    }
+
     match size {
         Size::Micro(i) => {
             let data_bits = match (i, ecl) {
@@ -486,9 +679,9 @@ fn compute_format_info_bits(size: Size, ecl: ECCLevel, mask_pattern: u8) -> u16
                 (4, ECCLevel::L) => 0b10100,
                 (4, ECCLevel::M) => 0b11000,
                 (4, ECCLevel::Q) => 0b11100,
-                _ => panic!("Invalid combination of size and ECC level")
+                _ => return Err(QrError::InvalidSymbolConfig(size, ecl))
             } as usize | (mask_pattern as usize);
-            FORMAT_INFOS_MICRO_QR[data_bits]
+            Ok(FORMAT_INFOS_MICRO_QR[data_bits])
         },
         Size::Standard(_) => {
             let data_bits = match ecl {
@@ -497,36 +690,184 @@ fn compute_format_info_bits(size: Size, ecl: ECCLevel, mask_pattern: u8) -> u16
                 ECCLevel::Q => 0b11000,
                 ECCLevel::H => 0b10000
             } as usize | (mask_pattern as usize);
-            FORMAT_INFOS_QR[data_bits]
+            Ok(FORMAT_INFOS_QR[data_bits])
         }
     }
 }
 
 /// Compute and write format bits into symbol
-pub fn insert_format_info(symbol: &mut image::GrayImage, size: Size, ecl: ECCLevel, mask_pattern: u8) {
-    let format_bits = compute_format_info_bits(size, ecl, mask_pattern);
+pub fn insert_format_info(symbol: &mut Matrix, size: Size, ecl: ECCLevel, mask_pattern: u8) -> QrResult<()> {
+    let format_bits = compute_format_info_bits(size, ecl, mask_pattern)?;
 
-    match size {
-        Size::Micro(_) => {
-            insert_bits_at(symbol, format_bits as u32, 15, &FORMAT_INFO_COORDS_MICRO_QR, size);
-        },
-        Size::Standard(i) => {
-            insert_bits_at(symbol, format_bits as u32, 15, &FORMAT_INFO_COORDS_QR_MAIN, size);
-            insert_bits_at(symbol, format_bits as u32, 15, &FORMAT_INFO_COORDS_QR_SIDE, size);
-            symbol[(12, 13+4*i as u32)] = BIT_BLACK;
-        }
+    let placement = info_placement(size)?;
+    for coords in placement.format.iter() {
+        insert_bits_at(symbol, format_bits as u32, 15, coords, Module::FormatInformation);
+    }
+    if let Some(pos) = placement.dark_module {
+        symbol.fill_reserved(pos, Module::FormatInformation, true);
+    }
+    Ok(())
+}
+
+/// Write the lower 15 bits of the given value into both copies of the format information, instead
+/// of the bits computed from the ECC level and mask. The bits are written as given, i.e. they have to
+/// include the XOR mask of the format information. Unlike insert_format_info, this also overwrites
+/// the format information of finished symbols. The result is NOT a standard symbol unless the bits
+/// are a valid format information, see format_bits_to_config.
+pub fn insert_raw_format_info(symbol: &mut Matrix, size: Size, bits: u16) -> QrResult<()> {
+    let placement = info_placement(size)?;
+    // reserve the modules again, so they are written like in the other stages
+    for &pos in placement.format.iter().flatten().chain(placement.dark_module.iter()) {
+        symbol.reserve(pos, Module::FormatInformation);
+    }
+    for coords in placement.format.iter() {
+        insert_bits_at(symbol, bits as u32, 15, coords, Module::FormatInformation);
     }
+    if let Some(pos) = placement.dark_module {
+        symbol.fill_reserved(pos, Module::FormatInformation, true);
+    }
+    Ok(())
 }
 
 /// Compute and insert version info bits into symbol
 /// Only does something for >= version 7 symbols.
-pub fn insert_version_info(symbol: &mut image::GrayImage, size: Size) {
+pub fn insert_version_info(symbol: &mut Matrix, size: Size) -> QrResult<()> {
+    let placement = info_placement(size)?;
     if let Size::Standard(i) = size {
-        if i >= 7 {
-            let version_bits = VERSION_INFOS[(i-7) as usize];
+        for coords in placement.version.iter() {
+            insert_bits_at(symbol, VERSION_INFOS[(i-7) as usize], 18, coords, Module::VersionInformation);
+        }
+    }
+    Ok(())
+}
+
+
+//-------------------------------------------------------------------
+// SYMBOL INSPECTION
+//-------------------------------------------------------------------
+
+/// Parameters of a finished symbol, as read from its dimensions and its format
+/// and version information
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct SymbolInfo {
+    pub size: Size,
+    pub level: ECCLevel,
+    pub mask: u8            // index of the applied mask pattern, as in QrCode
+}
+
+// helper function to read format or version bits from given coordinates in QR code,
+// the counterpart of insert_bits_at
+fn read_bits_at(symbol: &Matrix, coords: &[(u32, u32)]) -> u32 {
+    coords.iter().fold(0, |bits, &pos| (bits << 1) | symbol[pos].is_dark() as u32)
+}
+
+// read all copies of format or version bits
+fn read_copies(symbol: &Matrix, copies: &[Vec<(u32, u32)>]) -> Vec<u32> {
+    copies.iter().map(|coords| read_bits_at(symbol, coords)).collect()
+}
+
+/// The BCH codes of format and version information correct up to 3 bit errors, values further
+/// away from all table entries are not accepted
+pub const MAX_INFO_BIT_ERRORS: u32 = 3;
+
+// find the index of the table entry closest to any of the read values
+fn closest_table_entry(table: &[u32], values: &[u32]) -> Option<usize> {
+    table.iter().enumerate()
+        .flat_map(|(i, &entry)| values.iter().map(move |&value| ((entry ^ value).count_ones(), i)))
+        .min()
+        .filter(|&(distance, _)| distance <= MAX_INFO_BIT_ERRORS)
+        .map(|(_, i)| i)
+}
+
+// ECC level and mask of a standard symbol from the index into FORMAT_INFOS_QR
+fn standard_format_from_index(format: usize) -> (ECCLevel, u8) {
+    let level = match format >> 3 {
+        0b01 => ECCLevel::L,
+        0b00 => ECCLevel::M,
+        0b11 => ECCLevel::Q,
+        _ => ECCLevel::H
+    };
+    (level, (format & 0b111) as u8)
+}
+
+// size, ECC level and mask of a micro symbol from the index into FORMAT_INFOS_MICRO_QR.
+// The upper three bits are the symbol number, see table 13.
+fn micro_format_from_index(format: usize) -> (Size, ECCLevel, u8) {
+    let (version, level) = match format >> 2 {
+        0 => (1, ECCLevel::L),
+        1 => (2, ECCLevel::L),
+        2 => (2, ECCLevel::M),
+        3 => (3, ECCLevel::L),
+        4 => (3, ECCLevel::M),
+        5 => (4, ECCLevel::L),
+        6 => (4, ECCLevel::M),
+        _ => (4, ECCLevel::Q)
+    };
+    (Size::Micro(version), level, (format & 0b11) as u8)
+}
+
+/// Look up the ECC level and mask of the 15 format bits of a standard symbol, as read from the
+/// symbol (i.e. including the XOR mask). The closest table entry within MAX_INFO_BIT_ERRORS is used.
+pub fn format_bits_to_config(bits: u16) -> Option<(ECCLevel, u8)> {
+    let table: Vec<u32> = FORMAT_INFOS_QR.iter().map(|&f| f as u32).collect();
+    closest_table_entry(&table, &[bits as u32]).map(standard_format_from_index)
+}
+
+/// Same as format_bits_to_config for micro symbols, whose format bits also contain the size
+pub fn micro_format_bits_to_config(bits: u16) -> Option<(Size, ECCLevel, u8)> {
+    let table: Vec<u32> = FORMAT_INFOS_MICRO_QR.iter().map(|&f| f as u32).collect();
+    closest_table_entry(&table, &[bits as u32]).map(micro_format_from_index)
+}
+
+/// Look up the version of the 18 version bits of a standard symbol of version 7 and up.
+/// The closest table entry within MAX_INFO_BIT_ERRORS is used.
+pub fn version_bits_to_version(bits: u32) -> Option<u8> {
+    closest_table_entry(&VERSION_INFOS, &[bits]).map(|i| i as u8 + 7)
+}
+
+/// Return the size of the symbols with the given matrix width
+pub fn size_from_width(width: u32) -> QrResult<Size> {
+    match width {
+        11 | 13 | 15 | 17 => Ok(Size::Micro(((width - 9) / 2) as u8)),
+        21..=177 if (width - 17).is_multiple_of(4) => Ok(Size::Standard(((width - 17) / 4) as u8)),
+        _ => Err(QrError::InvalidMatrixWidth(width))
+    }
+}
+
+/// Read the size, ECC level and mask of a finished symbol from its dimensions and its format
+/// and version information, without decoding any data. Up to 3 wrong bits in the format and
+/// version information are tolerated.
+pub fn inspect_matrix(symbol: &Matrix) -> QrResult<SymbolInfo> {
+    let size = size_from_width(symbol.width())?;
+    let placement = info_placement(size)?;
+
+    match size {
+        Size::Micro(i) => {
+            let table: Vec<u32> = FORMAT_INFOS_MICRO_QR.iter().map(|&f| f as u32).collect();
+            let format = closest_table_entry(&table, &read_copies(symbol, &placement.format))
+                .ok_or(QrError::UnreadableFormatInfo)?;
 
-            insert_bits_at(symbol, version_bits, 18, &VERSION_INFO_COORDS_BL, size);
-            insert_bits_at(symbol, version_bits, 18, &VERSION_INFO_COORDS_TR, size);
+            let (format_size, level, mask) = micro_format_from_index(format);
+            if format_size != Size::Micro(i) {
+                return Err(QrError::UnreadableFormatInfo);
+            }
+            Ok(SymbolInfo { size, level, mask })
+        },
+        Size::Standard(i) => {
+            if i >= 7 {
+                let version = closest_table_entry(&VERSION_INFOS, &read_copies(symbol, &placement.version))
+                    .ok_or(QrError::UnreadableVersionInfo)?;
+                if version + 7 != i as usize {
+                    return Err(QrError::UnreadableVersionInfo);
+                }
+            }
+
+            let table: Vec<u32> = FORMAT_INFOS_QR.iter().map(|&f| f as u32).collect();
+            let format = closest_table_entry(&table, &read_copies(symbol, &placement.format))
+                .ok_or(QrError::UnreadableFormatInfo)?;
+
+            let (level, mask) = standard_format_from_index(format);
+            Ok(SymbolInfo { size, level, mask })
         }
     }
 }
@@ -538,29 +879,183 @@ pub fn insert_version_info(symbol: &mut image::GrayImage, size: Size) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "encode")]
+    use crate::render::render_matrix;
+    use crate::tables::lookup_capacity;
+    use crate::config::SymbolConfig;
 
     #[test]
     fn test_canvas_sizes() {
-        assert_eq!(create_qr_canvas(Size::Micro(1)).dimensions(), (11 + 4, 11 + 4));
-        assert_eq!(create_qr_canvas(Size::Micro(2)).dimensions(), (13 + 4, 13 + 4));
-        assert_eq!(create_qr_canvas(Size::Micro(3)).dimensions(), (15 + 4, 15 + 4));
-        assert_eq!(create_qr_canvas(Size::Micro(4)).dimensions(), (17 + 4, 17 + 4));
-        assert_eq!(create_qr_canvas(Size::Standard(1)).dimensions(), (21 + 8, 21 + 8));
-        assert_eq!(create_qr_canvas(Size::Standard(2)).dimensions(), (25 + 8, 25 + 8));
-        assert_eq!(create_qr_canvas(Size::Standard(40)).dimensions(), (177 + 8, 177 + 8));
+        assert_eq!(create_qr_canvas(Size::Micro(1)).width(), 11);
+        assert_eq!(create_qr_canvas(Size::Micro(2)).width(), 13);
+        assert_eq!(create_qr_canvas(Size::Micro(3)).width(), 15);
+        assert_eq!(create_qr_canvas(Size::Micro(4)).width(), 17);
+        assert_eq!(create_qr_canvas(Size::Standard(1)).width(), 21);
+        assert_eq!(create_qr_canvas(Size::Standard(2)).width(), 25);
+        assert_eq!(create_qr_canvas(Size::Standard(40)).width(), 177);
     }
 
     #[test]
+    #[cfg(feature = "encode")]
     fn test_standard() {
-        create_qr_canvas(Size::Standard(7)).save("./tmp_standard.png").unwrap();
+        render_matrix(&create_qr_canvas(Size::Standard(7)), 4).save("./tmp_standard.png").unwrap();
     }
 
     #[test]
+    #[cfg(feature = "encode")]
     fn test_micro() {
-        create_qr_canvas(Size::Micro(3)).save("./tmp_micro.png").unwrap();
+        render_matrix(&create_qr_canvas(Size::Micro(3)), 2).save("./tmp_micro.png").unwrap();
+    }
+
+    #[test]
+    fn test_function_pattern_positions() {
+        // version 7 has alignment patterns on the timing patterns
+        let size = Size::Standard(7);
+        let alignment = alignment_pattern_positions(size);
+        let timing = timing_pattern_positions(size);
+        assert_eq!(alignment.len(), 6 * 25);
+        assert_eq!(timing.len(), 2 * (45 - 14) - 2 * 5);
+        assert!(alignment.contains(&(6, 22)) && alignment.contains(&(22, 6)));
+        assert!(!timing.contains(&(6, 22)));
+
+        let canvas = create_qr_canvas(size);
+        for (i, &(x, y)) in timing.iter().enumerate() {
+            assert_eq!(canvas[(x, y)].is_dark(), (x + y) % 2 == 0, "Timing module {} wrong", i);
+        }
+
+        assert_eq!(timing_pattern_positions(Size::Micro(2)).len(), 2 * 5);
+        assert!(alignment_pattern_positions(Size::Micro(2)).is_empty());
+    }
+
+    #[test]
+    fn test_codeword_module_map() {
+        // 5-Q has two blocks of 15 and two blocks of 16 data codewords
+        let size = Size::Standard(5);
+        let capacity = lookup_capacity(size, ECCLevel::Q);
+        let map = codeword_module_map(size, ECCLevel::Q).unwrap();
+        assert_eq!(map.len(), capacity.codewords() as usize);
+        assert_eq!((map[0].block, map[0].index), (0, 0));
+        assert_eq!((map[5].block, map[5].index), (1, 1));
+        assert_eq!((map[61].kind, map[61].block, map[61].index), (CodewordKind::Data, 3, 15));
+        assert_eq!((map[62].kind, map[62].block, map[62].index), (CodewordKind::Ecc, 0, 0));
+        assert_eq!(map[0].modules[..2], [(36, 36), (35, 36)]);
+
+        // placing a single set codeword darkens exactly its modules
+        for &(size, level, i) in [(Size::Standard(5), ECCLevel::Q, 61), (Size::Micro(3), ECCLevel::L, 10)].iter() {
+            let capacity = lookup_capacity(size, level);
+            let mut data = vec![0u8; capacity.data_codewords() as usize];
+            let ecc = vec![0u8; capacity.ecc_words() as usize];
+            data[i] = 0xFF;
+
+            let mut symbol = create_qr_canvas(size);
+            insert_data_payload(&mut symbol, size, &data, &ecc).unwrap();
+            let mut dark: Vec<_> = encoding_region_positions(&create_qr_canvas(size), size).into_iter()
+                .filter(|&pos| symbol[pos].is_dark())
+                .collect();
+            let mut expected = codeword_module_map(size, level).unwrap()[i].modules.clone();
+            dark.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(dark, expected);
+        }
+    }
+
+    #[test]
+    fn test_info_placement() {
+        let p = info_placement(Size::Standard(1)).unwrap();
+        assert_eq!((p.format[0][0], p.format[0][14], p.format[1][0], p.format[1][7]), ((0, 8), (8, 0), (8, 20), (13, 8)));
+        assert_eq!(p.dark_module, Some((8, 13)));
+        assert!(p.version.is_empty());
+
+        let p = info_placement(Size::Standard(7)).unwrap();
+        assert_eq!((p.version[0][0], p.version[0][17], p.version[1][1]), ((5, 36), (0, 34), (35, 5)));
+        assert_eq!(info_placement(Size::Micro(2)).unwrap().format, vec![vec![(1, 8), (2, 8), (3, 8), (4, 8), (5, 8), (6, 8), (7, 8), (8, 8),
+                                                                 (8, 7), (8, 6), (8, 5), (8, 4), (8, 3), (8, 2), (8, 1)]]);
+
+        // no module is used twice
+        for size in Size::all() {
+            let p = info_placement(size).unwrap();
+            let mut modules: Vec<_> = p.format.iter().chain(p.version.iter()).flatten().chain(p.dark_module.iter()).collect();
+            let total = modules.len();
+            modules.sort_unstable();
+            modules.dedup();
+            assert_eq!(modules.len(), total, "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn test_area_is_correctable() {
+        assert_eq!(centered_area(Size::Standard(1), 0.0), (10, 10, 0));
+        assert_eq!(centered_area(Size::Standard(1), 0.2), (8, 8, 5));
+        assert_eq!(centered_area(Size::Standard(10), 2.0), (0, 0, 57));
+
+        let size = Size::Standard(10);
+        assert_eq!(area_is_correctable(size, ECCLevel::L, centered_area(size, 0.0)), Ok(true));
+        assert_eq!(area_is_correctable(size, ECCLevel::L, centered_area(size, 0.3)), Ok(false));
+        assert_eq!(area_is_correctable(size, ECCLevel::M, centered_area(size, 0.3)), Ok(true));
+        // the format information must stay readable, whatever the level
+        assert_eq!(area_is_correctable(size, ECCLevel::H, (0, 0, 9)), Ok(false));
+        assert_eq!(area_is_correctable(Size::Micro(1), ECCLevel::H, (0, 0, 1)),
+                   Err(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::H)));
+    }
+
+    #[test]
+    fn test_overlay_report() {
+        // the lower right module holds the most significant bit of the first data codeword
+        let report = overlay_report(Size::Standard(1), ECCLevel::M, (20, 20, 1, 1)).unwrap();
+        assert_eq!(report.codewords, [ObscuredCodeword { kind: CodewordKind::Data, block: 0, index: 0, bits: vec![0] }]);
+        assert_eq!((report.lost_per_block.clone(), report.correctable_per_block), (vec![1], 4));
+        assert!(report.is_correctable());
+
+        // a 2x4 rectangle covers exactly the first codeword
+        let report = overlay_report(Size::Standard(1), ECCLevel::M, (19, 17, 2, 4)).unwrap();
+        assert_eq!(report.codewords.len(), 1);
+        assert_eq!(report.codewords[0].bits, (0..8).collect::<Vec<_>>());
+
+        // the square of area_is_correctable, split up by block
+        let size = Size::Standard(10);
+        let (x, y, edge) = centered_area(size, 0.3);
+        let report = overlay_report(size, ECCLevel::L, (x, y, edge, edge)).unwrap();
+        assert_eq!(report.lost_per_block.len(), 4);
+        assert!(!report.hits_function_info);
+        assert!(report.correctable_blocks().contains(&false));
+        assert_eq!(report.codewords.len() as u32, report.lost_per_block.iter().sum::<u32>());
+        assert!(overlay_report(Size::Standard(1), ECCLevel::M, (0, 8, 1, 1)).unwrap().hits_function_info);
+    }
+
+    #[test]
+    fn test_encoding_region_size() {
+        // the encoding region must hold all codewords, plus at most 7 remainder bits
+        for size in Size::all() {
+            let codewords = lookup_capacity(size, ECCLevel::L).codewords() as usize;
+            let bits = match size {
+                Size::Micro(1) | Size::Micro(3) => codewords * 8 - 4,
+                _ => codewords * 8
+            };
+            let num_positions = encoding_region_positions(&create_qr_canvas(size), size).len();
+            assert!(num_positions >= bits && num_positions < bits + 8,
+                    "Encoding region of symbol {:?} has wrong size {}", size, num_positions);
+        }
     }
 
     #[test]
+    fn test_all_reserved_filled() {
+        // after all stages ran, no reserved module may be left, and no stage may have
+        // written outside of its region (checked by the debug assertions)
+        for size in Size::all() {
+            let capacity = lookup_capacity(size, ECCLevel::L);
+            let data = vec![0u8; capacity.data_codewords() as usize];
+            let ecc = vec![0u8; capacity.ecc_words() as usize];
+
+            let mut symbol = create_qr_canvas(size);
+            insert_data_payload(&mut symbol, size, &data, &ecc).unwrap();
+            insert_format_info(&mut symbol, size, ECCLevel::L, 0).unwrap();
+            insert_version_info(&mut symbol, size).unwrap();
+            assert!(symbol.is_complete(), "Symbol {:?} has unfilled modules", size);
+        }
+    }
+
+    #[test]
+    #[allow(non_snake_case)]
     fn test_tableE1() {
         assert_eq!(create_alignment_pattern_coord_list(3), [6, 22]);
         assert_eq!(create_alignment_pattern_coord_list(10), [6, 28, 50]);
@@ -570,4 +1065,116 @@ mod tests {
         assert_eq!(create_alignment_pattern_coord_list(33), [6, 30, 58, 86, 114, 142]);
         assert_eq!(create_alignment_pattern_coord_list(40), [6, 30, 58, 86, 114, 142, 170]);
     }
+
+    #[test]
+    fn test_inspect_matrix() {
+        for &(size, level, mask) in [(Size::Micro(3), ECCLevel::M, 2),
+                                     (Size::Standard(2), ECCLevel::Q, 5),
+                                     (Size::Standard(7), ECCLevel::H, 7)].iter() {
+            let mut symbol = create_qr_canvas(size);
+            insert_format_info(&mut symbol, size, level, mask).unwrap();
+            insert_version_info(&mut symbol, size).unwrap();
+            assert_eq!(inspect_matrix(&symbol), Ok(SymbolInfo { size, level, mask }));
+        }
+
+        // three wrong bits in the format information are corrected
+        let mut symbol = create_qr_canvas(Size::Standard(1));
+        insert_format_info(&mut symbol, Size::Standard(1), ECCLevel::L, 3).unwrap();
+        for x in 0..3 {
+            symbol.set((x, 8), if symbol[(x, 8)].is_dark() { Module::Light } else { Module::Dark });
+        }
+        assert_eq!(inspect_matrix(&symbol), Ok(SymbolInfo { size: Size::Standard(1), level: ECCLevel::L, mask: 3 }));
+
+        assert_eq!(inspect_matrix(&Matrix::new(19, Module::Light)), Err(QrError::InvalidMatrixWidth(19)));
+        assert_eq!(size_from_width(177), Ok(Size::Standard(40)));
+    }
+
+    #[test]
+    fn test_custom_canvas() {
+        // the layouts of the standard sizes give the standard canvas without the information modules
+        for size in [Size::Micro(2), Size::Standard(1), Size::Standard(7), Size::Standard(40)] {
+            let mut expected = create_qr_canvas(size);
+            for (_, _, module) in expected.enumerate_modules_mut() {
+                if *module == Module::FormatInformation || *module == Module::VersionInformation {
+                    *module = Module::EncodingRegion;
+                }
+            }
+            let layout = CustomLayout::of_size(size);
+            let canvas = create_custom_canvas(&layout);
+            assert_eq!(canvas, expected, "{:?}", size);
+            assert_eq!(custom_encoding_region_positions(&canvas, &layout), encoding_region_positions(&expected, size));
+        }
+
+        // a single finder in a symbol of even width, without timing patterns
+        let layout = CustomLayout { width: 16, finders: vec![(-1, -1)], alignments: vec![(12, 12)], timing: None };
+        let canvas = create_custom_canvas(&layout);
+        let positions = custom_encoding_region_positions(&canvas, &layout);
+        assert_eq!(positions.len(), 16 * 16 - 8 * 8 - 5 * 5);
+        assert_eq!(positions[..3], [(15, 15), (14, 15), (15, 14)]);
+    }
+
+    #[test]
+    fn test_raw_format_info() {
+        for size in [Size::Micro(4), Size::Standard(3)] {
+            let mut symbol = create_qr_canvas(size);
+            insert_format_info(&mut symbol, size, ECCLevel::M, 1).unwrap();
+            let mut raw = create_qr_canvas(size);
+            insert_raw_format_info(&mut raw, size, compute_format_info_bits(size, ECCLevel::M, 1).unwrap()).unwrap();
+            assert_eq!(raw, symbol);
+
+            // finished symbols are overwritten
+            insert_raw_format_info(&mut symbol, size, compute_format_info_bits(size, ECCLevel::L, 3).unwrap()).unwrap();
+            assert_eq!(inspect_matrix(&symbol), Ok(SymbolInfo { size, level: ECCLevel::L, mask: 3 }));
+        }
+
+        // arbitrary bits make the format information unreadable
+        let mut symbol = create_qr_canvas(Size::Standard(1));
+        insert_raw_format_info(&mut symbol, Size::Standard(1), 0x5412 ^ 0b1111_0000).unwrap();
+        assert_eq!(inspect_matrix(&symbol), Err(QrError::UnreadableFormatInfo));
+    }
+
+    #[test]
+    fn test_bch_format_code() {
+        for data in 0..32u8 {
+            assert_eq!(format_info_from_data(data, false), FORMAT_INFOS_QR[data as usize]);
+            assert_eq!(format_info_from_data(data, true), FORMAT_INFOS_MICRO_QR[data as usize]);
+        }
+        // example from Annex C: level M, mask 101
+        assert_eq!(bch_format_code(0b00101), 0b001010011011100);
+        assert_eq!(bch_format_code(0b100101), bch_format_code(0b00101));
+    }
+
+    #[test]
+    fn test_bch_version_code() {
+        for version in 7..=40u8 {
+            assert_eq!(bch_version_code(version), VERSION_INFOS[version as usize - 7]);
+        }
+        // example from Annex D: version 7
+        assert_eq!(bch_version_code(7), 0b000111110010010100);
+    }
+
+    #[test]
+    fn test_info_bits_lookup() {
+        for level in ECCLevel::all() {
+            for mask in 0..8 {
+                let bits = compute_format_info_bits(Size::Standard(1), level, mask).unwrap();
+                assert_eq!(format_bits_to_config(bits), Some((level, mask)));
+            }
+        }
+        for config in SymbolConfig::iter_all().filter(|c| c.size().is_micro()) {
+            for mask in 0..4 {
+                let bits = compute_format_info_bits(config.size(), config.ecc(), mask).unwrap();
+                assert_eq!(micro_format_bits_to_config(bits), Some((config.size(), config.ecc(), mask)));
+            }
+        }
+        for version in 7..=40 {
+            assert_eq!(version_bits_to_version(VERSION_INFOS[version as usize - 7]), Some(version));
+        }
+
+        // up to three bit errors are corrected, four are too many
+        assert_eq!(format_bits_to_config(0x77c4 ^ 0b100_0000_0010_0001), Some((ECCLevel::L, 0)));
+        assert_eq!(version_bits_to_version(0x07c94 ^ 0b111), Some(7));
+        assert_eq!(format_bits_to_config(0x5412 ^ 0b1111), None);
+        assert_eq!(version_bits_to_version(0x07c94 ^ 0b1111), None);
+    }
 }
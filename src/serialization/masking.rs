@@ -2,78 +2,88 @@
 
 use super::*;
 
-use image;
-
-
-/// Return the masking function for a given size. Pattern index is from 0..8 for standard
-/// sizes and in 0..4 for micro symbols. Returns a function that returns for the given index
-/// i,j (i row coord, j column coord, including the quiet region!)
-/// whether it meets the masking condition.
-fn get_masking_function(pattern_index: u8, size: Size) -> Box<dyn Fn(i32, i32) -> bool> {
-    match size {
-        Size::Micro(_) => {
-            match pattern_index {
-                0b00 => Box::new(| i, _j| { (i-2) % 2 == 0 }),
-                0b01 => Box::new(| i,  j| { ((i-2) / 2 + (j-2) / 3) % 2 == 0 }),
-                0b10 => Box::new(| i,  j| { (((i-2)*(j-2)) % 2 + ((i-2)*(j-2)) % 3) % 2 == 0 }),
-                0b11 => Box::new(| i,  j| { (((i-2)+(j-2)) % 2 + ((i-2)*(j-2)) % 3) % 2 == 0 }),
-                _ => panic!("Wrong pattern index given!")
-            }
-        },
-        Size::Standard(_) => {
-            match pattern_index {
-                0b000 => Box::new(| i,  j| { ((i-4) + (j-4)) % 2 == 0 }),
-                0b001 => Box::new(| i, _j| { (i-4) % 2 == 0 }),
-                0b010 => Box::new(|_i,  j| { (j-4) % 3 == 0 }),
-                0b011 => Box::new(| i,  j| { ((i-4) + (j-4)) % 3 == 0 }),
-                0b100 => Box::new(| i,  j| { ((i-4) / 2 + (j-4) / 3) % 2 == 0 }),
-                0b101 => Box::new(| i,  j| { ((i-4)*(j-4)) % 2 + ((i-4)*(j-4)) % 3 == 0 }),
-                0b110 => Box::new(| i,  j| { (((i-4)*(j-4)) % 2 + ((i-4)*(j-4)) % 3) % 2 == 0 }),
-                0b111 => Box::new(| i,  j| { (((i-4)+(j-4)) % 2 + ((i-4)*(j-4)) % 3) % 2 == 0 }),
-                _ => panic!("Wrong pattern index given!")
-            }
-        }
+
+/// Evaluate the mask condition of a standard mask pattern (index in 0..8) for the module
+/// at (x, y), in module coordinates of the symbol without the quiet region.
+/// Returns true if the module has to be flipped, or None for masks out of range.
+pub fn mask_bit(mask: u8, x: u32, y: u32) -> Option<bool> {
+    // the standard uses i for the row and j for the column
+    let (i, j) = (y, x);
+    Some(match mask {
+        0b000 => (i + j) % 2 == 0,
+        0b001 => i % 2 == 0,
+        0b010 => j % 3 == 0,
+        0b011 => (i + j) % 3 == 0,
+        0b100 => (i / 2 + j / 3) % 2 == 0,
+        0b101 => (i * j) % 2 + (i * j) % 3 == 0,
+        0b110 => ((i * j) % 2 + (i * j) % 3) % 2 == 0,
+        0b111 => ((i + j) % 2 + (i * j) % 3) % 2 == 0,
+        _ => return None
+    })
+}
+
+/// Return the standard mask pattern that corresponds to the given pattern index of the
+/// symbol size. Micro symbols use a subset of the standard patterns, with their own numbering.
+pub fn standard_mask_pattern(pattern_index: u8, size: Size) -> QrResult<u8> {
+    match (size, pattern_index) {
+        (Size::Micro(_), 0b00) => Ok(0b001),
+        (Size::Micro(_), 0b01) => Ok(0b100),
+        (Size::Micro(_), 0b10) => Ok(0b110),
+        (Size::Micro(_), 0b11) => Ok(0b111),
+        (Size::Standard(_), 0..=7) => Ok(pattern_index),
+        _ => Err(QrError::InvalidMaskPattern(pattern_index, size))
     }
 }
 
 
 /// apply mask to given symbol's encoding region. The second parameter is the canvas
 /// without content, to mark the encoding region inside the symbol.
-pub fn apply_mask(symbol: &mut image::GrayImage, pattern: u8, size: Size, marker: &image::GrayImage) {
-    // get masking function
-    let pattern_func = get_masking_function(pattern, size);
+pub fn apply_mask(symbol: &mut Matrix, pattern: u8, size: Size, marker: &Matrix) -> QrResult<()> {
+    // get standard mask pattern
+    let mask = standard_mask_pattern(pattern, size)?;
+    apply_custom_mask(symbol, marker, |x, y| mask_bit(mask, x as u32, y as u32) == Some(true));
+    Ok(())
+}
 
+/// Apply a caller-provided mask condition instead of a standard pattern, e.g. for research on
+/// the effectiveness of masks. The condition gets the module coordinates (x, y) and returns true
+/// if the module has to be flipped; only modules in the encoding region of the marker are touched.
+/// Symbols masked this way are not conformant, as readers unmask them with the standard pattern
+/// named in the format information.
+pub fn apply_custom_mask<F: Fn(i32, i32) -> bool>(symbol: &mut Matrix, marker: &Matrix, condition: F) {
     // iterate over symbol
-    for (x, y, pix) in symbol.enumerate_pixels_mut() {
-        // check if we are in the encoding region. Ignore all other pixels
-        if marker[(x, y)] == MARKER_ENCODING_REGION {
-            // retrieve the mask bit. Flip the bit if the mask bit
-            // is 1, leave it as is otherwise. This is equivalent with
-            // a XOR between the mask and value bits.
-            if pattern_func(y as i32, x as i32) {
-                *pix = if *pix == BIT_BLACK { BIT_WHITE } else { BIT_BLACK };
+    for (x, y, module) in symbol.enumerate_modules_mut() {
+        // check if we are in the encoding region. Ignore all other modules
+        if marker[(x, y)] == Module::EncodingRegion {
+            // Flip the bit if the mask bit is 1, leave it as is otherwise.
+            // This is equivalent with a XOR between the mask and value bits.
+            if condition(x as i32, y as i32) {
+                *module = module.inverted();
             }
         }
     }
 }
 
-/// Compute penalty score for symbol with mask applied for standard size QR codes.
-/// There is an extra function to do this for micro symbols, because it works differently for those.
 const PENALTY_N1: u32 = 3;
 const PENALTY_N2: u32 = 3;
 const PENALTY_N3: u32 = 40;
 const PENALTY_N4: u32 = 10;
 
-fn compute_mask_penalty_score_standard(masked_symbol: &image::GrayImage) -> u32 {
-    // NOte: all iterations exclude the quiet region, which accounts for the offset of 4.
+/// Compute penalty score for symbol with mask applied for standard size QR codes.
+/// There is an extra function to do this for micro symbols, because it works differently for those.
+/// The input must be a complete standard symbol with its three finder patterns, as their
+/// finder-like patterns are not counted.
+pub fn compute_mask_penalty_score_standard(masked_symbol: &Matrix) -> u32 {
+    let w = masked_symbol.width();
+
     // FIRST feature: adjacent modules of same color or size in symbol.
     let mut score: u32 = 0;
     {
         // search all the rows for adjacent blocks of same-color modules.
-        for y in 4..(masked_symbol.height()-4) {
-            let mut last_color = BIT_WHITE;
+        for y in 0..w {
+            let mut last_color = Module::Light;
             let mut current_run = 1;        // number of current adjacent modules found.
-            for x in 4..(masked_symbol.width()-4) {
+            for x in 0..w {
                 if masked_symbol[(x, y)] == last_color {
                     // counts against current run
                     current_run += 1;
@@ -94,10 +104,10 @@ fn compute_mask_penalty_score_standard(masked_symbol: &image::GrayImage) -> u32
 
         // now the same for columns. This is almost the same, but note that the order of
         // iteration changed.
-        for x in 4..(masked_symbol.width()-4) {
-            let mut last_color = BIT_WHITE;
+        for x in 0..w {
+            let mut last_color = Module::Light;
             let mut current_run = 1;        // number of current adjacent modules found.
-            for y in 4..(masked_symbol.height()-4) {
+            for y in 0..w {
                 if masked_symbol[(x, y)] == last_color {
                     // counts against current run
                     current_run += 1;
@@ -119,8 +129,8 @@ fn compute_mask_penalty_score_standard(masked_symbol: &image::GrayImage) -> u32
 
     // SECOND FEATURE: penalties for 2x2 module blocks of same color
     {
-        for y in 4..(masked_symbol.height()-5) {
-            for x in 4..(masked_symbol.width()-5) {
+        for y in 0..(w-1) {
+            for x in 0..(w-1) {
                 if masked_symbol[(x, y)] == masked_symbol[(x+1, y)] &&
                    masked_symbol[(x, y)] == masked_symbol[(x, y+1)] &&
                    masked_symbol[(x, y)] == masked_symbol[(x+1, y+1)] {
@@ -131,77 +141,185 @@ fn compute_mask_penalty_score_standard(masked_symbol: &image::GrayImage) -> u32
         }
     }
 
-    // THIRD FEATURE: 1011101 patterns with 4 zeros before or after it
+    // THIRD FEATURE: 1011101 patterns with 4 light modules before or after it
     {
-        const PATTERN: [image::Luma<u8>; 7] = [BIT_BLACK, BIT_WHITE, BIT_BLACK, BIT_BLACK, BIT_BLACK, BIT_WHITE, BIT_BLACK];
+        score += find_finder_like_patterns(masked_symbol).len() as u32 * PENALTY_N3;
 
-        for y in 4..(masked_symbol.height()-4) {
-            for x in 4..(masked_symbol.width()-10) {
-                // check if pattern exists in  (x:x+7, y)
-                if (x..(x+7)).map(|x_cur| masked_symbol.get_pixel(x_cur, y)).ne(PATTERN.iter()) {
-                    // is different, so go on
-                    continue;
-                }
+        // subtract 18*N3 for the 9 occurrences of the pattern in each direction in the
+        // finders + quiet space. Other matrices may have fewer of them.
+        score = score.saturating_sub(18 * PENALTY_N3);
+    }
 
-                // check for four white spaces
-                let is_black = |x_cur| 0 <= x_cur && x_cur < masked_symbol.width() && *masked_symbol.get_pixel(x_cur, y) == BIT_BLACK;
-                if !((x - 4)..x).any(&is_black) || !((x+7)..(x+11)).any(&is_black) {
-                    score += PENALTY_N3;
-                }
+    // FOURTH FEATURE: dark/light ratio balance
+    {
+        // count dark modules
+        let num_dark_modules = masked_symbol.enumerate_modules().filter(|&(_, _, m)| m.is_dark()).count();
+        let ratio = num_dark_modules as f64 / (w * w) as f64;
+
+        let ratio_diff = (0.5 - ratio).abs();
+        let step = (ratio_diff * 20.0).floor() as u32; // *20 is actually / 0.05;
+
+        // step is now the number of full-5%-steps by which ratio deviates from 50%.
+        score += PENALTY_N4 * step;
+    }
+
+    score
+}
+
+/// A 1:1:3:1:1 finder-like pattern, given by the coordinates of its first module
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct FinderLikePattern {
+    pub x: u32,
+    pub y: u32,
+    pub vertical: bool      // whether the pattern runs along a column instead of a row
+}
+
+/// Find all 1011101 patterns with 4 light modules before or after them, as used by the
+/// N3 penalty rule. Works on arbitrary matrices, e.g. to check artwork around a symbol for
+/// accidental look-alikes of the finder patterns. Modules outside of the matrix count as light.
+pub fn find_finder_like_patterns(matrix: &Matrix) -> Vec<FinderLikePattern> {
+    const PATTERN: [Module; 7] = [Module::Dark, Module::Light, Module::Dark, Module::Dark,
+                                  Module::Dark, Module::Light, Module::Dark];
+
+    let w = matrix.width();
+    let is_dark = |x: i32, y: i32| matrix.get(x, y) == Some(Module::Dark);
+    let mut found = Vec::new();
+
+    for y in 0..w {
+        for x in 0..w.saturating_sub(6) {
+            // check if pattern exists in  (x:x+7, y)
+            if (x..(x+7)).map(|x_cur| matrix[(x_cur, y)]).ne(PATTERN.iter().copied()) {
+                continue;
+            }
+
+            // check for four light modules
+            let (xi, yi) = (x as i32, y as i32);
+            if !((xi - 4)..xi).any(|x_cur| is_dark(x_cur, yi)) || !((xi+7)..(xi+11)).any(|x_cur| is_dark(x_cur, yi)) {
+                found.push(FinderLikePattern { x, y, vertical: false });
             }
         }
+    }
+
+    // same for columns
+    for x in 0..w {
+        for y in 0..w.saturating_sub(6) {
+            // check if pattern exists in  (x, y:y+7)
+            if (y..(y+7)).map(|y_cur| matrix[(x, y_cur)]).ne(PATTERN.iter().copied()) {
+                continue;
+            }
 
-        // subtract 9*N3 for the 9 occurrences of the pattern in the finders + quiet space
-        score -= 9 * PENALTY_N3;
+            // check for four light modules
+            let (xi, yi) = (x as i32, y as i32);
+            if !((yi - 4)..yi).any(|y_cur| is_dark(xi, y_cur)) || !((yi+7)..(yi+11)).any(|y_cur| is_dark(xi, y_cur)) {
+                found.push(FinderLikePattern { x, y, vertical: true });
+            }
+        }
+    }
+    found
+}
+
+/// Penalty points of a masked standard symbol, split up by rule and spread over the modules
+/// which cause them, e.g. a run of 6 modules with 4 points gives each module 2/3 of a point.
+/// The dark module balance (N4) is a property of the whole symbol and not spread. The finder
+/// patterns themselves are excluded, so the sum of all points gives the penalty score.
+#[derive(Clone,PartialEq,Debug)]
+pub struct PenaltyMap {
+    pub width: u32,
+    pub runs: Vec<f64>,         // N1: runs of 5 or more modules of the same color, row by row
+    pub blocks: Vec<f64>,       // N2: 2x2 blocks of the same color
+    pub finder_like: Vec<f64>,  // N3: 1011101 patterns outside of the finder patterns
+    pub balance: u32            // N4: deviation of the dark module ratio from 50%
+}
+
+impl PenaltyMap {
+    /// Points of all rules the module at (x, y) contributes to
+    pub fn total(&self, x: u32, y: u32) -> f64 {
+        let i = (y * self.width + x) as usize;
+        self.runs[i] + self.blocks[i] + self.finder_like[i]
+    }
 
-        // same for columns
-        for x in 4..(masked_symbol.width()-4) {
-            for y in 4..(masked_symbol.height()-10) {
-                // check if pattern exists in  (x, y:y+7)
-                if (y..(y+7)).map(|y_cur| masked_symbol.get_pixel(x, y_cur)).ne(PATTERN.iter()) {
-                    // is different, so go on
+    /// Sum of all points, equal to compute_mask_penalty_score_standard
+    pub fn score(&self) -> f64 {
+        self.runs.iter().chain(&self.blocks).chain(&self.finder_like).sum::<f64>() + self.balance as f64
+    }
+}
+
+/// Attribute the penalty score of a masked standard symbol to its modules, see PenaltyMap
+pub fn compute_penalty_map(masked_symbol: &Matrix) -> PenaltyMap {
+    let w = masked_symbol.width();
+    let index = |x: u32, y: u32| (y * w + x) as usize;
+    let mut runs = vec![0.0; (w * w) as usize];
+    let mut blocks = vec![0.0; (w * w) as usize];
+    let mut finder_like = vec![0.0; (w * w) as usize];
+
+    // N1: walk each row and column, spreading the penalty of each long run over its modules
+    for vertical in [false, true] {
+        for line in 0..w {
+            let at = |i: u32| if vertical { (line, i) } else { (i, line) };
+            let mut start = 0;
+            for i in 1..=w {
+                if i < w && masked_symbol[at(i)] == masked_symbol[at(start)] {
                     continue;
                 }
-
-                // check for four white spaces
-                let is_black = |y_cur| 0 <= y_cur && y_cur < masked_symbol.width() && *masked_symbol.get_pixel(x, y_cur) == BIT_BLACK;
-                if !((y - 4)..y).any(&is_black) || !((y+7)..(y+11)).any(&is_black) {
-                    score += PENALTY_N3;
+                // like compute_mask_penalty_score_standard, a leading light run counts one more module
+                let len = i - start;
+                let counted = len + (start == 0 && masked_symbol[at(0)] == Module::Light) as u32;
+                if counted >= 5 {
+                    let share = ((counted - 5) + PENALTY_N1) as f64 / len as f64;
+                    for j in start..i {
+                        let (x, y) = at(j);
+                        runs[index(x, y)] += share;
+                    }
                 }
+                start = i;
             }
         }
-
-        // subtract 9*N3 for the 9 occurrences of the pattern in the finders + quiet space
-        score -= 9 * PENALTY_N3;
     }
 
-    // FOURTH FEATURE: dark/light ratio balance
-    {
-        // count dark modules
-        let num_dark_modules = masked_symbol.pixels().filter(|&px| *px == BIT_BLACK).count();
-        let ratio = num_dark_modules as f64 / ((masked_symbol.width()-8) * (masked_symbol.height()-8)) as f64;
-
-        let ratio_diff = (0.5 - ratio).abs();
-        let step = (ratio_diff * 20.0).floor() as u32; // *20 is actually / 0.05;
+    // N2: every block of the same color gives a quarter of the points to each of its modules
+    for y in 0..(w-1) {
+        for x in 0..(w-1) {
+            let m = masked_symbol[(x, y)];
+            if m == masked_symbol[(x+1, y)] && m == masked_symbol[(x, y+1)] && m == masked_symbol[(x+1, y+1)] {
+                for &(bx, by) in [(x, y), (x+1, y), (x, y+1), (x+1, y+1)].iter() {
+                    blocks[index(bx, by)] += PENALTY_N2 as f64 / 4.0;
+                }
+            }
+        }
+    }
 
-        // step is now the number of full-5%-steps by which ratio deviates from 50%.
-        score += PENALTY_N4 * step;
+    // N3: the patterns within the finder patterns are the ones subtracted from the score
+    let in_finder = |x: u32, y: u32| (y < 7 && (x < 7 || x >= w - 7)) || (x < 7 && y >= w - 7);
+    for pattern in find_finder_like_patterns(masked_symbol) {
+        let (dx, dy) = if pattern.vertical { (0, 1) } else { (1, 0) };
+        if in_finder(pattern.x, pattern.y) && in_finder(pattern.x + 6 * dx, pattern.y + 6 * dy) {
+            continue;
+        }
+        for i in 0..7 {
+            finder_like[index(pattern.x + i * dx, pattern.y + i * dy)] += PENALTY_N3 as f64 / 7.0;
+        }
     }
 
-    score
+    let num_dark_modules = masked_symbol.enumerate_modules().filter(|&(_, _, m)| m.is_dark()).count();
+    let ratio = num_dark_modules as f64 / (w * w) as f64;
+    let balance = PENALTY_N4 * ((0.5 - ratio).abs() * 20.0).floor() as u32;
+
+    PenaltyMap { width: w, runs, blocks, finder_like, balance }
 }
 
-/// compute the mask score for a masked micro QR symbol
-fn compute_mask_score_micro(masked_symbol: &image::GrayImage) -> u32 {
-    // count number of black modules in right and lower edges of symbol
-    let sum1 = (3..(masked_symbol.height()-2))
-        .map(|y_cur| masked_symbol.get_pixel(masked_symbol.width()-2, y_cur))
-        .filter(|&px| *px == BIT_BLACK)
+/// compute the mask score for a masked micro QR symbol. Other than the penalty score
+/// of standard symbols, a higher score is better.
+pub fn compute_mask_score_micro(masked_symbol: &Matrix) -> u32 {
+    let w = masked_symbol.width();
+
+    // count number of dark modules in right and lower edges of symbol,
+    // not counting the modules of the timing patterns
+    let sum1 = (1..w)
+        .filter(|&y_cur| masked_symbol[(w-1, y_cur)].is_dark())
         .count() as u32;
 
-    let sum2 = (3..(masked_symbol.width()-2))
-        .map(|x_cur| masked_symbol.get_pixel(x_cur, masked_symbol.height()-2))
-        .filter(|&px| *px == BIT_BLACK)
+    let sum2 = (1..w)
+        .filter(|&x_cur| masked_symbol[(x_cur, w-1)].is_dark())
         .count() as u32;
 
     if sum1 <= sum2 {
@@ -211,58 +329,117 @@ fn compute_mask_score_micro(masked_symbol: &image::GrayImage) -> u32 {
     }
 }
 
+/// Apply the given mask pattern to a copy of the unmasked symbol and return it.
+/// Allows to force a certain mask instead of choosing the best one.
+pub fn apply_given_mask(unmasked_symbol: &Matrix, pattern: u8, size: Size) -> QrResult<Matrix> {
+    let canvas = try_create_qr_canvas(size)?;
+    let mut masked_symbol = unmasked_symbol.clone();
+    apply_mask(&mut masked_symbol, pattern, size, &canvas)?;
+    Ok(masked_symbol)
+}
+
+/// Return the number of available mask patterns for the given size
+pub fn num_mask_patterns(size: Size) -> u8 {
+    if size.is_micro() { 4 } else { 8 }
+}
+
+/// Evaluate all mask patterns for the given unmasked symbol and return their scores,
+/// indexed by the pattern index. For micro symbols these are the scores of
+/// compute_mask_score_micro (highest is best), for standard symbols the penalty scores of
+/// compute_mask_penalty_score_standard (lowest is best).
+pub fn compute_mask_scores(unmasked_symbol: &Matrix, size: Size) -> QrResult<Vec<u32>> {
+    let canvas = try_create_qr_canvas(size)?;
+    Ok((0..num_mask_patterns(size))
+        .map(|index| {
+            let mut masked_copy = unmasked_symbol.clone();
+            apply_mask(&mut masked_copy, index, size, &canvas).expect("Mask index in range");
+            match size {
+                Size::Micro(_) => compute_mask_score_micro(&masked_copy),
+                Size::Standard(_) => compute_mask_penalty_score_standard(&masked_copy)
+            }
+        })
+        .collect())
+}
+
+/// Rule to choose between several masks with the same best score
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum MaskTieBreak {
+    LowestIndex,
+    HighestIndex,
+    PayloadHash,    // derived from a hash of the unmasked symbol, the same for the same payload
+    Prefer(u8)      // the given mask if it is among the best, e.g. for reprints of an existing
+                    // symbol, otherwise the lowest index
+}
+
+impl MaskTieBreak {
+    /// The tie-break of apply_best_mask: the lowest index for standard symbols and the
+    /// highest index for micro symbols
+    pub fn default_for(size: Size) -> MaskTieBreak {
+        match size {
+            Size::Micro(_) => MaskTieBreak::HighestIndex,
+            Size::Standard(_) => MaskTieBreak::LowestIndex
+        }
+    }
+}
+
+/// How the mask of a symbol was chosen
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct MaskSelection {
+    pub tie_break: MaskTieBreak,
+    pub candidates: u8,         // number of masks with the best score
+    pub score: u32              // score of the chosen mask, see compute_mask_scores
+}
+
+// FNV-1a hash of the module colors, stable across platforms and releases
+fn symbol_hash(symbol: &Matrix) -> u32 {
+    symbol.enumerate_modules()
+        .fold(0x811c9dc5, |hash, (_, _, module)| (hash ^ module.is_dark() as u32).wrapping_mul(0x01000193))
+}
+
+/// Evaluate all available masks for the given symbol and choose the best one, using the given
+/// rule if several masks have the same best score. Returns the index of the mask.
+pub fn select_best_mask(unmasked_symbol: &Matrix, size: Size, tie_break: MaskTieBreak) -> QrResult<(u8, MaskSelection)> {
+    let scores = compute_mask_scores(unmasked_symbol, size)?;
+    let best_score = match size {
+        Size::Micro(_) => scores.iter().max(),      // mask with highest score is best
+        Size::Standard(_) => scores.iter().min()    // mask with lowest score is best
+    }.copied().unwrap();
+    let candidates: Vec<u8> = (0..num_mask_patterns(size)).filter(|&i| scores[i as usize] == best_score).collect();
+
+    let best_index = match tie_break {
+        MaskTieBreak::LowestIndex => candidates[0],
+        MaskTieBreak::HighestIndex => candidates[candidates.len() - 1],
+        MaskTieBreak::PayloadHash => candidates[symbol_hash(unmasked_symbol) as usize % candidates.len()],
+        MaskTieBreak::Prefer(mask) => if candidates.contains(&mask) { mask } else { candidates[0] }
+    };
+    Ok((best_index, MaskSelection { tie_break, candidates: candidates.len() as u8, score: best_score }))
+}
+
 /// Compute best mask and apply it.
 /// Will evaluate all available masks for the given symbol, apply the best mask and return
 /// the code of that mask and resulting masked symbol.
-pub fn apply_best_mask(unmasked_symbol: &image::GrayImage, size: Size) -> (u8, image::GrayImage) {
-    let canvas = create_qr_canvas(size);
-    match size {
-        Size::Micro(_) => {
-            let (best_index, masked_symbol, _) = {
-                (0..4)
-                .map( | index| {
-                    let mut masked_copy = unmasked_symbol.clone();
-                    apply_mask( & mut masked_copy, index, size, & canvas);
-                    let score = compute_mask_score_micro(&masked_copy);
-                    (index, masked_copy, score)
-                })
-                .max_by_key( | data | data.2)  // mask with highest score is best
-                .unwrap()
-            };
-            (best_index, masked_symbol)
-        },
-        Size::Standard(_) => {
-            let (best_index, masked_symbol, _) = {
-                (0..8)
-                .map( | index| {
-                    let mut masked_copy = unmasked_symbol.clone();
-                    apply_mask( & mut masked_copy, index, size, & canvas);
-                    let score = compute_mask_penalty_score_standard(&masked_copy);
-                    (index, masked_copy, score)
-                })
-                .min_by_key( | data | data.2)  // mask with lowest score is best
-                .unwrap()
-            };
-            (best_index, masked_symbol)
-        }
-    }
+pub fn apply_best_mask(unmasked_symbol: &Matrix, size: Size) -> QrResult<(u8, Matrix)> {
+    let (best_index, _) = select_best_mask(unmasked_symbol, size, MaskTieBreak::default_for(size))?;
+    Ok((best_index, apply_given_mask(unmasked_symbol, best_index, size)?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "encode")]
+    use crate::render::render_matrix;
 
-    fn create_masked_canvas(size: Size, pattern_index: u8) -> image::GrayImage {
+    fn create_masked_canvas(size: Size, pattern_index: u8) -> Matrix {
         // create canvas
         let mut canvas = create_qr_canvas(size);
 
         // retrieve pattern index
-        let pattern = get_masking_function(pattern_index, size);
+        let mask = standard_mask_pattern(pattern_index, size).unwrap();
 
-        // iterate over entire image and create mask in the encoding region
-        for (x, y, pix) in canvas.enumerate_pixels_mut() {
-            if *pix == MARKER_ENCODING_REGION {
-                *pix = if pattern(y as i32, x as i32) { BIT_BLACK } else { BIT_WHITE };
+        // iterate over entire matrix and create mask in the encoding region
+        for (x, y, module) in canvas.enumerate_modules_mut() {
+            if *module == Module::EncodingRegion {
+                *module = Module::from_bit(mask_bit(mask, x, y).unwrap());
             }
         }
 
@@ -270,16 +447,135 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "encode")]
     fn test_masks_micro() {
         for i in 0..4 {
-            create_masked_canvas(Size::Micro(4), i as u8).save(format!("./mask_pattern_M1_{}.png", i)).unwrap();
+            render_matrix(&create_masked_canvas(Size::Micro(4), i as u8), 2)
+                .save(format!("./mask_pattern_M1_{}.png", i)).unwrap();
         }
     }
 
     #[test]
+    #[cfg(feature = "encode")]
     fn test_masks_standard() {
         for i in 0..8 {
-            create_masked_canvas(Size::Standard(1), i as u8).save(format!("./mask_pattern_1_{}.png", i)).unwrap();
+            render_matrix(&create_masked_canvas(Size::Standard(1), i as u8), 4)
+                .save(format!("./mask_pattern_1_{}.png", i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_mask_bit() {
+        // the upper left module is flipped by every mask
+        // mask 010 only depends on the column
+        assert!((0..8).all(|mask| mask_bit(mask, 0, 0) == Some(true)));
+        assert_eq!((mask_bit(0b010, 0, 1), mask_bit(0b010, 1, 0)), (Some(true), Some(false)));
+        // mask 001 only depends on the row
+        assert_eq!((mask_bit(0b001, 5, 2), mask_bit(0b001, 2, 5)), (Some(true), Some(false)));
+        assert_eq!((mask_bit(0b100, 2, 1), mask_bit(0b100, 3, 1)), (Some(true), Some(false)));
+        assert_eq!(mask_bit(8, 0, 0), None);
+
+        assert_eq!(standard_mask_pattern(0b10, Size::Micro(2)), Ok(0b110));
+        assert_eq!(standard_mask_pattern(0b10, Size::Standard(2)), Ok(0b010));
+        assert_eq!(standard_mask_pattern(0b100, Size::Micro(2)), Err(QrError::InvalidMaskPattern(0b100, Size::Micro(2))));
+    }
+
+    #[test]
+    fn test_given_mask() {
+        // forcing the best mask gives the same result as choosing it automatically
+        for &size in [Size::Micro(2), Size::Standard(1)].iter() {
+            let canvas = create_masked_canvas(size, 1);
+            let scores = compute_mask_scores(&canvas, size).unwrap();
+            assert_eq!(scores.len(), num_mask_patterns(size) as usize);
+
+            let (best, masked) = apply_best_mask(&canvas, size).unwrap();
+            assert_eq!(apply_given_mask(&canvas, best, size), Ok(masked));
+            assert!(apply_given_mask(&canvas, num_mask_patterns(size), size).is_err());
         }
+
+        // the scores belong to the symbols masked with the corresponding pattern
+        let canvas = create_masked_canvas(Size::Micro(3), 0);
+        for (mask, &score) in compute_mask_scores(&canvas, Size::Micro(3)).unwrap().iter().enumerate() {
+            assert_eq!(compute_mask_score_micro(&apply_given_mask(&canvas, mask as u8, Size::Micro(3)).unwrap()), score);
+        }
+    }
+
+    #[test]
+    fn test_tie_break() {
+        // masks 1, 2 and 3 score the same for this symbol
+        let size = Size::Micro(1);
+        let canvas = create_masked_canvas(size, 0);
+        assert_eq!(compute_mask_scores(&canvas, size).unwrap(), [0, 69, 69, 69]);
+
+        let (lowest, selection) = select_best_mask(&canvas, size, MaskTieBreak::LowestIndex).unwrap();
+        assert_eq!(lowest, 1);
+        assert_eq!(selection, MaskSelection { tie_break: MaskTieBreak::LowestIndex, candidates: 3, score: 69 });
+        assert_eq!(select_best_mask(&canvas, size, MaskTieBreak::HighestIndex).unwrap().0, 3);
+        assert!((1..4).contains(&select_best_mask(&canvas, size, MaskTieBreak::PayloadHash).unwrap().0));
+        assert_eq!(select_best_mask(&canvas, size, MaskTieBreak::Prefer(2)).unwrap().0, 2);
+        assert_eq!(select_best_mask(&canvas, size, MaskTieBreak::Prefer(0)).unwrap().0, 1);
+
+        // the default reproduces apply_best_mask
+        for &size in [Size::Micro(2), Size::Standard(1)].iter() {
+            let canvas = create_masked_canvas(size, 1);
+            assert_eq!(select_best_mask(&canvas, size, MaskTieBreak::default_for(size)).unwrap().0, apply_best_mask(&canvas, size).unwrap().0);
+        }
+    }
+
+    #[test]
+    fn test_micro_score_edges() {
+        // only the right and lower edges count, except for the timing pattern modules
+        let mut symbol = Matrix::new(11, Module::Light);
+        symbol.set((10, 0), Module::Dark);
+        symbol.set((0, 10), Module::Dark);
+        assert_eq!(compute_mask_score_micro(&symbol), 0);
+
+        symbol.set((10, 5), Module::Dark);
+        symbol.set((10, 10), Module::Dark);
+        symbol.set((3, 10), Module::Dark);
+        // right edge has 2 dark modules, lower edge has 2 as well
+        assert_eq!(compute_mask_score_micro(&symbol), 2 * 16 + 2);
+    }
+
+    #[test]
+    fn test_penalty_map() {
+        for mask in 0..8 {
+            let symbol = create_masked_canvas(Size::Standard(2), mask);
+            let map = compute_penalty_map(&symbol);
+            let score = compute_mask_penalty_score_standard(&symbol);
+            assert!((map.score() - score as f64).abs() < 1e-6, "mask {}: {} != {}", mask, map.score(), score);
+        }
+
+        // in a dark matrix, every module is part of a run of 21 in both directions
+        let map = compute_penalty_map(&Matrix::new(21, Module::Dark));
+        assert!(map.runs.iter().all(|&p| (p - 2.0 * 19.0 / 21.0).abs() < 1e-6));
+        assert_eq!((map.blocks[0], map.blocks[21 + 1]), (0.75, 3.0));
+        assert!((map.total(0, 0) - (0.75 + 38.0 / 21.0)).abs() < 1e-6);
+        assert!(map.finder_like.iter().all(|&p| p == 0.0));
+        assert_eq!(map.balance, 100);
+
+        // a checkerboard has no penalties at all, and no finder patterns to subtract
+        let mut checkerboard = Matrix::new(21, Module::Light);
+        for (x, y, module) in checkerboard.enumerate_modules_mut() {
+            *module = Module::from_bit((x + y) % 2 == 0);
+        }
+        assert_eq!(compute_mask_penalty_score_standard(&checkerboard), 0);
+    }
+
+    #[test]
+    fn test_finder_like_patterns() {
+        let mut artwork = Matrix::new(15, Module::Light);
+        for &x in [4, 6, 7, 8, 10].iter() {
+            artwork.set((x, 3), Module::Dark);
+        }
+        assert_eq!(find_finder_like_patterns(&artwork), vec![FinderLikePattern { x: 4, y: 3, vertical: false }]);
+
+        // dark modules on both sides hide the pattern
+        artwork.set((1, 3), Module::Dark);
+        artwork.set((13, 3), Module::Dark);
+        assert!(find_finder_like_patterns(&artwork).is_empty());
+
+        // too small for any pattern
+        assert!(find_finder_like_patterns(&Matrix::new(5, Module::Dark)).is_empty());
     }
 }
@@ -1,8 +1,9 @@
-/// Contains lookup tables and other computation functions that provide reference
-/// data needed for encoding or decing a QR code, such as the capacity of each
-/// code configuration in different encodings, etc.
+//! Contains lookup tables and other computation functions that provide reference
+//! data needed for encoding or decing a QR code, such as the capacity of each
+//! code configuration in different encodings, etc.
 
 use crate::config::{Encoding, ECCLevel, Size, SymbolConfig};
+use crate::error::{QrError, QrResult};
 
 use std::collections::HashMap;
 use std::ops::Index;
@@ -22,7 +23,7 @@ impl BlockDef {
                      words_total: u32,
                      words: u32) -> BlockDef {
         BlockDef {
-            num_blocks: num_blocks,
+            num_blocks,
             codewords: words_total,
             data_codewords: words
         }
@@ -59,6 +60,7 @@ impl Index<Encoding> for SymbolCapacity {
 
 impl SymbolCapacity {
     /// constructor for entries with two block types
+    #[allow(clippy::too_many_arguments)]
     pub const fn new(bits: u32,
                      charsnum: u32,
                      charsalphanum: u32,
@@ -83,26 +85,43 @@ impl SymbolCapacity {
 
     /// compute and return the total number of codewords for this symbol (capacity)
     pub fn codewords(&self) -> u32 {
-        &self.block_def1.num_blocks * &self.block_def1.codewords +
-        &self.block_def2.num_blocks * &self.block_def2.codewords
+        self.block_def1.num_blocks * self.block_def1.codewords +
+        self.block_def2.num_blocks * self.block_def2.codewords
     }
 
     /// compute and return the number of data codewords for this symbol (capacity)
     pub fn data_codewords(&self) -> u32 {
-        &self.block_def1.num_blocks * &self.block_def1.data_codewords +
-        &self.block_def2.num_blocks * &self.block_def2.data_codewords
+        self.block_def1.num_blocks * self.block_def1.data_codewords +
+        self.block_def2.num_blocks * self.block_def2.data_codewords
     }
 
     /// compute and return the number of ecc codewords for this symbol
     pub fn ecc_words(&self) -> u32 {
-        &self.codewords() - &self.data_codewords()
+        self.codewords() - self.data_codewords()
     }
 
     /// compute and return the number of ecc codewords per block for this symbol
-    pub fn ecc_words_per_block(&self) -> u32 { &self.block_def1.codewords - &self.block_def1.data_codewords }
+    pub fn ecc_words_per_block(&self) -> u32 { self.block_def1.codewords - self.block_def1.data_codewords }
 
     /// compute and return number of blocks
-    pub fn num_blocks(&self) -> u32 { &self.block_def1.num_blocks + &self.block_def2.num_blocks }
+    pub fn num_blocks(&self) -> u32 { self.block_def1.num_blocks + self.block_def2.num_blocks }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Error correction capacity of a symbol, i.e. how many erroneous codewords the
+/// Reed-Solomon decoding can still repair.
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub struct CorrectionCapacity {
+    pub correctable_codewords_per_block: u32,   // codeword errors that can be corrected in every block
+    pub total_correctable_codewords: u32,       // sum of the above over all blocks of the symbol
+}
+
+impl CorrectionCapacity {
+    /// compute and return the number of modules covered by the correctable codewords. This
+    /// assumes the damage is aligned to codeword boundaries, so it is an upper bound for the
+    /// number of modules that may be obscured.
+    pub fn total_correctable_modules(&self) -> u32 { self.total_correctable_codewords * 8 }
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
@@ -349,36 +368,233 @@ define_capacity_table!(
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////
 
-/// Convenience function that just indexes into the static table
+/// Convenience function that just indexes into the static table.
+/// Panics for combinations of size and ECC level that do not exist.
 pub fn lookup_capacity(s: Size, ecc: ECCLevel) -> SymbolCapacity {
     SYMBOL_CAPACITY_TABLE[&SymbolConfig::new(s, ecc)]
 }
 
+// index of a symbol configuration in CAPACITY_BY_INDEX: micro versions first, then standard
+// versions, four ECC levels each
+const fn capacity_index(version: u8, micro: bool, ecc: ECCLevel) -> usize {
+    let row = if micro { version as usize - 1 } else { 4 + version as usize - 1 };
+    row * 4 + ecc as usize
+}
+
+lazy_static! {
+    // the capacity table as a plain array, for lookups without hashing
+    static ref CAPACITY_BY_INDEX: [Option<SymbolCapacity>; 44 * 4] = {
+        let mut table = [None; 44 * 4];
+        for (config, &capacity) in SYMBOL_CAPACITY_TABLE.iter() {
+            let (micro, version) = match config.size() {
+                Size::Micro(i) => (true, i),
+                Size::Standard(i) => (false, i)
+            };
+            table[capacity_index(version, micro, config.ecc())] = Some(capacity);
+        }
+        table
+    };
+}
+
+/// Same as try_lookup_capacity, but by plain version number, without constructing a Size or
+/// hashing. Meant for hot loops such as the search for the smallest size. Returns None for
+/// combinations that do not exist.
+pub fn lookup_capacity_by_index(version: u8, micro: bool, ecc: ECCLevel) -> Option<SymbolCapacity> {
+    let max_version = if micro { 4 } else { 40 };
+    if version == 0 || version > max_version {
+        return None;
+    }
+    CAPACITY_BY_INDEX[capacity_index(version, micro, ecc)]
+}
+
+/// Same as lookup_capacity, but returns an error for combinations of size and ECC level
+/// that do not exist
+pub fn try_lookup_capacity(s: Size, ecc: ECCLevel) -> QrResult<SymbolCapacity> {
+    if !s.is_valid() {
+        return Err(QrError::InvalidSize(s));
+    }
+    SYMBOL_CAPACITY_TABLE.get(&SymbolConfig::new(s, ecc))
+        .copied()
+        .ok_or(QrError::InvalidSymbolConfig(s, ecc))
+}
+
+/// Return the number of bits of the last data codeword. In M1 and M3 symbols it only holds
+/// four bits, in the high nibble with the low nibble left zero; in all other sizes it is a
+/// full codeword. The data bits of the capacity already take this into account.
+pub fn last_data_codeword_bits(s: Size) -> u32 {
+    match s {
+        Size::Micro(1) | Size::Micro(3) => 4,
+        _ => 8
+    }
+}
+
+/// Check that a payload of the given number of bits fits into the data capacity, e.g. for
+/// bitstreams built outside of the crate
+pub fn check_data_bits(bits: usize, s: Size, ecc: ECCLevel) -> QrResult<()> {
+    let capacity = try_lookup_capacity(s, ecc)?.data_bits as usize;
+    if bits > capacity {
+        return Err(QrError::DataTooLong { bits, capacity, suggestion: None });
+    }
+    Ok(())
+}
+
+/// Check data codewords built outside of the crate: their number must match the capacity,
+/// and the unused low nibble of the last codeword of M1 and M3 symbols must be zero, as it is
+/// not placed into the symbol.
+pub fn check_data_codewords(data: &[u8], s: Size, ecc: ECCLevel) -> QrResult<()> {
+    let expected = try_lookup_capacity(s, ecc)?.data_codewords() as usize;
+    if data.len() != expected {
+        return Err(QrError::WrongNumberOfCodewords { expected, actual: data.len() });
+    }
+    match data.last() {
+        Some(&last) if last_data_codeword_bits(s) == 4 && last & 0x0F != 0 => Err(QrError::InvalidLastCodeword(last)),
+        _ => Ok(())
+    }
+}
+
 /// Returns the number of misdecode protection codewords p
 pub fn get_p_for_symbol(s: Size, ecc: ECCLevel) -> u8 {
     // by definition in the standard ISO/IEC 18004:2015
     match s {
         Size::Micro(1) => 2,
-        Size::Micro(2) => if ecc == ECCLevel::L { 3 } else { 2 },
+        Size::Micro(2) if ecc == ECCLevel::L => 3,
+        Size::Micro(2) => 2,
         Size::Micro(3) => 2,
-        Size::Micro(4) => if ecc == ECCLevel::L { 2 } else { 0 },
+        Size::Micro(4) if ecc == ECCLevel::L => 2,
         Size::Standard(1) => match ecc {
             ECCLevel::L => 3,
             ECCLevel::M => 2,
             _ => 1
         },
-        Size::Standard(2) => if ecc == ECCLevel::L {2} else {0},
-        Size::Standard(3) => if ecc == ECCLevel::L {1} else {0},
+        Size::Standard(2) if ecc == ECCLevel::L => 2,
+        Size::Standard(3) if ecc == ECCLevel::L => 1,
         _ => 0
     }
 }
 
+/// Compute the error correction capacity of a symbol from its block definitions and the number
+/// of misdecode protection codewords p. Every block can correct up to (e - p) / 2 erroneous
+/// codewords, with e the number of ecc codewords in that block (see 7.5.1 in the standard).
+/// Panics for combinations of size and ECC level that do not exist, see try_correction_capacity.
+pub fn correction_capacity(s: Size, ecc: ECCLevel) -> CorrectionCapacity {
+    let cap = lookup_capacity(s, ecc);
+    let p = get_p_for_symbol(s, ecc) as u32;
+
+    // both block types of a symbol always have the same number of ecc codewords
+    let per_block = (cap.ecc_words_per_block() - p) / 2;
+
+    CorrectionCapacity {
+        correctable_codewords_per_block: per_block,
+        total_correctable_codewords: per_block * cap.num_blocks(),
+    }
+}
+
+/// Same as correction_capacity, but returns an error for combinations of size and ECC level
+/// that do not exist
+pub fn try_correction_capacity(s: Size, ecc: ECCLevel) -> QrResult<CorrectionCapacity> {
+    try_lookup_capacity(s, ecc)?;
+    Ok(correction_capacity(s, ecc))
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// A violation of the invariants every capacity table has to fulfill
+#[derive(Clone,Copy,Hash,Eq,PartialEq,Debug)]
+pub enum TableViolation {
+    TotalCodewordsMismatch(Size),           // the ECC levels of a size differ in the total number of codewords
+    BlockSizeMismatch(SymbolConfig),        // second block type is not exactly one codeword larger than the first
+    DataBitsMismatch(SymbolConfig)          // data bits do not match the number of data codewords
+}
+
+/// Check the given capacity table for consistency and return all violations found.
+/// The entries are checked in the order of their size and ECC level; sizes
+/// missing in the table are skipped.
+pub fn check_table(table: &HashMap<SymbolConfig, SymbolCapacity>) -> Vec<TableViolation> {
+    let mut violations = Vec::new();
+    for size in Size::all() {
+        let entries: Vec<_> = ECCLevel::all()
+            .filter_map(|l| table.get(&SymbolConfig::new(size, l)).map(|cap| (SymbolConfig::new(size, l), cap)))
+            .collect();
+
+        // all levels of a size share the same number of codewords
+        if entries.iter().any(|(_, cap)| cap.codewords() != entries[0].1.codewords()) {
+            violations.push(TableViolation::TotalCodewordsMismatch(size));
+        }
+
+        for &(config, cap) in entries.iter() {
+            // the second block type has exactly one more data codeword than the first
+            if cap.block_def2.num_blocks != 0 &&
+               (cap.block_def1.codewords + 1 != cap.block_def2.codewords ||
+                cap.block_def1.data_codewords + 1 != cap.block_def2.data_codewords) {
+                violations.push(TableViolation::BlockSizeMismatch(config));
+            }
+
+            // the last data codeword of M1 and M3 symbols only has four bits
+            let data_bits = match size {
+                Size::Micro(1) | Size::Micro(3) => cap.data_codewords() * 8 - 4,
+                _ => cap.data_codewords() * 8
+            };
+            if data_bits != cap.data_bits {
+                violations.push(TableViolation::DataBitsMismatch(config));
+            }
+        }
+    }
+
+    violations
+}
+
+/// Check the built-in capacity table for consistency, see check_table
+pub fn self_check() -> Vec<TableViolation> {
+    check_table(&SYMBOL_CAPACITY_TABLE)
+}
+
 ///////////////////////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_try_lookup() {
+        assert_eq!(try_lookup_capacity(Size::Micro(3), ECCLevel::M), Ok(lookup_capacity(Size::Micro(3), ECCLevel::M)));
+        assert_eq!(try_lookup_capacity(Size::Micro(1), ECCLevel::M), Err(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::M)));
+        assert_eq!(try_lookup_capacity(Size::Standard(41), ECCLevel::L), Err(QrError::InvalidSize(Size::Standard(41))));
+    }
+
+    #[test]
+    fn test_short_last_codeword() {
+        assert_eq!(last_data_codeword_bits(Size::Micro(1)), 4);
+        assert_eq!(last_data_codeword_bits(Size::Micro(2)), 8);
+        assert_eq!(last_data_codeword_bits(Size::Micro(3)), 4);
+        assert_eq!(last_data_codeword_bits(Size::Standard(1)), 8);
+
+        // M1 holds 20 data bits in three codewords
+        assert_eq!(check_data_bits(20, Size::Micro(1), ECCLevel::L), Ok(()));
+        assert_eq!(check_data_bits(24, Size::Micro(1), ECCLevel::L),
+                   Err(QrError::DataTooLong { bits: 24, capacity: 20, suggestion: None }));
+        assert_eq!(check_data_codewords(&[0x12, 0x34, 0x50], Size::Micro(1), ECCLevel::L), Ok(()));
+        assert_eq!(check_data_codewords(&[0x12, 0x34, 0x56], Size::Micro(1), ECCLevel::L), Err(QrError::InvalidLastCodeword(0x56)));
+        assert_eq!(check_data_codewords(&[0x12, 0x34], Size::Micro(1), ECCLevel::L),
+                   Err(QrError::WrongNumberOfCodewords { expected: 3, actual: 2 }));
+        assert_eq!(check_data_codewords(&[0xFF; 5], Size::Micro(2), ECCLevel::L), Ok(()));
+    }
+
+    #[test]
+    fn test_lookup_by_index() {
+        for (config, &capacity) in SYMBOL_CAPACITY_TABLE.iter() {
+            let (micro, version) = match config.size() {
+                Size::Micro(i) => (true, i),
+                Size::Standard(i) => (false, i)
+            };
+            assert_eq!(lookup_capacity_by_index(version, micro, config.ecc()), Some(capacity));
+        }
+        assert_eq!(lookup_capacity_by_index(1, true, ECCLevel::M), None);
+        assert_eq!(lookup_capacity_by_index(4, true, ECCLevel::H), None);
+        assert_eq!(lookup_capacity_by_index(5, true, ECCLevel::L), None);
+        assert_eq!(lookup_capacity_by_index(0, false, ECCLevel::L), None);
+        assert_eq!(lookup_capacity_by_index(41, false, ECCLevel::L), None);
+    }
+
     #[test]
     fn test_table1() {
         assert_eq!(lookup_capacity(Size::Micro(3), ECCLevel::M).data_codewords(), 9);
@@ -386,6 +602,20 @@ mod tests {
         assert_eq!(lookup_capacity(Size::Standard(1), ECCLevel::Q).ecc_words(), 13);
     }
 
+    #[test]
+    fn test_correction_capacity() {
+        // M1 symbols only support error detection
+        assert_eq!(correction_capacity(Size::Micro(1), ECCLevel::L).total_correctable_codewords, 0);
+        // 1-L: 7 ecc codewords, 3 of those for misdecode protection
+        assert_eq!(correction_capacity(Size::Standard(1), ECCLevel::L).correctable_codewords_per_block, 2);
+        assert_eq!(correction_capacity(Size::Standard(1), ECCLevel::H).correctable_codewords_per_block, 8);
+        // 5-Q: four blocks with 18 ecc codewords each
+        let cap = correction_capacity(Size::Standard(5), ECCLevel::Q);
+        assert_eq!(cap.correctable_codewords_per_block, 9);
+        assert_eq!(cap.total_correctable_codewords, 36);
+        assert_eq!(cap.total_correctable_modules(), 288);
+    }
+
     #[test]
     fn test_table2() {
         // check for all entries of a single size that all LMQH levels return the same number of
@@ -417,6 +647,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_self_check() {
+        assert_eq!(self_check(), []);
+
+        // break a copy of the table
+        let mut table = SYMBOL_CAPACITY_TABLE.clone();
+        let config = SymbolConfig::new(Size::Standard(5), ECCLevel::Q);
+        table.get_mut(&config).unwrap().block_def2.codewords += 1;
+        table.get_mut(&config).unwrap().block_def2.data_codewords += 1;
+        table.get_mut(&SymbolConfig::new(Size::Micro(3), ECCLevel::L)).unwrap().data_bits += 4;
+        assert_eq!(check_table(&table), [
+            TableViolation::DataBitsMismatch(SymbolConfig::new(Size::Micro(3), ECCLevel::L)),
+            TableViolation::TotalCodewordsMismatch(Size::Standard(5)),
+            TableViolation::BlockSizeMismatch(config),
+            TableViolation::DataBitsMismatch(config)
+        ]);
+    }
+
     #[test]
     fn test_table4() {
         // check the number of data bits is exactly 8 times the number of data words
@@ -1,3 +1,4 @@
+#![cfg(feature = "encode")]
 
 use qr_gen::*;
 
@@ -5,24 +6,751 @@ use qr_gen::*;
 #[test]
 fn test_micro_symbol() {
     // create symbol
-    let masked_symbol = create_qr_code(b"1234567", Size::Micro(3), ECCLevel::M, Some(Encoding::Numeric));
+    let masked_symbol = create_qr_code(b"1234567", Size::Micro(3), ECCLevel::M, Some(Encoding::Numeric), None).unwrap();
 
     // save it
     masked_symbol.save("./micro3M_1234567.test.png").unwrap();
 }
 
 #[test]
+#[allow(non_snake_case)]
 fn test_standard_symbol_6H() {
-    let masked_symbol = create_qr_code(b"AC-47", Size::Standard(6), ECCLevel::H, Some(Encoding::Alphanumeric));
+    let masked_symbol = create_qr_code(b"AC-47", Size::Standard(6), ECCLevel::H, Some(Encoding::Alphanumeric), None).unwrap();
 
     // save it
     masked_symbol.save("./standard6H_AC-47.test.png").unwrap();
 }
 
 #[test]
+#[allow(non_snake_case)]
 fn test_standard_symbol_7Q() {
-    let masked_symbol = create_qr_code(b"AC-47", Size::Standard(7), ECCLevel::Q, Some(Encoding::Alphanumeric));
+    let masked_symbol = create_qr_code(b"AC-47", Size::Standard(7), ECCLevel::Q, Some(Encoding::Alphanumeric), None).unwrap();
 
     // save it
     masked_symbol.save("./standard7Q_AC-47.test.png").unwrap();
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_layers() {
+    // going through the layers one by one must give the same result as the high-level function
+    let (data, ecc) = encode_codewords(b"01234567", Size::Standard(1), ECCLevel::M, Encoding::Numeric).unwrap();
+    assert_eq!(data.len(), 16);
+    assert_eq!(ecc.len(), 10);
+
+    let code = place_codewords(&data, &ecc, Size::Standard(1), ECCLevel::M).unwrap();
+    assert_eq!(code.matrix.width(), 21);
+
+    let img = render::to_gray_image(&code);
+    assert_eq!(img, create_qr_code(b"01234567", Size::Standard(1), ECCLevel::M, Some(Encoding::Numeric), None).unwrap());
+}
+
+#[test]
+fn test_automatic_encoding() {
+    // without an encoding, the content is segmented optimally
+    assert_eq!(create_qr_code(b"AC-47", Size::Standard(2), ECCLevel::M, None, None),
+               create_qr_code(b"AC-47", Size::Standard(2), ECCLevel::M, Some(Encoding::Alphanumeric), None));
+    assert_eq!(create_qr_code(b"1234567", Size::Micro(3), ECCLevel::M, None, None),
+               create_qr_code(b"1234567", Size::Micro(3), ECCLevel::M, Some(Encoding::Numeric), None));
+
+    // mixed content fits into a smaller symbol when segmented
+    assert!(create_qr_code(b"ORDER-0001234567", Size::Micro(3), ECCLevel::L, Some(Encoding::Alphanumeric), None).is_err());
+    assert!(create_qr_code(b"ORDER-0001234567", Size::Micro(3), ECCLevel::L, None, None).is_ok());
+}
+
+#[test]
+fn test_forced_micro_mask() {
+    let (data, ecc) = encode_codewords(b"1234567", Size::Micro(3), ECCLevel::M, Encoding::Numeric).unwrap();
+    let best = place_codewords(&data, &ecc, Size::Micro(3), ECCLevel::M).unwrap();
+    for mask in 0..4 {
+        let code = place_codewords_with_mask(&data, &ecc, Size::Micro(3), ECCLevel::M, mask).unwrap();
+        assert_eq!(code.mask, mask);
+        assert_eq!(code.mask_selection, None);
+        assert_eq!(code.matrix == best.matrix, mask == best.mask);
+    }
+}
+
+#[test]
+fn test_custom_mask() {
+    let (data, ecc) = encode_codewords(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, Encoding::Alphanumeric).unwrap();
+    // a custom condition that equals a standard pattern gives the same symbol
+    let standard = |x: i32, y: i32| serialization::masking::mask_bit(2, x as u32, y as u32) == Some(true);
+    assert_eq!(place_codewords_with_custom_mask(&data, &ecc, Size::Standard(1), ECCLevel::Q, standard, 2),
+               place_codewords_with_mask(&data, &ecc, Size::Standard(1), ECCLevel::Q, 2));
+
+    let code = place_codewords_with_custom_mask(&data, &ecc, Size::Standard(1), ECCLevel::Q, |x, y| x * y % 5 == 0, 6).unwrap();
+    assert_eq!(inspect_matrix(&code.matrix), Ok(SymbolInfo { size: Size::Standard(1), level: ECCLevel::Q, mask: 6 }));
+    assert_ne!(code.matrix, place_codewords_with_mask(&data, &ecc, Size::Standard(1), ECCLevel::Q, 6).unwrap().matrix);
+    assert_eq!(place_codewords_with_custom_mask(&data, &ecc, Size::Standard(1), ECCLevel::Q, |_, _| true, 8),
+               Err(QrError::InvalidMaskPattern(8, Size::Standard(1))));
+}
+
+// a symbol family defined outside of the crate: one finder and one alignment pattern,
+// without timing patterns, holding 12 data and 8 ecc codewords in a single block
+struct Square16;
+
+impl serialization::SymbolFamily for Square16 {
+    fn layout(&self) -> serialization::CustomLayout {
+        serialization::CustomLayout { width: 16, finders: vec![(-1, -1)], alignments: vec![(12, 12)], timing: None }
+    }
+
+    fn capacity(&self, level: ECCLevel) -> QrResult<tables::SymbolCapacity> {
+        match level {
+            ECCLevel::L => Ok(tables::SymbolCapacity::new(96, 0, 0, 0, 0, 1, 20, 12, 0, 0, 0)),
+            _ => Err(QrError::InvalidSymbolConfig(Size::Standard(1), level))
+        }
+    }
+}
+
+#[test]
+fn test_custom_symbol() {
+    let data: Vec<u8> = (0..12).collect();
+    let symbol = create_custom_symbol(&Square16, &data, ECCLevel::L, |x, y| (x + y) % 2 == 0).unwrap();
+    assert!(symbol.is_complete());
+    assert_eq!(symbol.width(), 16);
+    assert_eq!(create_custom_symbol(&Square16, &data[1..], ECCLevel::L, |_, _| false),
+               Err(QrError::WrongNumberOfCodewords { expected: 12, actual: 11 }));
+    assert!(create_custom_symbol(&Square16, &data, ECCLevel::M, |_, _| false).is_err());
+
+    // the standard sizes are a family as well, and out-of-range sizes are rejected
+    let data = [0u8; 16];
+    assert_eq!(create_custom_symbol(&Size::Standard(1), &data, ECCLevel::M, |_, _| false).unwrap().width(), 21);
+    assert_eq!(create_custom_symbol(&Size::Standard(41), &data, ECCLevel::M, |_, _| false),
+               Err(QrError::InvalidSize(Size::Standard(41))));
+}
+
+#[test]
+fn test_invalid_size() {
+    for size in [Size::Standard(41), Size::Micro(5)] {
+        assert_eq!(serialization::try_create_qr_canvas(size), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::area_is_correctable(size, ECCLevel::L, (0, 0, 1)), Err(QrError::InvalidSize(size)));
+        assert_eq!(tables::try_correction_capacity(size, ECCLevel::L), Err(QrError::InvalidSize(size)));
+        let mut matrix = Matrix::new(21, Module::Light);
+        assert_eq!(serialization::insert_format_info(&mut matrix, size, ECCLevel::L, 0), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::insert_raw_format_info(&mut matrix, size, 0), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::insert_version_info(&mut matrix, size), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::info_placement(size), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::masking::compute_mask_scores(&matrix, size), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::masking::select_best_mask(&matrix, size, serialization::masking::MaskTieBreak::LowestIndex), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::masking::apply_best_mask(&matrix, size), Err(QrError::InvalidSize(size)));
+        assert_eq!(serialization::masking::apply_given_mask(&matrix, 0, size), Err(QrError::InvalidSize(size)));
+        // nothing was written
+        assert_eq!(matrix, Matrix::new(21, Module::Light));
+        assert_eq!(create_qr_matrix(b"1", size, ECCLevel::L, None, None), Err(QrError::InvalidSize(size)));
+    }
+    assert!(serialization::try_create_qr_canvas(Size::Micro(4)).is_ok());
+}
+
+#[test]
+fn test_auto_size() {
+    // 1-L holds 41 digits, 1-M 34, 1-Q 27 and 1-H 17
+    let digits = [b'7'; 30];
+    let code = create_qr_matrix_auto(&digits, ECCLevel::L, None, None, SizeSelection::default()).unwrap();
+    assert_eq!((code.size, code.level), (Size::Standard(1), ECCLevel::L));
+    let code = create_qr_matrix_auto(&digits, ECCLevel::L, None, None, SizeSelection { boost_ecc: true, ..SizeSelection::default() }).unwrap();
+    assert_eq!((code.size, code.level), (Size::Standard(1), ECCLevel::M));
+    let code = create_qr_matrix_auto(&digits[..10], ECCLevel::M, None, None, SizeSelection { boost_ecc: true, ..SizeSelection::default() }).unwrap();
+    assert_eq!((code.size, code.level), (Size::Standard(1), ECCLevel::H));
+
+    // the size is chosen for the requested level, never bumped for boosting
+    let code = create_qr_matrix_auto(&digits, ECCLevel::H, None, None, SizeSelection { boost_ecc: true, ..SizeSelection::default() }).unwrap();
+    assert_eq!((code.size, code.level), (Size::Standard(2), ECCLevel::H));
+
+    // version range
+    let selection = SizeSelection { min_version: 5, ..SizeSelection::default() };
+    assert_eq!(create_qr_matrix_auto(&digits, ECCLevel::L, None, None, selection).unwrap().size, Size::Standard(5));
+    let selection = SizeSelection { max_version: 1, ..SizeSelection::default() };
+    assert!(matches!(create_qr_matrix_auto(&digits, ECCLevel::H, None, None, selection), Err(QrError::DataTooLong { .. })));
+    let selection = SizeSelection { min_version: 3, max_version: 2, ..SizeSelection::default() };
+    assert_eq!(create_qr_matrix_auto(&digits, ECCLevel::L, None, None, selection), Err(QrError::InvalidSize(Size::Standard(3))));
+    let selection = SizeSelection { max_version: 41, ..SizeSelection::default() };
+    assert_eq!(create_qr_matrix_auto(&digits, ECCLevel::L, None, None, selection), Err(QrError::InvalidSize(Size::Standard(41))));
+}
+
+#[test]
+fn test_auto_size_micro() {
+    let prefer = SizeSelection { micro: MicroPolicy::PreferMicro, ..SizeSelection::default() };
+    let only = SizeSelection { micro: MicroPolicy::MicroOnly, ..SizeSelection::default() };
+    let auto = |content: &[u8], level, selection| create_qr_matrix_auto(content, level, None, None, selection).map(|code| code.size);
+
+    assert_eq!(auto(b"12345", ECCLevel::L, prefer), Ok(Size::Micro(1)));
+    // M1 only supports numeric mode and level L, M2 no bytes
+    assert_eq!(auto(b"ABC", ECCLevel::L, prefer), Ok(Size::Micro(2)));
+    assert_eq!(auto(b"12345", ECCLevel::M, prefer), Ok(Size::Micro(2)));
+    assert_eq!(auto(b"abc", ECCLevel::L, prefer), Ok(Size::Micro(3)));
+    // micro symbols do not support H and ECI headers
+    assert_eq!(auto(b"12345", ECCLevel::H, prefer), Ok(Size::Standard(1)));
+    assert_eq!(create_qr_matrix_auto(b"12345", ECCLevel::L, None, Some(26), prefer).unwrap().size, Size::Standard(1));
+
+    assert_eq!(auto(b"12345", ECCLevel::H, only), Err(QrError::InvalidSymbolConfig(Size::Micro(4), ECCLevel::H)));
+    assert!(matches!(auto(&[b'a'; 30], ECCLevel::L, only), Err(QrError::DataTooLong { .. })));
+    assert_eq!(auto(b"12345", ECCLevel::L, SizeSelection::default()), Ok(Size::Standard(1)));
+}
+
+#[test]
+fn test_logo() {
+    let content = b"https://example.com/products/4711";
+    let logo = |level, selection, ratio| create_qr_matrix_with_logo(content, level, None, None, selection, ratio)
+        .map(|code| (code.size, code.level));
+    let plain = create_qr_matrix_auto(content, ECCLevel::L, None, None, SizeSelection::default()).unwrap();
+    assert_eq!(logo(ECCLevel::L, SizeSelection::default(), 0.0), Ok((plain.size, plain.level)));
+
+    // a logo needs a higher level or a larger version, never a smaller level than requested
+    let (size, level) = logo(ECCLevel::L, SizeSelection::default(), 0.3).unwrap();
+    assert!((size, level) != (plain.size, plain.level));
+    assert_eq!(serialization::area_is_correctable(size, level, serialization::centered_area(size, 0.3)), Ok(true));
+    assert!(logo(ECCLevel::Q, SizeSelection::default(), 0.3).unwrap().1 >= ECCLevel::Q);
+
+    let selection = SizeSelection { max_version: 3, ..SizeSelection::default() };
+    assert_eq!(logo(ECCLevel::L, selection, 0.6), Err(QrError::LogoTooLarge));
+    assert!(matches!(logo(ECCLevel::L, SizeSelection { max_version: 1, ..selection }, 0.1), Err(QrError::DataTooLong { .. })));
+
+    // no micro size supports level H, reported like in create_qr_matrix_auto
+    let micro_only = SizeSelection { micro: MicroPolicy::MicroOnly, ..SizeSelection::default() };
+    let expected = Err(QrError::InvalidSymbolConfig(Size::Micro(4), ECCLevel::H));
+    assert_eq!(logo(ECCLevel::H, micro_only, 0.1), expected);
+    assert_eq!(create_qr_matrix_auto(b"1", ECCLevel::H, None, None, micro_only).map(|code| (code.size, code.level)), expected);
+}
+
+#[test]
+fn test_from_codewords() {
+    let code = create_qr_matrix(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, None, None).unwrap();
+    let (data, ecc) = encode_codewords(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, Encoding::Alphanumeric).unwrap();
+    let codewords = [data, ecc].concat();
+
+    let reconstructed = create_qr_matrix_from_codewords(&codewords, Size::Standard(1), ECCLevel::Q, None).unwrap();
+    assert_eq!((reconstructed.matrix, reconstructed.mask), (code.matrix.clone(), code.mask));
+    let masked = create_qr_matrix_from_codewords(&codewords, Size::Standard(1), ECCLevel::Q, Some(code.mask)).unwrap();
+    assert_eq!(masked.matrix, code.matrix);
+    assert_eq!(create_qr_code_from_codewords(&codewords, Size::Standard(1), ECCLevel::Q, None).unwrap(),
+               render::to_gray_image(&code));
+
+    assert_eq!(create_qr_matrix_from_codewords(&codewords[1..], Size::Standard(1), ECCLevel::Q, None),
+               Err(QrError::WrongNumberOfCodewords { expected: 26, actual: 25 }));
+
+    // the last data codeword of M1 only holds four bits
+    let (mut data, ecc) = encode_codewords(b"123", Size::Micro(1), ECCLevel::L, Encoding::Numeric).unwrap();
+    assert!(create_qr_matrix_from_codewords(&[data.clone(), ecc.clone()].concat(), Size::Micro(1), ECCLevel::L, None).is_ok());
+    data[2] |= 0x01;
+    assert_eq!(create_qr_matrix_from_codewords(&[data.clone(), ecc].concat(), Size::Micro(1), ECCLevel::L, None),
+               Err(QrError::InvalidLastCodeword(data[2])));
+
+    // data codewords in block order, of a symbol with blocks of two lengths
+    let mut encoder = bitcoding::QrBitRecorder::new();
+    bitcoding::encode_data_segment(&mut encoder, b"DATA CODEWORDS", Encoding::Alphanumeric, Size::Standard(5)).unwrap();
+    let data = bitcoding::finalize_bitstream(&mut encoder, Size::Standard(5), ECCLevel::Q).unwrap();
+    let code = create_qr_matrix(b"DATA CODEWORDS", Size::Standard(5), ECCLevel::Q, Some(Encoding::Alphanumeric), None).unwrap();
+    assert_eq!(create_qr_matrix_from_data_codewords(&data, Size::Standard(5), ECCLevel::Q, None).unwrap().matrix, code.matrix);
+    let masked = create_qr_matrix_from_data_codewords(&data, Size::Standard(5), ECCLevel::Q, Some(1)).unwrap();
+    assert_eq!(masked.matrix, create_qr_matrix_with_mask(b"DATA CODEWORDS", Size::Standard(5), ECCLevel::Q, Some(Encoding::Alphanumeric), None, 1).unwrap().matrix);
+    assert_eq!(create_qr_matrix_from_data_codewords(&data[1..], Size::Standard(5), ECCLevel::Q, None),
+               Err(QrError::WrongNumberOfCodewords { expected: 62, actual: 61 }));
+
+    // de-interleaving the placed codewords gives back the blocks
+    let (interleaved, ecc) = reedsolomon::construct_codewords(&data, Size::Standard(5), ECCLevel::Q).unwrap();
+    let blocks = blocks::deinterleave_codewords(&[interleaved, ecc].concat(), Size::Standard(5), ECCLevel::Q).unwrap();
+    let data_blocks: Vec<u8> = blocks.iter().flat_map(|(data, _)| data.iter().copied()).collect();
+    assert_eq!(data_blocks, data);
+}
+
+#[test]
+fn test_text_content() {
+    let bytes = create_qr_matrix(b"Hello", Size::Standard(1), ECCLevel::M, None, None).unwrap();
+    assert_eq!(create_qr_matrix("Hello", Size::Standard(1), ECCLevel::M, None, None).unwrap(), bytes);
+    assert_eq!(create_qr_matrix(String::from("Hello"), Size::Standard(1), ECCLevel::M, None, None).unwrap(), bytes);
+    assert_eq!(create_qr_matrix(b"Hello".to_vec(), Size::Standard(1), ECCLevel::M, None, None).unwrap(), bytes);
+    assert_eq!(create_qr_code(std::borrow::Cow::Borrowed("Hello"), Size::Standard(1), ECCLevel::M, None, None).unwrap(),
+               render::to_gray_image(&bytes));
+
+    // texts are downgraded to ISO 8859-1, or marked as UTF-8 if that is not possible
+    let latin1 = create_qr_matrix(&b"Gr\xFC\xDFe"[..], Size::Standard(2), ECCLevel::M, None, None).unwrap();
+    assert_eq!(create_qr_matrix("Grüße", Size::Standard(2), ECCLevel::M, None, None).unwrap(), latin1);
+    assert_eq!(create_qr_matrix("5 €", Size::Standard(2), ECCLevel::M, None, None),
+               create_qr_matrix("5 €".as_bytes(), Size::Standard(2), ECCLevel::M, None, Some(26)));
+    assert_eq!(create_qr_matrix("Grüße", Size::Standard(2), ECCLevel::M, None, Some(26)),
+               create_qr_matrix("Grüße".as_bytes(), Size::Standard(2), ECCLevel::M, None, Some(26)));
+    // micro symbols can not have an ECI header
+    assert_eq!(create_qr_matrix("5 €", Size::Micro(4), ECCLevel::L, None, None),
+               create_qr_matrix("5 €".as_bytes(), Size::Micro(4), ECCLevel::L, None, None));
+    assert_eq!(encode_with_options("5 €", EncodeOptions::default()).unwrap().size, Size::Standard(1));
+}
+
+#[test]
+fn test_plan() {
+    let content = "HELLO WORLD 2024 hello";
+    let plan = plan(content, ECCLevel::M, None, None, SizeSelection::default()).unwrap();
+    let code = create_qr_matrix_auto(content.as_bytes(), ECCLevel::M, None, None, SizeSelection::default()).unwrap();
+    assert_eq!((plan.size, plan.level, plan.dimensions()), (Size::Standard(2), ECCLevel::M, 33));
+    assert_eq!(Some(plan.info.clone()), code.encode_info);
+    assert_eq!(plan.segments.first().map(|s| s.0), Some(Encoding::Alphanumeric));
+    assert_eq!(plan.segments.last().map(|s| s.1.end), Some(content.len()));
+
+    assert_eq!(plan.render(RenderStyle::Matrix), Ok(Rendered::Matrix(code.clone())));
+    assert_eq!(plan.render(RenderStyle::Image), Ok(Rendered::Image(render::to_gray_image(&code))));
+    let options = render::VectorOptions::new(render::Unit::Millimeter, 0.5);
+    assert_eq!(plan.render(RenderStyle::Svg(options)), Ok(Rendered::Vector(render::svg::to_svg(&code, &options))));
+
+    let selection = SizeSelection { max_version: 1, ..SizeSelection::default() };
+    assert!(matches!(qr_gen::plan(content, ECCLevel::M, None, None, selection), Err(QrError::DataTooLong { .. })));
+}
+
+#[test]
+fn test_from_reader() {
+    let content = b"[server]\nport = 8080\n";
+    assert_eq!(create_qr_matrix_from_reader(&content[..], Size::Standard(2), ECCLevel::M, None, None),
+               create_qr_matrix(content, Size::Standard(2), ECCLevel::M, None, None));
+
+    // 1-L holds at most 41 digits, so no more than 42 bytes are read from an endless reader
+    let result = create_qr_matrix_from_reader(std::io::repeat(b'7'), Size::Standard(1), ECCLevel::L, None, None);
+    assert!(matches!(result, Err(QrError::DataTooLong { bits, .. }) if bits == 4 + 10 + 14 * 10));
+    let result = create_qr_matrix_from_reader(std::io::repeat(b'x'), Size::Standard(1), ECCLevel::L, Some(Encoding::Bytes), None);
+    assert!(matches!(result, Err(QrError::DataTooLong { bits, .. }) if bits == 4 + 8 + 18 * 8));
+
+    struct Failing;
+    impl std::io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::PermissionDenied.into())
+        }
+    }
+    assert_eq!(create_qr_matrix_from_reader(Failing, Size::Standard(1), ECCLevel::L, None, None),
+               Err(QrError::ReadFailed(std::io::ErrorKind::PermissionDenied)));
+}
+
+#[test]
+fn test_encode_options() {
+    let content = "OPTIONS 42";
+    assert_eq!(encode_with_options(content, EncodeOptions::default()),
+               create_qr_matrix_auto(content.as_bytes(), ECCLevel::M, None, None, SizeSelection::default()));
+
+    let options = EncodeOptions { size: Some(Size::Standard(3)), level: ECCLevel::Q, eci: Some(3), mask: Some(5), ..EncodeOptions::default() };
+    assert_eq!(encode_with_options(content, options),
+               create_qr_matrix_with_mask(content.as_bytes(), Size::Standard(3), ECCLevel::Q, None, Some(3), 5));
+
+    let options = EncodeOptions { quiet_zone: Some(1), ..options };
+    assert_eq!(encode_image_with_options(content, options).unwrap().dimensions(), (31, 31));
+    let options = EncodeOptions { size: Some(Size::Micro(1)), ..options };
+    assert_eq!(encode_with_options(content, options), Err(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::Q)));
+
+    // on failure, the placeholder comes with the error
+    let (img, error) = encode_image_or_placeholder(content, options, 40);
+    assert_eq!((img, error), (render::render_placeholder(40), Some(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::Q))));
+    let options = EncodeOptions { size: Some(Size::Standard(3)), ..options };
+    assert_eq!(encode_image_or_placeholder(content, options, 40), (encode_image_with_options(content, options).unwrap(), None));
+
+    // lowercase letters are only uppercased on request
+    let options = EncodeOptions { size: Some(Size::Standard(2)), encoding: Some(Encoding::Alphanumeric), ..EncodeOptions::default() };
+    assert!(matches!(encode_with_options("https://example.com", options), Err(QrError::InvalidCharacter { position: 0, .. })));
+    let code = encode_with_options("https://example.com", EncodeOptions { uppercase_alphanumeric: true, ..options }).unwrap();
+    assert_eq!(code.matrix, encode_with_options("HTTPS://EXAMPLE.COM", options).unwrap().matrix);
+    assert_eq!(code.encode_info.unwrap().warnings, vec![EncodeWarning::Uppercased(15)]);
+    let options = EncodeOptions { uppercase_alphanumeric: true, ..options };
+    assert_eq!(encode_with_options("HTTPS://EXAMPLE.COM", options).unwrap().encode_info.unwrap().warnings, vec![]);
+    assert_eq!(encode_with_options("https://example.com", EncodeOptions { encoding: None, ..options }).unwrap().matrix,
+               create_qr_matrix("https://example.com", Size::Standard(2), ECCLevel::M, None, None).unwrap().matrix);
+}
+
+#[test]
+fn test_reprint() {
+    // the metadata of a symbol reproduces it, also when the automatic choices would differ
+    let original = encode_with_options("REPRINT-42", EncodeOptions { mask: Some(5), level: ECCLevel::L, ..EncodeOptions::default() }).unwrap();
+    let options = EncodeOptions::default().matching(original.symbol_info());
+    assert_eq!(encode_with_options("REPRINT-42", options).unwrap().matrix, original.matrix);
+    assert_ne!(encode_with_options("REPRINT-42", EncodeOptions::default()).unwrap().matrix, original.matrix);
+
+    // also from the metadata read back from the symbol
+    let info = inspect_matrix(&original.matrix).unwrap();
+    assert_eq!(encode_with_options("REPRINT-42", EncodeOptions::default().matching(info)).unwrap().matrix, original.matrix);
+    assert!(matches!(encode_with_options("REPRINT-42 WITH A MUCH LONGER CONTENT", options), Err(QrError::DataTooLong { .. })));
+}
+
+#[test]
+fn test_validate() {
+    let options = EncodeOptions::default();
+    assert_eq!(validate("HELLO WORLD", options), vec![]);
+    assert_eq!(validate("", options), vec![ValidationIssue::Empty]);
+
+    // all invalid characters are reported, lowercase letters only without uppercasing
+    let options = EncodeOptions { encoding: Some(Encoding::Alphanumeric), ..options };
+    assert_eq!(validate("Hello, World", options),
+               vec![ValidationIssue::InvalidCharacters { encoding: Encoding::Alphanumeric, positions: vec![1, 2, 3, 4, 5, 8, 9, 10, 11] }]);
+    assert_eq!(validate("Hello, World", EncodeOptions { uppercase_alphanumeric: true, ..options }),
+               vec![ValidationIssue::InvalidCharacters { encoding: Encoding::Alphanumeric, positions: vec![5] }]);
+    let kanji = EncodeOptions { encoding: Some(Encoding::Kanji), ..EncodeOptions::default() };
+    assert_eq!(validate(&[0x93, 0x5F, b'A', b'B', 0xE4, 0xAA, 0x93][..], kanji),
+               vec![ValidationIssue::InvalidCharacters { encoding: Encoding::Kanji, positions: vec![2, 6] }]);
+
+    // the length is checked against the largest allowed symbol
+    let options = EncodeOptions { size: Some(Size::Standard(1)), level: ECCLevel::L, ..EncodeOptions::default() };
+    assert_eq!(validate(&[b'7'; 41][..], options), vec![]);
+    assert_eq!(validate(&[b'7'; 42][..], options), vec![ValidationIssue::TooLong { bits: 154, capacity: 152 }]);
+    assert_eq!(validate("123", EncodeOptions { size: Some(Size::Micro(1)), level: ECCLevel::H, ..options }),
+               vec![ValidationIssue::Unusable(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::H))]);
+    for content in ["HELLO WORLD", "", "123"] {
+        assert_eq!(validate(content, options).is_empty(), encode_with_options(content, options).is_ok() && !content.is_empty());
+    }
+}
+
+#[test]
+fn test_encode_info() {
+    let code = create_qr_matrix(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, None, None).unwrap();
+    assert_eq!(code.encode_info, Some(EncodeInfo {
+        data_bits: 4 + 9 + 61,
+        capacity_bits: 104,
+        terminator_bits: 4,
+        alignment_bits: 2,
+        pad_codewords: 3,
+        segments: vec![(Encoding::Alphanumeric, 0..11)],
+        warnings: vec![]
+    }));
+
+    // forced encodings that are less dense than necessary are reported
+    let warnings = |content: &[u8], encoding| create_qr_matrix(content, Size::Standard(2), ECCLevel::L, Some(encoding), None)
+        .unwrap().encode_info.unwrap().warnings;
+    assert_eq!(warnings(b"0123456789", Encoding::Bytes),
+               vec![EncodeWarning::LessDenseEncoding { forced: Encoding::Bytes, possible: Encoding::Numeric }]);
+    assert_eq!(warnings(b"ABC-42", Encoding::Bytes),
+               vec![EncodeWarning::LessDenseEncoding { forced: Encoding::Bytes, possible: Encoding::Alphanumeric }]);
+    assert_eq!(warnings(b"42", Encoding::Alphanumeric),
+               vec![EncodeWarning::LessDenseEncoding { forced: Encoding::Alphanumeric, possible: Encoding::Numeric }]);
+    assert_eq!(warnings(b"abc", Encoding::Bytes), vec![]);
+    assert_eq!(warnings(b"", Encoding::Bytes), vec![]);
+    assert_eq!(create_qr_matrix(b"42", Size::Standard(2), ECCLevel::L, None, None).unwrap().encode_info.unwrap().warnings, vec![]);
+
+    // advice about the scannability
+    let warnings = |content: &[u8], size, eci| create_qr_matrix(content, size, ECCLevel::L, None, eci)
+        .unwrap().encode_info.unwrap().warnings;
+    assert_eq!(warnings(&[b'7'; 40], Size::Standard(1), None), vec![EncodeWarning::NearCapacity]);
+    assert_eq!(warnings(b"123", Size::Micro(2), None), vec![EncodeWarning::MicroSymbol(Size::Micro(2))]);
+    assert_eq!(warnings("gr\u{fc}n".as_bytes(), Size::Standard(1), None), vec![EncodeWarning::Utf8WithoutEci]);
+    assert_eq!(warnings("gr\u{fc}n".as_bytes(), Size::Standard(1), Some(Charset::Utf8.into())), vec![]);
+    assert_eq!(warnings(b"gr\xfcn", Size::Standard(1), None), vec![]);
+
+    let (data, ecc) = encode_codewords(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, Encoding::Alphanumeric).unwrap();
+    assert_eq!(place_codewords(&data, &ecc, Size::Standard(1), ECCLevel::Q).unwrap().encode_info, None);
+}
+
+#[test]
+fn test_mask_override() {
+    for mask in 0..8 {
+        let code = create_qr_matrix_with_mask(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, None, None, mask).unwrap();
+        assert_eq!(inspect_matrix(&code.matrix).unwrap().mask, mask);
+    }
+    assert_eq!(create_qr_matrix_with_mask(b"HELLO WORLD", Size::Standard(1), ECCLevel::Q, None, None, 8),
+               Err(QrError::InvalidMaskPattern(8, Size::Standard(1))));
+    assert_eq!(create_qr_matrix_with_mask(b"12345", Size::Micro(2), ECCLevel::L, None, None, 4),
+               Err(QrError::InvalidMaskPattern(4, Size::Micro(2))));
+}
+
+#[test]
+fn test_mask_tie_break() {
+    let (data, ecc) = encode_codewords(b"1234567", Size::Micro(3), ECCLevel::M, Encoding::Numeric).unwrap();
+    let best = place_codewords(&data, &ecc, Size::Micro(3), ECCLevel::M).unwrap();
+    let selection = best.mask_selection.unwrap();
+    assert_eq!(selection.tie_break, MaskTieBreak::HighestIndex);
+    assert!(selection.candidates >= 1);
+
+    // preferring the mask of a previous print reproduces it
+    let reprint = place_codewords_with_tie_break(&data, &ecc, Size::Micro(3), ECCLevel::M, MaskTieBreak::Prefer(best.mask)).unwrap();
+    assert_eq!(reprint.matrix, best.matrix);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(create_qr_code(b"AC-47", Size::Micro(1), ECCLevel::L, Some(Encoding::Alphanumeric), None),
+               Err(QrError::UnsupportedEncoding(Encoding::Alphanumeric, Size::Micro(1))));
+    assert_eq!(create_qr_code(b"1234", Size::Micro(2), ECCLevel::H, None, None),
+               Err(QrError::InvalidSymbolConfig(Size::Micro(2), ECCLevel::H)));
+    // the level is checked before the content is encoded
+    assert_eq!(create_qr_code(b"abc", Size::Micro(1), ECCLevel::M, Some(Encoding::Bytes), None),
+               Err(QrError::InvalidSymbolConfig(Size::Micro(1), ECCLevel::M)));
+    assert_eq!(create_qr_code(b"ac-47", Size::Standard(1), ECCLevel::L, Some(Encoding::Alphanumeric), None),
+               Err(QrError::InvalidCharacter { encoding: Encoding::Alphanumeric, position: 0, value: b'a' }));
+    assert_eq!(create_qr_code(&[b'x'; 100], Size::Standard(1), ECCLevel::L, None, None),
+               Err(QrError::DataTooLong { bits: 12 + 800, capacity: 152, suggestion: Some(SymbolConfig::from_str("5-L").unwrap()) }));
+    // without a size at the requested level, a lower level is suggested
+    assert!(matches!(create_qr_matrix(&[b'x'; 2000], Size::Standard(40), ECCLevel::H, None, None),
+                     Err(QrError::DataTooLong { suggestion: Some(config), .. }) if config == SymbolConfig::from_str("38-M").unwrap()));
+    assert!(matches!(create_qr_matrix(&[b'x'; 3000], Size::Standard(40), ECCLevel::L, None, None),
+                     Err(QrError::DataTooLong { suggestion: None, .. })));
+
+    let (data, ecc) = encode_codewords(b"1234567", Size::Micro(3), ECCLevel::M, Encoding::Numeric).unwrap();
+    assert_eq!(place_codewords_with_mask(&data, &ecc, Size::Micro(3), ECCLevel::M, 4),
+               Err(QrError::InvalidMaskPattern(4, Size::Micro(3))));
+    assert_eq!(place_codewords(&data[1..], &ecc, Size::Micro(3), ECCLevel::M),
+               Err(QrError::WrongNumberOfCodewords { expected: 9, actual: 8 }));
+}
+
+#[test]
+fn test_eci() {
+    // ECI 26 (UTF-8) header: 4 bits mode + 8 bits assignment
+    let plain = create_qr_matrix("ä".as_bytes(), Size::Standard(1), ECCLevel::L, Some(Encoding::Bytes), None).unwrap();
+    let eci = create_qr_matrix("ä".as_bytes(), Size::Standard(1), ECCLevel::L, Some(Encoding::Bytes), Some(26)).unwrap();
+    assert_ne!(plain.matrix, eci.matrix);
+
+    // 152 data bits in 1-L: 4 + 8 + 8 * 17 = 148 bits fit without ECI, but not with it
+    assert!(create_qr_matrix(&[b'a'; 17], Size::Standard(1), ECCLevel::L, Some(Encoding::Bytes), None).is_ok());
+    assert_eq!(create_qr_matrix(&[b'a'; 17], Size::Standard(1), ECCLevel::L, Some(Encoding::Bytes), Some(26)).unwrap_err(),
+               QrError::DataTooLong { bits: 12 + 148, capacity: 152, suggestion: Some(SymbolConfig::from_str("2-L").unwrap()) });
+
+    assert_eq!(create_qr_code(b"1234", Size::Micro(3), ECCLevel::L, None, Some(26)),
+               Err(QrError::EciNotSupported(Size::Micro(3))));
+    assert_eq!(create_qr_code(b"1234", Size::Standard(1), ECCLevel::L, None, Some(1000000)),
+               Err(QrError::InvalidEciAssignment(1000000)));
+    // reserved for future use by the AIM ECI specification
+    assert_eq!(create_qr_code(b"1234", Size::Standard(1), ECCLevel::L, None, Some(811800)),
+               Err(QrError::InvalidEciAssignment(811800)));
+}
+
+#[test]
+fn test_eci_segments() {
+    use bitcoding::Segment;
+
+    // Greek in ISO 8859-7 (ECI 9), followed by UTF-8 (ECI 26)
+    let segments = [Segment::new(Encoding::Bytes, vec![0xC1, 0xE2]).with_eci(9),
+                    Segment::new(Encoding::Bytes, "ä").with_eci(26)];
+    let code = create_qr_matrix_from_segments(&segments, Size::Standard(1), ECCLevel::L).unwrap();
+    let info = code.encode_info.unwrap();
+    assert_eq!(info.segments, vec![(Encoding::Bytes, 0..2), (Encoding::Bytes, 2..4)]);
+    assert_eq!(info.data_bits, 2 * (12 + 12) + 4 * 8);
+    assert_eq!(create_qr_matrix_from_segments(&segments, Size::Micro(4), ECCLevel::L),
+               Err(QrError::EciNotSupported(Size::Micro(4))));
+
+    // each ECI header takes 12 bits of the 152 data bits of 1-L
+    let selection = SizeSelection { micro: MicroPolicy::PreferMicro, ..SizeSelection::default() };
+    let segments = |len: usize| [Segment::new(Encoding::Bytes, vec![b'a'; len - len / 2]).with_eci(9),
+                                 Segment::new(Encoding::Bytes, vec![b'a'; len / 2]).with_eci(26)];
+    assert_eq!(create_qr_matrix_from_segments_auto(&segments(13), ECCLevel::L, selection).unwrap().size, Size::Standard(1));
+    assert_eq!(create_qr_matrix_from_segments_auto(&segments(14), ECCLevel::L, selection).unwrap().size, Size::Standard(2));
+}
+
+#[test]
+fn test_utf8() {
+    let code = create_qr_matrix_utf8("Grüße", ECCLevel::M).unwrap();
+    assert_eq!(code.size, Size::Standard(1));
+    assert_eq!(code, create_qr_matrix("Grüße".as_bytes(), Size::Standard(1), ECCLevel::M, Some(Encoding::Bytes), Some(26)).unwrap());
+
+    // 1-L holds 17 bytes, minus one for the ECI header
+    assert_eq!(create_qr_matrix_utf8(&"a".repeat(16), ECCLevel::L).unwrap().size, Size::Standard(1));
+    assert_eq!(create_qr_matrix_utf8(&"a".repeat(17), ECCLevel::L).unwrap().size, Size::Standard(2));
+
+    assert!(matches!(create_qr_matrix_utf8(&"a".repeat(3000), ECCLevel::L), Err(QrError::DataTooLong { .. })));
+}
+
+#[test]
+fn test_kanji_text() {
+    assert_eq!(create_qr_matrix_kanji("点茗", Size::Micro(3), ECCLevel::L),
+               create_qr_matrix(&[0x93, 0x5F, 0xE4, 0xAA], Size::Micro(3), ECCLevel::L, Some(Encoding::Kanji), None));
+    assert_eq!(create_qr_matrix_kanji("QR点", Size::Standard(1), ECCLevel::L),
+               Err(QrError::InvalidCharacter { encoding: Encoding::Kanji, position: 0, value: b'Q' }));
+}
+
+#[test]
+fn test_structured_append() {
+    let parts: [&[u8]; 3] = [b"ABCDEFGH", b"IJKLMN", b"OPQRST"];
+    let codes = create_structured_append(&parts, Size::Standard(1), ECCLevel::M, None).unwrap();
+    assert_eq!(codes.len(), 3);
+    assert_ne!(codes[1], create_qr_matrix(parts[1], Size::Standard(1), ECCLevel::M, None, None).unwrap());
+
+    // symbols of a series split by hand are the same as the ones created in one go
+    let parity = bitcoding::structured_append_parity_of_parts(&parts);
+    assert_eq!(parity, bitcoding::structured_append_parity(b"ABCDEFGHIJKLMNOPQRST"));
+    let header = bitcoding::StructuredAppend { position: 1, total: 3, parity };
+    assert_eq!(create_structured_append_symbol(parts[1], Size::Standard(1), ECCLevel::M, None, header).unwrap(), codes[1]);
+
+    assert_eq!(create_structured_append(&[&b"A"[..]; 17], Size::Standard(1), ECCLevel::M, None),
+               Err(QrError::InvalidStructuredAppend { position: 0, total: 17 }));
+    assert_eq!(create_structured_append(&parts, Size::Micro(4), ECCLevel::M, None),
+               Err(QrError::StructuredAppendNotSupported(Size::Micro(4))));
+}
+
+#[test]
+fn test_split() {
+    // fits into a single symbol
+    let codes = create_qr_matrix_split(b"0123456789", Size::Standard(1), ECCLevel::L, None).unwrap();
+    assert_eq!(codes, vec![create_qr_matrix(b"0123456789", Size::Standard(1), ECCLevel::L, None, None).unwrap()]);
+
+    // 1-L holds 152 bits, minus 20 for the structured append header leaves room for 35 digits
+    let digits: Vec<u8> = (0..100).map(|i| b'0' + (i % 10) as u8).collect();
+    let codes = create_qr_matrix_split(&digits, Size::Standard(1), ECCLevel::L, Some(Encoding::Numeric)).unwrap();
+    let parts = [&digits[..35], &digits[35..70], &digits[70..]];
+    assert_eq!(codes, create_structured_append(&parts, Size::Standard(1), ECCLevel::L, Some(Encoding::Numeric)).unwrap());
+
+    // too long even for a series of 16 symbols
+    let digits: Vec<u8> = (0..16 * 35 + 1).map(|i| b'0' + (i % 10) as u8).collect();
+    assert!(matches!(create_qr_matrix_split(&digits, Size::Standard(1), ECCLevel::L, Some(Encoding::Numeric)),
+                     Err(QrError::DataTooLong { .. })));
+    assert_eq!(create_qr_matrix_split(&digits, Size::Micro(4), ECCLevel::L, None),
+               Err(QrError::StructuredAppendNotSupported(Size::Micro(4))));
+}
+
+#[test]
+fn test_inspect() {
+    for &(content, size, level) in [(&b"12345"[..], Size::Micro(1), ECCLevel::L),
+                                    (b"HELLO", Size::Micro(4), ECCLevel::Q),
+                                    (b"hello world", Size::Standard(3), ECCLevel::M),
+                                    (b"hello world", Size::Standard(12), ECCLevel::H)].iter() {
+        let code = create_qr_matrix(content, size, level, None, None).unwrap();
+        assert_eq!(inspect_matrix(&code.matrix), Ok(SymbolInfo { size, level, mask: code.mask }));
+    }
+}
+
+#[test]
+fn test_fnc1() {
+    let fnc1 = bitcoding::Fnc1::second_position("a").unwrap();
+    let code = create_qr_matrix_fnc1(b"AA1234BBB112", Size::Standard(1), ECCLevel::M, None, fnc1).unwrap();
+    assert_eq!(inspect_matrix(&code.matrix), Ok(SymbolInfo { size: Size::Standard(1), level: ECCLevel::M, mask: code.mask }));
+    assert_ne!(code, create_qr_matrix(b"AA1234BBB112", Size::Standard(1), ECCLevel::M, None, None).unwrap());
+
+    assert_eq!(create_qr_matrix_fnc1(b"01049123451234591597033130128%10ABC123", Size::Micro(4), ECCLevel::L, None, bitcoding::Fnc1::First),
+               Err(QrError::Fnc1NotSupported(Size::Micro(4))));
+    assert!(create_qr_matrix_fnc1(b"01049123451234591597033130128%10ABC123", Size::Standard(2), ECCLevel::M, None, bitcoding::Fnc1::First).is_ok());
+
+    let content = gs1::element_string(&[("01", "04912345123459"), ("15", "970331"), ("30", "128"), ("10", "ABC123")]).unwrap();
+    assert_eq!(content, b"01049123451234591597033130128\x1d10ABC123");
+    assert!(create_qr_matrix_fnc1(&content, Size::Standard(2), ECCLevel::M, None, bitcoding::Fnc1::First).is_ok());
+}
+
+#[test]
+fn test_base45() {
+    // Base45 text is encoded in alphanumeric mode instead of byte mode
+    let payload: Vec<u8> = (0..60).map(|i| (i * 37) as u8).collect();
+    let text = base45::encode(&payload);
+    let code = encode_with_options(text.as_str(), EncodeOptions::default()).unwrap();
+    let info = code.encode_info.unwrap();
+    assert_eq!(info.segments, vec![(Encoding::Alphanumeric, 0..90)]);
+    assert!(info.data_bits < 6 * text.len());
+    assert_eq!(base45::decode(&text), Ok(payload));
+}
+
+#[cfg(feature = "zlib")]
+#[test]
+fn test_compressed() {
+    // repetitive content is compressed into a smaller symbol
+    let content = b"temperature=21.5;humidity=40%;".repeat(12);
+    let options = EncodeOptions { level: ECCLevel::L, ..EncodeOptions::default() };
+    let code = encode_compressed(&content, options).unwrap();
+    assert!(code.size.dimensions() < encode_with_options(&content[..], options).unwrap().size.dimensions());
+    let payload = compression::compress(&content);
+    assert_eq!(code.matrix, encode_with_options(&payload[..], options).unwrap().matrix);
+    assert_eq!(compression::decompress(&payload).unwrap(), &content[..]);
+
+    // content which does not get smaller stays as it is
+    assert_eq!(encode_compressed(b"HELLO WORLD", options), encode_with_options(&b"HELLO WORLD"[..], options));
+
+    // compression can make content fit into a given size
+    let options = EncodeOptions { size: Some(Size::Standard(5)), ..options };
+    assert!(matches!(encode_with_options(&content[..], options), Err(QrError::DataTooLong { .. })));
+    assert!(encode_compressed(&content, options).is_ok());
+}
+
+#[test]
+fn test_split_segmented() {
+    let digits: Vec<u8> = (0..90).map(|i| b'0' + (i % 10) as u8).collect();
+    let numeric = |range: std::ops::Range<usize>| (Encoding::Numeric, range);
+
+    // 35 digits fit into each 1-L symbol, but the segments are not split
+    let codes = create_qr_matrix_split_segmented(&digits, Size::Standard(1), ECCLevel::L,
+                                                 &[numeric(0..30), numeric(30..60), numeric(60..90)]).unwrap();
+    let parts = [&digits[..30], &digits[30..60], &digits[60..]];
+    assert_eq!(codes, create_structured_append(&parts, Size::Standard(1), ECCLevel::L, Some(Encoding::Numeric)).unwrap());
+
+    // unless a segment does not fit into a symbol on its own
+    let codes = create_qr_matrix_split_segmented(&digits, Size::Standard(1), ECCLevel::L,
+                                                 &[numeric(0..20), numeric(20..70), numeric(70..90)]).unwrap();
+    let parts = [&digits[..20], &digits[20..55], &digits[55..70], &digits[70..]];
+    assert_eq!(codes.len(), 4);
+    assert_eq!(codes[1], create_structured_append(&parts, Size::Standard(1), ECCLevel::L, Some(Encoding::Numeric)).unwrap()[1]);
+}
+
+#[test]
+fn test_micro_renderers() {
+    // all renderers use the 2 module quiet region of micro symbols: M1 covers 11 + 2 * 2 modules
+    let code = create_qr_matrix(b"123", Size::Micro(1), ECCLevel::L, None, None).unwrap();
+    assert_eq!((code.quiet_zone(), code.size.dimensions_with_quiet_region()), (2, 15));
+
+    let img = render::to_gray_image(&code);
+    assert_eq!(img.dimensions(), (15, 15));
+    assert_eq!((img[(1, 1)], img[(2, 2)], img[(12, 12)], img[(13, 13)]),
+               (render::BIT_WHITE, render::BIT_BLACK, render::module_color(code.matrix[(10, 10)]), render::BIT_WHITE));
+
+    let svg = render::svg::to_svg(&code, &render::VectorOptions::new(render::Unit::Millimeter, 1.0));
+    assert!(svg.contains("width=\"15mm\" height=\"15mm\" viewBox=\"0 0 15 15\""));
+    assert!(svg.contains("<rect x=\"2\" y=\"2\" width=\"1\" height=\"1\" fill=\"#000\"/>"));
+    let eps = render::eps::to_eps(&code, &render::VectorOptions::new(render::Unit::Point, 1.0));
+    assert!(eps.contains("%%BoundingBox: 0 0 15 15\n"));
+    assert!(eps.contains("\n2 12 1 1 rectfill\n"));
+
+    let colors = [image::Luma([255u8]), image::Luma([0u8])];
+    let mut sheet = image::GrayImage::from_pixel(40, 40, image::Luma([7]));
+    render::to_image_region(&code, &mut sheet, (5, 5), 2, colors).unwrap();
+    assert_eq!((sheet[(4, 4)], sheet[(8, 8)], sheet[(9, 9)], sheet[(34, 34)], sheet[(35, 35)]),
+               (image::Luma([7]), colors[0], colors[1], colors[0], image::Luma([7])));
+
+    let mut buf = vec![7u8; 30 * 30];
+    render::render_gray_into(&code.matrix, &mut buf, 30, 30, 30, render::GrayBufferOptions::for_code(&code, 2)).unwrap();
+    let region = image::imageops::crop_imm(&sheet, 5, 5, 30, 30).to_image();
+    assert_eq!(buf, region.into_raw());
+
+    let selection = SizeSelection { micro: MicroPolicy::MicroOnly, ..SizeSelection::default() };
+    let plan = plan("123", ECCLevel::L, None, None, selection).unwrap();
+    assert_eq!(plan.dimensions(), 15);
+}
+
+#[test]
+fn test_multiplexed() {
+    let codes: Vec<QrCode> = [&b"RED"[..], b"GREEN", b"BLUE"].iter()
+        .map(|&content| create_qr_matrix(content, Size::Standard(1), ECCLevel::M, None, None).unwrap())
+        .collect();
+    let img = render::render_multiplexed(&codes).unwrap();
+    for (i, code) in codes.iter().enumerate() {
+        assert_eq!(render::demultiplex_channel(&img, i), render::to_gray_image(code));
+    }
+
+    // unused channels are light
+    let img = render::render_multiplexed(&codes[..1]).unwrap();
+    assert!(render::demultiplex_channel(&img, 2).pixels().all(|&p| p == render::BIT_WHITE));
+
+    let micro = create_qr_matrix(b"1", Size::Micro(1), ECCLevel::L, None, None).unwrap();
+    assert_eq!(render::render_multiplexed(&[codes[0].clone(), micro]),
+               Err(QrError::MultiplexSizeMismatch(Size::Standard(1), Size::Micro(1))));
+    assert_eq!(render::render_multiplexed(&[]), Err(QrError::InvalidChannelCount(0)));
+}
+
+// render the matrix as text, one line per row
+fn matrix_to_text(matrix: &Matrix) -> String {
+    (0..matrix.width())
+        .map(|y| (0..matrix.width()).map(|x| if matrix[(x, y)].is_dark() { '#' } else { '.' }).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_policy_v1_golden() {
+    let code = create_qr_matrix_with_policy(b"ORDER-0001234567", Size::Standard(1), ECCLevel::M, None, None,
+                                            EncodingPolicy::V1).unwrap();
+    // this must never change. If it does, the change belongs into a new policy.
+    let expected = [
+        "#######.##.#..#######",
+        "#.....#..###..#.....#",
+        "#.###.#.#.###.#.###.#",
+        "#.###.#...#...#.###.#",
+        "#.###.#....##.#.###.#",
+        "#.....#.#..##.#.....#",
+        "#######.#.#.#.#######",
+        "..........#.#........",
+        "#.#...##.......#..#.#",
+        "..##.#.#...#.#...#.##",
+        "##.#####...##..######",
+        ".##..#..#.##..#...##.",
+        "##.######.###..###..#",
+        "........#.#..#...##..",
+        "#######.###....##...#",
+        "#.....#.....#...#..##",
+        "#.###.#..##..#.#...##",
+        "#.###.#...#.......#..",
+        "#.###.#.#####.####.##",
+        "#.....#..#...##.....#",
+        "#######.##.####.#####",
+    ];
+    assert_eq!(code.mask, 1);
+    assert_eq!(matrix_to_text(&code.matrix), expected.join("\n"));
+}